@@ -0,0 +1,228 @@
+//! Sample playback for the Space Invaders sound circuits.
+//!
+//! The board's sounds are analog, so the usual way to reproduce them is with
+//! recorded samples, `0.wav` to `9.wav`. A `Mixer` turns `SoundEvent`s into
+//! interleaved 16 bit PCM, producing exactly as many frames as the emulated
+//! cycles it is advanced by, so audio stays locked to emulation speed no matter
+//! which backend plays it.
+
+use crate::{
+    interconnect::{Sound, SoundEvent},
+    Error,
+};
+use log::warn;
+use std::path::Path;
+
+mod wav;
+pub use self::wav::write_wav;
+use self::wav::Clip;
+
+/// The 8080's clock rate on the Space Invaders board.
+pub const CPU_CLOCK: u64 = 2_000_000;
+
+/// Sounds in the order of their conventional sample file names.
+const SAMPLE_FILES: [Sound; 10] = [
+    Sound::Ufo,
+    Sound::Shot,
+    Sound::PlayerDeath,
+    Sound::InvaderDeath,
+    Sound::Fleet1,
+    Sound::Fleet2,
+    Sound::Fleet3,
+    Sound::Fleet4,
+    Sound::UfoHit,
+    Sound::ExtraLife,
+];
+
+/// A clip for each sound, any of which may be absent.
+#[derive(Default)]
+pub struct Samples {
+    clips: [Option<Clip>; 10],
+}
+
+impl Samples {
+    /// Loads `0.wav` to `9.wav` from `dir`. Missing files leave their sound silent.
+    pub fn load_dir<P: AsRef<Path>>(dir: P) -> Result<Samples, Error> {
+        let mut samples = Samples::default();
+        for (i, &sound) in SAMPLE_FILES.iter().enumerate() {
+            let path = dir.as_ref().join(format!("{}.wav", i));
+            match std::fs::read(&path) {
+                Ok(bytes) => samples.insert(sound, &bytes)?,
+                Err(e) => warn!("no sample for {:?} at {}: {}", sound, path.display(), e),
+            }
+        }
+        Ok(samples)
+    }
+
+    /// Decodes `wav` and uses it for `sound`.
+    pub fn insert(&mut self, sound: Sound, wav: &[u8]) -> Result<(), Error> {
+        self.clips[index(sound)] = Some(wav::parse(wav)?);
+        Ok(())
+    }
+
+    fn get(&self, sound: Sound) -> Option<&Clip> {
+        self.clips[index(sound)].as_ref()
+    }
+}
+
+fn index(sound: Sound) -> usize {
+    SAMPLE_FILES.iter().position(|&s| s == sound).unwrap()
+}
+
+struct Voice {
+    sound: Sound,
+    /// Position in the clip, in 16.16 fixed point.
+    position: u64,
+    looping: bool,
+}
+
+pub struct Mixer {
+    samples: Samples,
+    sample_rate: u32,
+    channels: u16,
+    voices: Vec<Voice>,
+    /// Cycles not yet turned into a whole frame, scaled by `sample_rate`.
+    remainder: u64,
+}
+
+impl Mixer {
+    pub fn new(samples: Samples, sample_rate: u32, channels: u16) -> Mixer {
+        Mixer {
+            samples,
+            sample_rate,
+            channels,
+            voices: Vec::new(),
+            remainder: 0,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    /// Starts or stops voices in response to a sound port event.
+    ///
+    /// The UFO loops until its bit drops; every other sound plays to the end
+    /// of its sample once started.
+    pub fn handle(&mut self, event: SoundEvent) {
+        match event {
+            SoundEvent::Start(sound) => {
+                if self.samples.get(sound).is_none() {
+                    return;
+                }
+                self.voices.retain(|v| v.sound != sound);
+                self.voices.push(Voice {
+                    sound,
+                    position: 0,
+                    looping: sound == Sound::Ufo,
+                });
+            }
+            SoundEvent::Stop(Sound::Ufo) => self.voices.retain(|v| v.sound != Sound::Ufo),
+            SoundEvent::Stop(_) => {}
+        }
+    }
+
+    /// Appends the audio for `cycles` emulated clock cycles to `out`.
+    pub fn mix(&mut self, cycles: u64, out: &mut Vec<i16>) {
+        let scaled = self.remainder + cycles * self.sample_rate as u64;
+        let frames = scaled / CPU_CLOCK;
+        self.remainder = scaled % CPU_CLOCK;
+        out.reserve(frames as usize * self.channels as usize);
+        for _ in 0..frames {
+            let mut sum = 0i32;
+            for voice in self.voices.iter_mut() {
+                let clip = self.samples.get(voice.sound).unwrap();
+                let mut i = (voice.position >> 16) as usize;
+                if i >= clip.samples.len() && voice.looping && !clip.samples.is_empty() {
+                    voice.position %= (clip.samples.len() as u64) << 16;
+                    i = (voice.position >> 16) as usize;
+                }
+                if let Some(&sample) = clip.samples.get(i) {
+                    sum += sample as i32;
+                }
+                voice.position += ((clip.sample_rate as u64) << 16) / self.sample_rate as u64;
+            }
+            let samples = &self.samples;
+            self.voices.retain(|v| {
+                v.looping
+                    || ((v.position >> 16) as usize) < samples.get(v.sound).unwrap().samples.len()
+            });
+            let sample = sum.max(i16::MIN as i32).min(i16::MAX as i32) as i16;
+            for _ in 0..self.channels {
+                out.push(sample);
+            }
+        }
+    }
+
+    /// Silences every voice, as when jumping to a save state.
+    pub fn stop_all(&mut self) {
+        self.voices.clear();
+    }
+
+    /// Whether any voice is still sounding.
+    pub fn is_active(&self) -> bool {
+        !self.voices.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_wav, Mixer, Samples, CPU_CLOCK};
+    use crate::interconnect::{Sound, SoundEvent};
+
+    fn clip(rate: u32, samples: &[i16]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_wav(&mut bytes, rate, 1, samples).unwrap();
+        bytes
+    }
+
+    fn samples() -> Samples {
+        let mut samples = Samples::default();
+        samples.insert(Sound::Ufo, &clip(1000, &[1, 2])).unwrap();
+        samples
+            .insert(Sound::Shot, &clip(1000, &[10, 20, 30]))
+            .unwrap();
+        samples
+    }
+
+    #[test]
+    fn frames_follow_cycles() {
+        let mut mixer = Mixer::new(Samples::default(), 44100, 2);
+        let mut out = Vec::new();
+        for _ in 0..60 {
+            mixer.mix(CPU_CLOCK / 60, &mut out);
+        }
+        assert_eq!(out.len(), 2 * 44099);
+        mixer.mix(60, &mut out);
+        assert_eq!(out.len(), 2 * 44100);
+        assert!(out.iter().all(|&s| s == 0));
+    }
+
+    #[test]
+    fn mixes_and_loops() {
+        let mut mixer = Mixer::new(samples(), 1000, 1);
+        let mut out = Vec::new();
+        mixer.handle(SoundEvent::Start(Sound::Ufo));
+        mixer.handle(SoundEvent::Start(Sound::Shot));
+        mixer.handle(SoundEvent::Stop(Sound::Shot));
+        mixer.mix(CPU_CLOCK / 1000 * 5, &mut out);
+        assert_eq!(out, [11, 22, 31, 2, 1]);
+        mixer.handle(SoundEvent::Stop(Sound::Ufo));
+        assert!(!mixer.is_active());
+        mixer.handle(SoundEvent::Start(Sound::Fleet1));
+        assert!(!mixer.is_active());
+    }
+
+    #[test]
+    fn resamples() {
+        let mut mixer = Mixer::new(samples(), 2000, 1);
+        let mut out = Vec::new();
+        mixer.handle(SoundEvent::Start(Sound::Shot));
+        mixer.mix(CPU_CLOCK / 2000 * 7, &mut out);
+        assert_eq!(out, [10, 10, 20, 20, 30, 30, 0]);
+    }
+}
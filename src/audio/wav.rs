@@ -0,0 +1,117 @@
+//! Minimal RIFF/WAVE support: 8 and 16 bit PCM in, 16 bit PCM out.
+
+use crate::Error;
+use std::io::{self, Write};
+
+/// A decoded clip, downmixed to mono.
+pub(super) struct Clip {
+    pub(super) sample_rate: u32,
+    pub(super) samples: Vec<i16>,
+}
+
+pub(super) fn parse(bytes: &[u8]) -> Result<Clip, Error> {
+    let invalid = |reason| Error::InvalidWav { reason };
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(invalid("not a RIFF/WAVE file"));
+    }
+    let mut format = None;
+    let mut data = None;
+    let mut rest = &bytes[12..];
+    while rest.len() >= 8 {
+        let id = &rest[0..4];
+        let len = u32::from_le_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize;
+        let body = rest
+            .get(8..8 + len)
+            .ok_or_else(|| invalid("truncated chunk"))?;
+        match id {
+            b"fmt " if len >= 16 => format = Some(body),
+            b"data" => data = Some(body),
+            _ => {}
+        }
+        rest = rest.get(8 + len + len % 2..).unwrap_or(&[]);
+    }
+    let format = format.ok_or_else(|| invalid("missing fmt chunk"))?;
+    let data = data.ok_or_else(|| invalid("missing data chunk"))?;
+
+    let u16_at = |i: usize| u16::from_le_bytes([format[i], format[i + 1]]);
+    if u16_at(0) != 1 {
+        return Err(invalid("only PCM is supported"));
+    }
+    let channels = u16_at(2) as usize;
+    let sample_rate = u32::from_le_bytes([format[4], format[5], format[6], format[7]]);
+    let bits = u16_at(14);
+    if channels == 0 || sample_rate == 0 {
+        return Err(invalid("no channels or zero sample rate"));
+    }
+
+    let decoded: Vec<i16> = match bits {
+        8 => data.iter().map(|&b| ((b as i16) - 128) << 8).collect(),
+        16 => data
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect(),
+        _ => return Err(invalid("only 8 and 16 bit samples are supported")),
+    };
+    let samples = decoded
+        .chunks_exact(channels)
+        .map(|frame| (frame.iter().map(|&s| s as i32).sum::<i32>() / channels as i32) as i16)
+        .collect();
+    Ok(Clip {
+        sample_rate,
+        samples,
+    })
+}
+
+/// Writes interleaved 16 bit PCM as a WAV file.
+pub fn write_wav<W: Write>(
+    mut writer: W,
+    sample_rate: u32,
+    channels: u16,
+    samples: &[i16],
+) -> io::Result<()> {
+    let data_len = (samples.len() * 2) as u32;
+    let block_align = channels * 2;
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data_len).to_le_bytes())?;
+    writer.write_all(b"WAVEfmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&channels.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+    writer.write_all(&block_align.to_le_bytes())?;
+    writer.write_all(&16u16.to_le_bytes())?;
+    writer.write_all(b"data")?;
+    writer.write_all(&data_len.to_le_bytes())?;
+    for sample in samples {
+        writer.write_all(&sample.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, write_wav};
+
+    #[test]
+    fn round_trip() {
+        let mut bytes = Vec::new();
+        write_wav(&mut bytes, 11025, 2, &[100, 300, -50, -150]).unwrap();
+        let clip = parse(&bytes).unwrap();
+        assert_eq!(clip.sample_rate, 11025);
+        assert_eq!(clip.samples, [200, -100]);
+    }
+
+    #[test]
+    fn eight_bit() {
+        let mut bytes = Vec::new();
+        write_wav(&mut bytes, 8000, 1, &[]).unwrap();
+        // Patch the header to 8 bit mono and append two samples.
+        bytes[34] = 8;
+        bytes[40] = 2;
+        bytes.extend_from_slice(&[0x80, 0xff]);
+        let clip = parse(&bytes).unwrap();
+        assert_eq!(clip.samples, [0, 127 << 8]);
+        assert!(parse(b"RIFF\0\0\0\0WAVX").is_err());
+    }
+}
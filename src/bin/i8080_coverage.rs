@@ -0,0 +1,125 @@
+//! Runs a program and reports which of its rom bytes it used.
+//!
+//! Usage: `i8080-coverage <rom> [--invaders --frames N] [--steps N]
+//! [--listing FILE --lcov FILE] [--symbols FILE] [--out FILE]`
+//!
+//! The program runs on the bare cpu until it halts or leaves the rom, or for
+//! at most `--steps` instructions. `--invaders` runs it as Space Invaders for
+//! `--frames` frames instead. The rom is then written as an annotated
+//! disassembly to `--out`, or standard output, marking every byte as
+//! executed, read as data or never touched, and a summary is printed to
+//! standard error. Given the listing our assembler wrote for the program,
+//! `--lcov` also writes line coverage for tools like genhtml.
+
+use i8080_emulator::{
+    coverage::Coverage, interconnect::Rom, listing::Listing, machine::SpaceInvaders,
+    symbols::Symbols, Emulator, Error,
+};
+use std::{env, fs, path::PathBuf, process};
+
+/// Instructions run on the bare cpu unless `--steps` is given.
+const DEFAULT_STEPS: u64 = 10_000_000;
+
+struct Options {
+    rom: PathBuf,
+    frames: Option<u64>,
+    steps: u64,
+    listing: Option<PathBuf>,
+    lcov: Option<PathBuf>,
+    symbols: Option<PathBuf>,
+    out: Option<PathBuf>,
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: i8080-coverage <rom> [--invaders --frames N] [--steps N] \
+         [--listing FILE --lcov FILE] [--symbols FILE] [--out FILE]"
+    );
+    process::exit(2);
+}
+
+fn parse_args() -> Options {
+    let mut args = env::args().skip(1);
+    let mut options = Options {
+        rom: PathBuf::new(),
+        frames: None,
+        steps: DEFAULT_STEPS,
+        listing: None,
+        lcov: None,
+        symbols: None,
+        out: None,
+    };
+    let mut rom = None;
+    let mut invaders = false;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--invaders" => invaders = true,
+            "--frames" => options.frames = Some(value().parse().unwrap_or_else(|_| usage())),
+            "--steps" => options.steps = value().parse().unwrap_or_else(|_| usage()),
+            "--listing" => options.listing = Some(value().into()),
+            "--lcov" => options.lcov = Some(value().into()),
+            "--symbols" => options.symbols = Some(value().into()),
+            "--out" => options.out = Some(value().into()),
+            _ if rom.is_none() && !arg.starts_with('-') => rom = Some(PathBuf::from(arg)),
+            _ => usage(),
+        }
+    }
+    options.rom = rom.unwrap_or_else(|| usage());
+    if invaders != options.frames.is_some() || options.listing.is_some() != options.lcov.is_some() {
+        usage();
+    }
+    options
+}
+
+fn main() {
+    let options = parse_args();
+    if let Err(e) = run(&options) {
+        eprintln!("i8080-coverage: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(options: &Options) -> Result<(), Error> {
+    let rom = Rom::from_path(&options.rom)?;
+    let symbols = match &options.symbols {
+        Some(path) => Some(Symbols::load(path)?),
+        None => None,
+    };
+    let mut coverage = Coverage::new();
+    let (annotated, rom_len) = match options.frames {
+        Some(frames) => {
+            let mut machine = SpaceInvaders::new(rom);
+            while machine.frame_count() < frames {
+                coverage.step(&mut machine)?;
+            }
+            let interconnect = machine.interconnect();
+            let rom_len = interconnect.rom_len();
+            let annotated = coverage.annotate(interconnect, 0, rom_len, symbols.as_ref());
+            (annotated.to_string(), rom_len)
+        }
+        None => {
+            let mut emulator = Emulator::new(rom);
+            for _ in 0..options.steps {
+                if emulator.is_finished() {
+                    break;
+                }
+                coverage.step_emulator(&mut emulator)?;
+            }
+            let interconnect = emulator.interconnect();
+            let rom_len = interconnect.rom_len();
+            let annotated = coverage.annotate(interconnect, 0, rom_len, symbols.as_ref());
+            (annotated.to_string(), rom_len)
+        }
+    };
+    match &options.out {
+        Some(path) => fs::write(path, annotated)?,
+        None => print!("{}", annotated),
+    }
+    if let (Some(listing), Some(lcov)) = (&options.listing, &options.lcov) {
+        let listing = Listing::load(listing)?;
+        coverage.write_lcov(fs::File::create(lcov)?, &listing)?;
+    }
+    eprintln!("{}", coverage.summary(0, rom_len));
+    Ok(())
+}
@@ -0,0 +1,17 @@
+//! A Debug Adapter Protocol server for 8080 roms, speaking over standard
+//! input and output.
+//!
+//! Usage: `i8080-dap`
+//!
+//! Editors start it as a debug adapter; the rom, listing and machine come
+//! from the `launch` request's arguments.
+
+use i8080_emulator::dap;
+use std::{io, process};
+
+fn main() {
+    if let Err(e) = dap::serve(io::stdin(), io::stdout()) {
+        eprintln!("i8080-dap: {}", e);
+        process::exit(1);
+    }
+}
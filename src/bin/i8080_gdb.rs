@@ -0,0 +1,83 @@
+//! Serves a rom to GDB over the remote serial protocol.
+//!
+//! Usage: `i8080-gdb <rom> [--port N | --stdio] [--invaders]`
+//!
+//! Listens on `127.0.0.1:1234` by default, one client at a time. With
+//! `--stdio` the protocol runs over standard input and output instead, for
+//! `target remote | i8080-gdb rom --stdio`. The rom runs on the bare cpu
+//! unless `--invaders` puts it in the full Space Invaders machine, with its
+//! video interrupts.
+
+use i8080_emulator::{
+    debugger::Target, gdb::GdbStub, interconnect::Rom, machine::SpaceInvaders, Emulator, Error,
+};
+use std::{
+    env, io,
+    net::{Ipv4Addr, TcpListener},
+    path::PathBuf,
+    process,
+};
+
+struct Options {
+    rom: PathBuf,
+    port: u16,
+    stdio: bool,
+    invaders: bool,
+}
+
+fn usage() -> ! {
+    eprintln!("usage: i8080-gdb <rom> [--port N | --stdio] [--invaders]");
+    process::exit(2);
+}
+
+fn parse_args() -> Options {
+    let mut args = env::args().skip(1);
+    let mut options = Options {
+        rom: PathBuf::new(),
+        port: 1234,
+        stdio: false,
+        invaders: false,
+    };
+    let mut rom = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--port" => {
+                options.port = args
+                    .next()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or_else(|| usage())
+            }
+            "--stdio" => options.stdio = true,
+            "--invaders" => options.invaders = true,
+            _ if rom.is_none() && !arg.starts_with('-') => rom = Some(PathBuf::from(arg)),
+            _ => usage(),
+        }
+    }
+    options.rom = rom.unwrap_or_else(|| usage());
+    options
+}
+
+fn main() {
+    let options = parse_args();
+    let result = Rom::from_path(&options.rom).and_then(|rom| match options.invaders {
+        true => serve(&options, SpaceInvaders::new(rom)),
+        false => serve(&options, Emulator::new(rom)),
+    });
+    if let Err(e) = result {
+        eprintln!("i8080-gdb: {}", e);
+        process::exit(1);
+    }
+}
+
+fn serve<T: Target>(options: &Options, target: T) -> Result<(), Error> {
+    let mut stub = GdbStub::new(target);
+    if options.stdio {
+        return stub.serve(io::stdin(), io::stdout());
+    }
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, options.port))?;
+    eprintln!("waiting for gdb on {}", listener.local_addr()?);
+    let (stream, client) = listener.accept()?;
+    eprintln!("debugging for {}", client);
+    stream.set_nodelay(true)?;
+    stub.serve(stream.try_clone()?, stream)
+}
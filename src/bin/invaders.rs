@@ -0,0 +1,341 @@
+//! Plays Space Invaders in an SDL2 window.
+//!
+//! Usage: `invaders <rom> [--patch FILE]... [--samples DIR] [--scale N] [--state FILE]
+//! [--no-overlay] [--record FILE | --replay FILE] [--cheats FILE]`
+//!
+//! `<rom>` is a directory holding the `invaders.e` to `invaders.h` set, an
+//! Intel HEX file, or a raw image loaded at 0. Samples are `0.wav` to `9.wav`.
+//!
+//! `--record` writes the session's inputs to a movie file on quitting;
+//! resetting or loading a state starts the recording over. `--replay` plays a
+//! movie back, stopping with an error if the picture ever differs from the
+//! recording, then hands control to the keyboard. `--cheats` holds the bytes
+//! listed in a cheat file at their values every frame. Movies carry the
+//! cheats they were recorded with and replay with those, so `--cheats` can't
+//! be given with `--replay`. `--patch` applies an IPS or BPS patch file to
+//! the rom first, and may be repeated.
+//!
+//! Keys:
+//!   C / 5             insert coin
+//!   1 / 2             one or two player start
+//!   Left Right Space  player one
+//!   J L K             player two
+//!   T                 tilt
+//!   P                 pause
+//!   Backspace         hold to rewind, up to ten seconds
+//!   F3                reset
+//!   F5 / F9           save / load state
+//!   F12               save a screenshot as `invaders_NNNNNN.png`
+//!   Esc               quit
+
+use i8080_emulator::{
+    audio::{Mixer, Samples},
+    cheats::Cheats,
+    interconnect::{Button, Rom, Sound, SoundEvent},
+    machine::{Movie, Replay, Rewind, SpaceInvaders, SCREEN_HEIGHT, SCREEN_WIDTH},
+    screenshot, Error,
+};
+use sdl2::{
+    audio::{AudioQueue, AudioSpecDesired},
+    event::Event,
+    keyboard::Keycode,
+    pixels::PixelFormatEnum,
+};
+use std::{
+    env, fs,
+    path::PathBuf,
+    process, thread,
+    time::{Duration, Instant},
+};
+
+const SAMPLE_RATE: i32 = 44_100;
+/// Audio queued beyond this is dropped rather than letting latency grow.
+const MAX_QUEUED_SAMPLES: u32 = SAMPLE_RATE as u32 / 10;
+/// Frames between rewind snapshots, and how many are kept.
+const REWIND_INTERVAL: u64 = 2;
+const REWIND_SNAPSHOTS: usize = 300;
+
+struct Options {
+    rom: PathBuf,
+    patches: Vec<PathBuf>,
+    samples: Option<PathBuf>,
+    scale: u32,
+    state: PathBuf,
+    overlay: bool,
+    record: Option<PathBuf>,
+    replay: Option<PathBuf>,
+    cheats: Option<PathBuf>,
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: invaders <rom> [--patch FILE]... [--samples DIR] [--scale N] [--state FILE] \
+         [--no-overlay] [--record FILE | --replay FILE] [--cheats FILE]"
+    );
+    process::exit(2);
+}
+
+fn parse_args() -> Options {
+    let mut args = env::args().skip(1);
+    let mut options = Options {
+        rom: PathBuf::new(),
+        patches: Vec::new(),
+        samples: None,
+        scale: 3,
+        state: PathBuf::from("invaders.state"),
+        overlay: true,
+        record: None,
+        replay: None,
+        cheats: None,
+    };
+    let mut rom = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--patch" => options
+                .patches
+                .push(args.next().unwrap_or_else(|| usage()).into()),
+            "--samples" => options.samples = Some(args.next().unwrap_or_else(|| usage()).into()),
+            "--scale" => {
+                options.scale = args
+                    .next()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|&s| s > 0)
+                    .unwrap_or_else(|| usage())
+            }
+            "--state" => options.state = args.next().unwrap_or_else(|| usage()).into(),
+            "--no-overlay" => options.overlay = false,
+            "--record" => options.record = Some(args.next().unwrap_or_else(|| usage()).into()),
+            "--replay" => options.replay = Some(args.next().unwrap_or_else(|| usage()).into()),
+            "--cheats" => options.cheats = Some(args.next().unwrap_or_else(|| usage()).into()),
+            _ if rom.is_none() && !arg.starts_with('-') => rom = Some(PathBuf::from(arg)),
+            _ => usage(),
+        }
+    }
+    options.rom = rom.unwrap_or_else(|| usage());
+    if options.replay.is_some() && (options.record.is_some() || options.cheats.is_some()) {
+        usage();
+    }
+    options
+}
+
+fn button(key: Keycode) -> Option<Button> {
+    match key {
+        Keycode::C | Keycode::Num5 => Some(Button::Coin),
+        Keycode::Num1 => Some(Button::Start1),
+        Keycode::Num2 => Some(Button::Start2),
+        Keycode::Left => Some(Button::Left1),
+        Keycode::Right => Some(Button::Right1),
+        Keycode::Space => Some(Button::Fire1),
+        Keycode::J => Some(Button::Left2),
+        Keycode::L => Some(Button::Right2),
+        Keycode::K => Some(Button::Fire2),
+        Keycode::T => Some(Button::Tilt),
+        _ => None,
+    }
+}
+
+fn main() {
+    let options = parse_args();
+    if let Err(e) = run(&options) {
+        eprintln!("invaders: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let rom = Rom::from_path(&options.rom)
+        .and_then(|rom| rom.with_patch_files(&options.patches))
+        .map_err(|e| e.to_string())?;
+    let mut machine = SpaceInvaders::new(rom);
+    if let Some(path) = &options.cheats {
+        machine.set_cheats(Cheats::load(path).map_err(|e| e.to_string())?);
+    }
+    let samples = match &options.samples {
+        Some(dir) => Samples::load_dir(dir).map_err(|e| e.to_string())?,
+        None => Samples::default(),
+    };
+    let mut mixer = Mixer::new(samples, SAMPLE_RATE as u32, 1);
+    let mut recording = options.record.as_ref().map(|_| Movie::new(&machine));
+    let movie = match &options.replay {
+        Some(path) => Some(
+            fs::read(path)
+                .map_err(Error::from)
+                .and_then(|bytes| Movie::from_bytes(&bytes))
+                .map_err(|e| e.to_string())?,
+        ),
+        None => None,
+    };
+    let mut replay = match &movie {
+        Some(movie) => Some(movie.replay(&mut machine).map_err(|e| e.to_string())?),
+        None => None,
+    };
+
+    let sdl = sdl2::init()?;
+    let video = sdl.video()?;
+    let window = video
+        .window(
+            "Space Invaders",
+            SCREEN_WIDTH as u32 * options.scale,
+            SCREEN_HEIGHT as u32 * options.scale,
+        )
+        .position_centered()
+        .build()
+        .map_err(|e| e.to_string())?;
+    let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+    let texture_creator = canvas.texture_creator();
+    let mut texture = texture_creator
+        .create_texture_streaming(
+            PixelFormatEnum::RGB888,
+            SCREEN_WIDTH as u32,
+            SCREEN_HEIGHT as u32,
+        )
+        .map_err(|e| e.to_string())?;
+
+    let audio = sdl.audio()?;
+    let queue: AudioQueue<i16> = audio.open_queue(
+        None,
+        &AudioSpecDesired {
+            freq: Some(SAMPLE_RATE),
+            channels: Some(1),
+            samples: Some(1024),
+        },
+    )?;
+    queue.resume();
+
+    let mut events = sdl.event_pump()?;
+    let frame_time = Duration::from_nanos(1_000_000_000 / 60);
+    let mut next_frame = Instant::now();
+    let mut paused = false;
+    let mut rewind = Rewind::new(REWIND_INTERVAL, REWIND_SNAPSHOTS);
+    let mut rewinding = false;
+    let mut pcm = Vec::new();
+    let mut pixels = Vec::with_capacity(SCREEN_WIDTH * SCREEN_HEIGHT * 4);
+
+    'running: loop {
+        for event in events.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => break 'running,
+                Event::KeyDown {
+                    keycode: Some(key),
+                    repeat: false,
+                    ..
+                } => match key {
+                    Keycode::P => paused = !paused,
+                    Keycode::Backspace => rewinding = true,
+                    Keycode::F3 => {
+                        machine.reset();
+                        restart(&mut recording, &mut replay, &machine);
+                    }
+                    Keycode::F5 => match fs::write(&options.state, machine.save_state()) {
+                        Ok(()) => eprintln!("saved state to {}", options.state.display()),
+                        Err(e) => eprintln!("cannot save state: {}", e),
+                    },
+                    Keycode::F9 => match fs::read(&options.state)
+                        .map_err(Error::from)
+                        .and_then(|state| machine.load_state(&state))
+                    {
+                        Ok(()) => {
+                            restart(&mut recording, &mut replay, &machine);
+                            mixer.stop_all();
+                            if machine.interconnect().sound().is_playing(Sound::Ufo) {
+                                mixer.handle(SoundEvent::Start(Sound::Ufo));
+                            }
+                        }
+                        Err(e) => eprintln!("cannot load state: {}", e),
+                    },
+                    Keycode::F12 => {
+                        let path = format!("invaders_{:06}.png", machine.frame_count());
+                        match screenshot::save(machine.frame(), &path, options.overlay) {
+                            Ok(()) => eprintln!("saved screenshot to {}", path),
+                            Err(e) => eprintln!("cannot save screenshot: {}", e),
+                        }
+                    }
+                    _ => {
+                        if let Some(button) = button(key) {
+                            machine.game_pad_mut().press(button);
+                        }
+                    }
+                },
+                Event::KeyUp {
+                    keycode: Some(key), ..
+                } => {
+                    if key == Keycode::Backspace {
+                        rewinding = false;
+                    } else if let Some(button) = button(key) {
+                        machine.game_pad_mut().release(button);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if rewinding {
+            if rewind
+                .step_back(&mut machine)
+                .map_err(|e| e.to_string())?
+                .is_some()
+            {
+                restart(&mut recording, &mut replay, &machine);
+                mixer.stop_all();
+            }
+        } else if !paused {
+            let cycles = machine.cpu().cycles();
+            if let Some(playing) = &mut replay {
+                if !playing.step(&mut machine).map_err(|e| e.to_string())? {
+                    eprintln!("replay finished after {} frames", playing.position());
+                    replay = None;
+                }
+            } else if let Some(movie) = &mut recording {
+                movie
+                    .record_frame(&mut machine)
+                    .map_err(|e| e.to_string())?;
+            } else {
+                machine.run_frame().map_err(|e| e.to_string())?;
+            }
+            rewind.record(&machine);
+            for event in machine.interconnect_mut().sound_mut().drain() {
+                mixer.handle(event);
+            }
+            mixer.mix(machine.cpu().cycles() - cycles, &mut pcm);
+            if queue.size() / 2 < MAX_QUEUED_SAMPLES {
+                queue.queue_audio(&pcm)?;
+            }
+            pcm.clear();
+        }
+
+        pixels.clear();
+        for rgb in machine.frame().to_rgb(options.overlay) {
+            pixels.extend_from_slice(&rgb.to_le_bytes());
+        }
+        texture
+            .update(None, &pixels, SCREEN_WIDTH * 4)
+            .map_err(|e| e.to_string())?;
+        canvas.copy(&texture, None, None)?;
+        canvas.present();
+
+        next_frame += frame_time;
+        let now = Instant::now();
+        if next_frame > now {
+            thread::sleep(next_frame - now);
+        } else {
+            next_frame = now;
+        }
+    }
+    if let (Some(path), Some(movie)) = (&options.record, &recording) {
+        fs::write(path, movie.to_bytes()).map_err(|e| e.to_string())?;
+        eprintln!("recorded {} frames to {}", movie.len(), path.display());
+    }
+    Ok(())
+}
+
+/// Starts the recording over after the machine jumped, and stops any replay.
+fn restart(recording: &mut Option<Movie>, replay: &mut Option<Replay>, machine: &SpaceInvaders) {
+    if let Some(movie) = recording {
+        *movie = Movie::new(machine);
+    }
+    *replay = None;
+}
@@ -0,0 +1,265 @@
+//! Runs Space Invaders without a display and saves frames as images.
+//!
+//! Usage: `invaders-dump <rom> [--patch FILE]...
+//! (--frames N [--press BUTTON@FRAME[+LEN]]... | --movie FILE)
+//! [--every K] [--out DIR] [--format ppm|png] [--no-overlay] [--expect IMAGE]
+//! [--profile FILE] [--symbols FILE] [--heatmap FILE] [--vram-writes] [--cheats FILE]`
+//!
+//! With `--every K` every Kth frame of the run is written to
+//! `DIR/frame_NNNNNN.EXT`, otherwise only the last one. `--press coin@60` holds the coin button for
+//! eight frames from frame 60; button names are those of `Button::name`.
+//! `--movie` instead replays a recorded movie from its starting state, with
+//! the cheats it was recorded with, failing if any frame's picture differs
+//! from the recording.
+//! `--expect` compares the last frame against a golden image and exits with
+//! status 1 if any pixel differs, e.g.
+//! `invaders-dump roms --frames 600 --press coin@60 --press start1@120 --expect attract_600.ppm`.
+//! PNG images, for `--expect` as for `--format`, need the `png` feature.
+//! `--profile` profiles the run, printing a report of the hottest routines
+//! and writing folded stacks for flamegraph tools to FILE. `--symbols` names
+//! the routines in both.
+//! `--heatmap` counts the reads, writes and fetches at every address over the
+//! run and saves them as CSV or, given an image extension, as a 256x256
+//! picture. `--vram-writes` saves `vram_NNNNNN.EXT` beside each frame written,
+//! showing the pixels drawn (green), erased (red), both (yellow) or rewritten
+//! unchanged (grey) during that frame. `--cheats` holds the bytes listed in a
+//! cheat file at their values every frame, to reach late-game states quickly;
+//! it can't be given with `--movie`.
+//! `--patch` applies an IPS or BPS patch file to the rom first, and may be
+//! repeated.
+
+use i8080_emulator::{
+    cheats::Cheats,
+    heatmap::Heatmap,
+    interconnect::{Button, Rom},
+    machine::{Movie, SpaceInvaders},
+    profiler::{Profiler, REPORT_ROWS},
+    screenshot::{self, FrameDumper, ImageFormat},
+    symbols::Symbols,
+};
+use std::{env, fs, path::PathBuf, process, sync::Arc};
+
+/// Frames a `--press` holds its button unless a length is given.
+const DEFAULT_HOLD: u64 = 8;
+
+struct Press {
+    button: Button,
+    start: u64,
+    len: u64,
+}
+
+impl Press {
+    fn parse(arg: &str) -> Option<Press> {
+        let (name, when) = arg.split_once('@')?;
+        let (start, len) = match when.split_once('+') {
+            Some((start, len)) => (start, len.parse().ok()?),
+            None => (when, DEFAULT_HOLD),
+        };
+        Some(Press {
+            button: Button::from_name(name)?,
+            start: start.parse().ok()?,
+            len,
+        })
+    }
+
+    fn held(&self, frame: u64) -> bool {
+        frame >= self.start && frame < self.start + self.len
+    }
+}
+
+struct Options {
+    rom: PathBuf,
+    patches: Vec<PathBuf>,
+    frames: u64,
+    every: Option<u64>,
+    out: PathBuf,
+    format: ImageFormat,
+    overlay: bool,
+    presses: Vec<Press>,
+    movie: Option<PathBuf>,
+    expect: Option<PathBuf>,
+    profile: Option<PathBuf>,
+    symbols: Option<PathBuf>,
+    heatmap: Option<PathBuf>,
+    vram_writes: bool,
+    cheats: Option<PathBuf>,
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: invaders-dump <rom> [--patch FILE]... (--frames N [--press BUTTON@FRAME[+LEN]]... | --movie FILE) \
+         [--every K] [--out DIR] [--format ppm|png] [--no-overlay] [--expect IMAGE] \
+         [--profile FILE] [--symbols FILE] [--heatmap FILE] [--vram-writes] \
+         [--cheats FILE]"
+    );
+    process::exit(2);
+}
+
+fn parse_args() -> Options {
+    let mut args = env::args().skip(1);
+    let mut options = Options {
+        rom: PathBuf::new(),
+        patches: Vec::new(),
+        frames: 0,
+        every: None,
+        out: PathBuf::from("."),
+        format: ImageFormat::Ppm,
+        overlay: true,
+        presses: Vec::new(),
+        movie: None,
+        expect: None,
+        profile: None,
+        symbols: None,
+        heatmap: None,
+        vram_writes: false,
+        cheats: None,
+    };
+    let mut rom = None;
+    let mut frames = None;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--patch" => options.patches.push(value().into()),
+            "--frames" => frames = Some(value().parse().unwrap_or_else(|_| usage())),
+            "--every" => {
+                options.every = Some(
+                    value()
+                        .parse()
+                        .ok()
+                        .filter(|&k| k > 0)
+                        .unwrap_or_else(|| usage()),
+                )
+            }
+            "--out" => options.out = value().into(),
+            "--format" => {
+                options.format = ImageFormat::from_extension(&value()).unwrap_or_else(|| usage())
+            }
+            "--no-overlay" => options.overlay = false,
+            "--press" => options
+                .presses
+                .push(Press::parse(&value()).unwrap_or_else(|| usage())),
+            "--movie" => options.movie = Some(value().into()),
+            "--expect" => options.expect = Some(value().into()),
+            "--profile" => options.profile = Some(value().into()),
+            "--symbols" => options.symbols = Some(value().into()),
+            "--heatmap" => options.heatmap = Some(value().into()),
+            "--vram-writes" => options.vram_writes = true,
+            "--cheats" => options.cheats = Some(value().into()),
+            _ if rom.is_none() && !arg.starts_with('-') => rom = Some(PathBuf::from(arg)),
+            _ => usage(),
+        }
+    }
+    options.rom = rom.unwrap_or_else(|| usage());
+    match (&options.movie, frames) {
+        (None, Some(frames)) => options.frames = frames,
+        (Some(_), None) if options.presses.is_empty() && options.cheats.is_none() => {}
+        _ => usage(),
+    }
+    options
+}
+
+fn main() {
+    let options = parse_args();
+    match run(&options) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("invaders-dump: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Returns whether the last frame matched `--expect`, if given.
+fn run(options: &Options) -> Result<bool, i8080_emulator::Error> {
+    let mut machine =
+        SpaceInvaders::new(Rom::from_path(&options.rom)?.with_patch_files(&options.patches)?);
+    let symbols = match &options.symbols {
+        Some(path) => Some(Arc::new(Symbols::load(path)?)),
+        None => None,
+    };
+    machine.cpu_mut().set_symbols(symbols.clone());
+    if options.profile.is_some() {
+        machine.set_profiler(Some(Profiler::new()));
+    }
+    if let Some(path) = &options.cheats {
+        machine.set_cheats(Cheats::load(path)?);
+    }
+    if options.heatmap.is_some() || options.vram_writes {
+        machine.set_heatmap(Some(Heatmap::new()));
+    }
+    let movie = match &options.movie {
+        Some(path) => Some(Movie::from_bytes(&fs::read(path)?)?),
+        None => None,
+    };
+    let frames = movie.as_ref().map_or(options.frames, |m| m.len() as u64);
+    let dumper = FrameDumper::new(
+        &options.out,
+        options.every.unwrap_or(frames.max(1)),
+        options.format,
+        options.overlay,
+    )?;
+    match &movie {
+        Some(movie) => {
+            let mut replay = movie.replay(&mut machine)?;
+            // The movie starts from its saved state's frame count.
+            let dumper = dumper.with_start(machine.frame_count());
+            while replay.step(&mut machine)? {
+                print_dump(&dumper, &machine, options.vram_writes)?;
+            }
+        }
+        None => {
+            for frame in 0..frames {
+                for &button in Button::ALL.iter() {
+                    let held = options
+                        .presses
+                        .iter()
+                        .any(|p| p.button == button && p.held(frame));
+                    machine.game_pad_mut().set(button, held);
+                }
+                machine.run_frame()?;
+                print_dump(&dumper, &machine, options.vram_writes)?;
+            }
+        }
+    }
+    if let (Some(path), Some(profiler)) = (&options.profile, machine.profiler()) {
+        let mut folded = String::new();
+        profiler
+            .write_folded(&mut folded, symbols.as_deref())
+            .expect("writing to a String");
+        fs::write(path, folded)?;
+        print!("{}", profiler.report(symbols.as_deref(), REPORT_ROWS));
+    }
+    if let (Some(path), Some(heatmap)) = (&options.heatmap, machine.heatmap()) {
+        heatmap.save(path)?;
+    }
+    if let Some(expect) = &options.expect {
+        let differing = machine.frame().diff(&screenshot::load(expect)?);
+        if differing > 0 {
+            eprintln!(
+                "frame {} differs from {} in {} pixels",
+                machine.frame_count(),
+                expect.display(),
+                differing
+            );
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn print_dump(
+    dumper: &FrameDumper,
+    machine: &SpaceInvaders,
+    vram_writes: bool,
+) -> Result<(), i8080_emulator::Error> {
+    if let Some(path) = dumper.dump(machine)? {
+        println!("{}", path.display());
+    }
+    if vram_writes {
+        if let Some(path) = dumper.dump_vram_writes(machine)? {
+            println!("{}", path.display());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,114 @@
+//! Runs Space Invaders under the control of a Rhai script, without a display.
+//!
+//! Usage: `invaders-script <rom> <script> [--patch FILE]... [--frames N] [--every K]
+//! [--out DIR] [--format ppm|png] [--no-overlay]`
+//!
+//! The run lasts until the script calls `stop()`, or for at most N frames
+//! with `--frames`. A script error, such as a failed check `throw`n from a
+//! callback, is printed and exits with status 1, so scripts can serve as test
+//! scenarios in CI. With `--every K` every Kth frame is written to
+//! `DIR/frame_NNNNNN.EXT` with the boxes the script outlined drawn over it.
+//! See the `script` module for the functions scripts can call.
+
+use i8080_emulator::{
+    interconnect::Rom,
+    machine::SpaceInvaders,
+    screenshot::{FrameDumper, ImageFormat},
+    script::Script,
+};
+use std::{env, path::PathBuf, process};
+
+struct Options {
+    rom: PathBuf,
+    script: PathBuf,
+    patches: Vec<PathBuf>,
+    frames: Option<u64>,
+    every: Option<u64>,
+    out: PathBuf,
+    format: ImageFormat,
+    overlay: bool,
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: invaders-script <rom> <script> [--patch FILE]... [--frames N] [--every K] \
+         [--out DIR] [--format ppm|png] [--no-overlay]"
+    );
+    process::exit(2);
+}
+
+fn parse_args() -> Options {
+    let mut args = env::args().skip(1);
+    let mut options = Options {
+        rom: PathBuf::new(),
+        script: PathBuf::new(),
+        patches: Vec::new(),
+        frames: None,
+        every: None,
+        out: PathBuf::from("."),
+        format: ImageFormat::Ppm,
+        overlay: true,
+    };
+    let mut paths = Vec::new();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--patch" => options.patches.push(value().into()),
+            "--frames" => options.frames = Some(value().parse().unwrap_or_else(|_| usage())),
+            "--every" => {
+                options.every = Some(
+                    value()
+                        .parse()
+                        .ok()
+                        .filter(|&k| k > 0)
+                        .unwrap_or_else(|| usage()),
+                )
+            }
+            "--out" => options.out = value().into(),
+            "--format" => {
+                options.format = ImageFormat::from_extension(&value()).unwrap_or_else(|| usage())
+            }
+            "--no-overlay" => options.overlay = false,
+            _ if paths.len() < 2 && !arg.starts_with('-') => paths.push(PathBuf::from(arg)),
+            _ => usage(),
+        }
+    }
+    if paths.len() != 2 {
+        usage();
+    }
+    options.script = paths.pop().unwrap();
+    options.rom = paths.pop().unwrap();
+    options
+}
+
+fn main() {
+    let options = parse_args();
+    if let Err(e) = run(&options) {
+        eprintln!("invaders-script: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(options: &Options) -> Result<(), i8080_emulator::Error> {
+    let rom = Rom::from_path(&options.rom)?.with_patch_files(&options.patches)?;
+    let mut machine = SpaceInvaders::new(rom);
+    let mut script = Script::load(&options.script, &mut machine)?;
+    let dumper = match options.every {
+        Some(every) => Some(FrameDumper::new(
+            &options.out,
+            every,
+            options.format,
+            options.overlay,
+        )?),
+        None => None,
+    };
+    while !script.is_stopped() && options.frames.is_none_or(|n| machine.frame_count() < n) {
+        script.run_frame(&mut machine)?;
+        if let Some(dumper) = &dumper {
+            if let Some(path) = dumper.dump_with(&machine, |pixels| script.draw_overlay(pixels))? {
+                println!("{}", path.display());
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,291 @@
+//! Plays Space Invaders in a terminal.
+//!
+//! Usage: `invaders-tui <rom> [--patch FILE]... [--half-blocks] [--no-color] [--vram-writes]
+//! [--cheats FILE] [--frames N]`
+//!
+//! The screen is drawn with braille characters (112x64 cells) by default, or
+//! half blocks (224x128) with `--half-blocks`. With `--frames N` the rom is run
+//! for N frames without a display and the final screen printed, which is handy
+//! in CI logs.
+//!
+//! V, or `--vram-writes` from the start, swaps the picture for the pixels video
+//! ram writes touched in the last frame: green drawn, red erased, yellow both
+//! and grey rewritten unchanged. `--cheats` holds the bytes listed in a cheat
+//! file at their values every frame. `--patch` applies an IPS or BPS patch
+//! file to the rom first, and may be repeated.
+//!
+//! Keys: C coin, 1/2 start, Left/Right/Space player one, P pause, V video ram
+//! writes, Q or Esc quit.
+//! Terminals rarely report key releases, so a key counts as held for a few
+//! frames after its last press or repeat.
+
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    queue,
+    style::{Color, Print, ResetColor, SetForegroundColor},
+    terminal,
+};
+use i8080_emulator::{
+    cheats::Cheats,
+    heatmap::{Heatmap, PixelWrite},
+    interconnect::{Button, Rom},
+    machine::{overlay_color, Frame, SpaceInvaders},
+};
+use std::{
+    env,
+    io::{self, Write},
+    path::PathBuf,
+    process, thread,
+    time::{Duration, Instant},
+};
+
+/// Frames a key stays pressed after the terminal last reported it.
+const HOLD_FRAMES: u32 = 8;
+
+struct Options {
+    rom: PathBuf,
+    patches: Vec<PathBuf>,
+    half_blocks: bool,
+    color: bool,
+    vram_writes: bool,
+    cheats: Option<PathBuf>,
+    frames: Option<u64>,
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: invaders-tui <rom> [--patch FILE]... [--half-blocks] [--no-color] [--vram-writes] \
+         [--cheats FILE] [--frames N]"
+    );
+    process::exit(2);
+}
+
+fn parse_args() -> Options {
+    let mut args = env::args().skip(1);
+    let mut options = Options {
+        rom: PathBuf::new(),
+        patches: Vec::new(),
+        half_blocks: false,
+        color: true,
+        vram_writes: false,
+        cheats: None,
+        frames: None,
+    };
+    let mut rom = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--patch" => options
+                .patches
+                .push(args.next().unwrap_or_else(|| usage()).into()),
+            "--half-blocks" => options.half_blocks = true,
+            "--no-color" => options.color = false,
+            "--vram-writes" => options.vram_writes = true,
+            "--cheats" => options.cheats = Some(args.next().unwrap_or_else(|| usage()).into()),
+            "--frames" => {
+                options.frames = Some(
+                    args.next()
+                        .and_then(|n| n.parse().ok())
+                        .unwrap_or_else(|| usage()),
+                )
+            }
+            _ if rom.is_none() && !arg.starts_with('-') => rom = Some(PathBuf::from(arg)),
+            _ => usage(),
+        }
+    }
+    options.rom = rom.unwrap_or_else(|| usage());
+    options
+}
+
+fn button(code: KeyCode) -> Option<Button> {
+    match code {
+        KeyCode::Char('c') | KeyCode::Char('5') => Some(Button::Coin),
+        KeyCode::Char('1') => Some(Button::Start1),
+        KeyCode::Char('2') => Some(Button::Start2),
+        KeyCode::Left => Some(Button::Left1),
+        KeyCode::Right => Some(Button::Right1),
+        KeyCode::Char(' ') => Some(Button::Fire1),
+        _ => None,
+    }
+}
+
+fn main() {
+    let options = parse_args();
+    let result = Rom::from_path(&options.rom)
+        .and_then(|rom| rom.with_patch_files(&options.patches))
+        .map_err(|e| e.to_string())
+        .and_then(|rom| {
+            let mut machine = SpaceInvaders::new(rom);
+            if let Some(path) = &options.cheats {
+                machine.set_cheats(Cheats::load(path).map_err(|e| e.to_string())?);
+            }
+            match options.frames {
+                Some(frames) => run_headless(&mut machine, frames, &options),
+                None => run_interactive(&mut machine, &options),
+            }
+        });
+    if let Err(e) = result {
+        eprintln!("invaders-tui: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run_headless(machine: &mut SpaceInvaders, frames: u64, options: &Options) -> Result<(), String> {
+    if options.vram_writes {
+        machine.set_heatmap(Some(Heatmap::new()));
+    }
+    for _ in 0..frames {
+        machine.run_frame().map_err(|e| e.to_string())?;
+    }
+    let mut out = io::stdout();
+    draw(&mut out, machine, options).map_err(|e| e.to_string())?;
+    out.flush().map_err(|e| e.to_string())
+}
+
+fn run_interactive(machine: &mut SpaceInvaders, options: &Options) -> Result<(), String> {
+    let mut out = io::stdout();
+    terminal::enable_raw_mode().map_err(|e| e.to_string())?;
+    let result = queue!(out, terminal::EnterAlternateScreen, cursor::Hide)
+        .and_then(|()| out.flush())
+        .map_err(|e| e.to_string())
+        .and_then(|()| frame_loop(machine, options, &mut out));
+    let _ = queue!(
+        out,
+        ResetColor,
+        cursor::Show,
+        terminal::LeaveAlternateScreen
+    );
+    let _ = out.flush();
+    let _ = terminal::disable_raw_mode();
+    result
+}
+
+fn frame_loop(
+    machine: &mut SpaceInvaders,
+    options: &Options,
+    out: &mut impl Write,
+) -> Result<(), String> {
+    let frame_time = Duration::from_nanos(1_000_000_000 / 60);
+    let mut next_frame = Instant::now();
+    let mut held: Vec<(Button, u32)> = Vec::new();
+    let mut paused = false;
+    if options.vram_writes {
+        machine.set_heatmap(Some(Heatmap::new()));
+    }
+    loop {
+        while event::poll(Duration::from_secs(0)).map_err(|e| e.to_string())? {
+            if let Event::Key(KeyEvent {
+                code,
+                modifiers,
+                kind,
+                ..
+            }) = event::read().map_err(|e| e.to_string())?
+            {
+                match code {
+                    KeyCode::Esc | KeyCode::Char('q') => return Ok(()),
+                    KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => {
+                        return Ok(())
+                    }
+                    KeyCode::Char('p') if kind == KeyEventKind::Press => paused = !paused,
+                    KeyCode::Char('v') if kind == KeyEventKind::Press => {
+                        let heatmap = match machine.heatmap() {
+                            Some(_) => None,
+                            None => Some(Heatmap::new()),
+                        };
+                        machine.set_heatmap(heatmap);
+                    }
+                    _ => {}
+                }
+                if let Some(button) = button(code) {
+                    held.retain(|&(b, _)| b != button);
+                    if kind == KeyEventKind::Release {
+                        machine.game_pad_mut().release(button);
+                    } else {
+                        held.push((button, HOLD_FRAMES));
+                    }
+                }
+            }
+        }
+
+        if !paused {
+            let pad = machine.game_pad_mut();
+            for &(button, _) in held.iter() {
+                pad.press(button);
+            }
+            machine.run_frame().map_err(|e| e.to_string())?;
+            let pad = machine.game_pad_mut();
+            for (button, frames) in held.iter_mut() {
+                *frames -= 1;
+                if *frames == 0 {
+                    pad.release(*button);
+                }
+            }
+            held.retain(|&(_, frames)| frames > 0);
+        }
+
+        queue!(out, cursor::MoveTo(0, 0)).map_err(|e| e.to_string())?;
+        draw(out, machine, options).map_err(|e| e.to_string())?;
+        out.flush().map_err(|e| e.to_string())?;
+
+        next_frame += frame_time;
+        let now = Instant::now();
+        if next_frame > now {
+            thread::sleep(next_frame - now);
+        } else {
+            next_frame = now;
+        }
+    }
+}
+
+/// Writes the frame, tinting each line's cells with the overlay colour, or
+/// the last frame's video ram writes if the machine is recording them.
+fn draw(out: &mut impl Write, machine: &SpaceInvaders, options: &Options) -> io::Result<()> {
+    let writes = machine.heatmap().map(|h| h.vram_writes());
+    let written;
+    let frame = match writes {
+        Some(writes) => {
+            let pixels = writes.pixels();
+            written = Frame::from_pixels(
+                pixels
+                    .into_iter()
+                    .map(|w| w != PixelWrite::Untouched)
+                    .collect(),
+            );
+            &written
+        }
+        None => machine.frame(),
+    };
+    let (lines, cell_width, cell_height) = if options.half_blocks {
+        (frame.to_half_blocks(), 1, 2)
+    } else {
+        (frame.to_braille(), 2, 4)
+    };
+    let cells = writes.map(|w| w.cells(cell_width, cell_height));
+    let columns = frame.width() / cell_width;
+    let mut current = None;
+    for (row, line) in lines.iter().enumerate() {
+        for (column, c) in line.chars().enumerate() {
+            if options.color {
+                let rgb = match &cells {
+                    Some(cells) => cells[row * columns + column].rgb(),
+                    None => overlay_color(column * cell_width, row * cell_height),
+                };
+                if current != Some(rgb) {
+                    let color = Color::Rgb {
+                        r: (rgb >> 16) as u8,
+                        g: (rgb >> 8) as u8,
+                        b: rgb as u8,
+                    };
+                    queue!(out, SetForegroundColor(color))?;
+                    current = Some(rgb);
+                }
+            }
+            queue!(out, Print(c))?;
+        }
+        queue!(out, Print("\r\n"))?;
+    }
+    if options.color {
+        queue!(out, ResetColor)?;
+    }
+    Ok(())
+}
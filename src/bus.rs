@@ -0,0 +1,155 @@
+use crate::instruction::Instruction;
+use core::cell::RefCell;
+
+/// The address space as seen by the cpu.
+///
+/// `I8080` performs every memory access through a `Bus`, which keeps the core
+/// independent of any particular machine's memory map. The 8080's separate
+/// 256 port I/O space is reached through the same trait.
+pub trait Bus {
+    fn read_byte(&self, addr: u16) -> u8;
+    fn write_byte(&mut self, addr: u16, value: u8);
+
+    /// Handles an `IN` from `port`. Unmapped ports read as zero.
+    fn input(&mut self, _port: u8) -> u8 {
+        0
+    }
+
+    /// Handles an `OUT` to `port`. Writes to unmapped ports are dropped.
+    fn output(&mut self, _port: u8, _value: u8) {}
+}
+
+/// Watches the memory accesses made through a `Probed` bus.
+///
+/// Instruction fetches are reported as reads like any other.
+pub trait Probe {
+    fn read(&mut self, _addr: u16, _value: u8) {}
+
+    /// Called before `addr` is overwritten, while it still holds `old`.
+    fn write(&mut self, _addr: u16, _old: u8, _new: u8) {}
+}
+
+impl Probe for () {}
+
+impl<P: Probe + ?Sized> Probe for &mut P {
+    fn read(&mut self, addr: u16, value: u8) {
+        (**self).read(addr, value)
+    }
+
+    fn write(&mut self, addr: u16, old: u8, new: u8) {
+        (**self).write(addr, old, new)
+    }
+}
+
+/// Reports every access to both probes, the first first.
+impl<A: Probe, B: Probe> Probe for (A, B) {
+    fn read(&mut self, addr: u16, value: u8) {
+        self.0.read(addr, value);
+        self.1.read(addr, value);
+    }
+
+    fn write(&mut self, addr: u16, old: u8, new: u8) {
+        self.0.write(addr, old, new);
+        self.1.write(addr, old, new);
+    }
+}
+
+/// The bytes of the instruction being executed, for probes that tell its
+/// fetches apart from data reads.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Fetch {
+    start: u16,
+    len: u16,
+}
+
+impl Fetch {
+    /// The instruction at `pc`.
+    pub fn at(bus: &impl Bus, pc: u16) -> Fetch {
+        Fetch {
+            start: pc,
+            len: Instruction::read(bus, pc).len(),
+        }
+    }
+
+    /// The fetched addresses, opcode first.
+    pub fn addrs(self) -> impl Iterator<Item = u16> {
+        (0..self.len).map(move |i| self.start.wrapping_add(i))
+    }
+
+    /// Whether a read of `addr` is part of the fetch rather than data.
+    pub fn contains(self, addr: u16) -> bool {
+        addr.wrapping_sub(self.start) < self.len
+    }
+}
+
+/// A bus that reports every access to a `Probe` before passing it on.
+pub struct Probed<'a, B, P> {
+    bus: &'a mut B,
+    probe: RefCell<&'a mut P>,
+}
+
+impl<'a, B: Bus, P: Probe> Probed<'a, B, P> {
+    pub fn new(bus: &'a mut B, probe: &'a mut P) -> Probed<'a, B, P> {
+        Probed {
+            bus,
+            probe: RefCell::new(probe),
+        }
+    }
+}
+
+impl<'a, B: Bus, P: Probe> Bus for Probed<'a, B, P> {
+    fn read_byte(&self, addr: u16) -> u8 {
+        let value = self.bus.read_byte(addr);
+        self.probe.borrow_mut().read(addr, value);
+        value
+    }
+
+    fn write_byte(&mut self, addr: u16, value: u8) {
+        let old = self.bus.read_byte(addr);
+        self.probe.get_mut().write(addr, old, value);
+        self.bus.write_byte(addr, value);
+    }
+
+    fn input(&mut self, port: u8) -> u8 {
+        self.bus.input(port)
+    }
+
+    fn output(&mut self, port: u8, value: u8) {
+        self.bus.output(port, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Bus;
+    use crate::{
+        i8080::{Register, I8080},
+        instruction::{Instruction, Opcode},
+    };
+
+    struct FlatMemory([u8; 0x10000]);
+
+    impl Bus for FlatMemory {
+        fn read_byte(&self, addr: u16) -> u8 {
+            self.0[addr as usize]
+        }
+
+        fn write_byte(&mut self, addr: u16, value: u8) {
+            self.0[addr as usize] = value;
+        }
+    }
+
+    #[test]
+    fn cpu_runs_on_any_bus() {
+        let mut memory = FlatMemory([0; 0x10000]);
+        let mut cpu = I8080::new();
+        let lxi = Instruction::new_trinary(Opcode::LXI(Register::SP), 0x8000).unwrap();
+        let call = Instruction::new_trinary(Opcode::CALL, 0x0040).unwrap();
+        cpu.emulate_instruction(lxi, &mut memory).unwrap();
+        cpu.emulate_instruction(call, &mut memory).unwrap();
+        assert_eq!(cpu.pc(), 0x0040);
+        assert_eq!(cpu.sp(), 0x7ffe);
+        assert_eq!(memory.read_byte(0x7fff), 0x00);
+        assert_eq!(memory.read_byte(0x7ffe), 0x06);
+    }
+}
@@ -0,0 +1,422 @@
+//! Searching work ram for the bytes that matter, and freezing them.
+//!
+//! A `RamSearch` narrows work ram down to the addresses behaving some way
+//! between snapshots: holding a value, or having changed, stayed the same,
+//! gone up or gone down since the last look. Addresses found that way are
+//! frozen as `Cheats`, which the machine writes back at the end of every
+//! frame. Cheat files list one `address:value` pair per line, both in hex:
+//!
+//! ```text
+//! ; player one's score, 9990
+//! 20f8:90
+//! 20f9:99
+//! ```
+//!
+//! `CheatConsole` offers both as text commands, for the debugger front ends.
+
+use crate::{
+    debugger::Target,
+    mem_map::{WRAM_END, WRAM_START},
+    Bus, Error,
+};
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{
+    convert::TryFrom,
+    fmt::{self, Display, Write},
+};
+
+/// Candidates `CheatConsole` lists after a search.
+const LISTED_CANDIDATES: usize = 16;
+
+/// How a byte must compare with its value at the last snapshot to stay a
+/// candidate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    /// Holds exactly this value now.
+    Equal(u8),
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+}
+
+impl Comparison {
+    fn matches(self, old: u8, new: u8) -> bool {
+        match self {
+            Comparison::Equal(value) => new == value,
+            Comparison::Changed => new != old,
+            Comparison::Unchanged => new == old,
+            Comparison::Increased => new > old,
+            Comparison::Decreased => new < old,
+        }
+    }
+}
+
+/// Work ram addresses still matching every comparison made since the search
+/// began.
+#[derive(Clone, Debug)]
+pub struct RamSearch {
+    /// Work ram as of the last snapshot.
+    snapshot: Vec<u8>,
+    candidates: Vec<u16>,
+}
+
+impl RamSearch {
+    /// Starts a search with every work ram address a candidate.
+    pub fn new<T: Target + ?Sized>(target: &T) -> RamSearch {
+        RamSearch {
+            snapshot: (WRAM_START..=WRAM_END)
+                .map(|addr| target.read_byte(addr))
+                .collect(),
+            candidates: (WRAM_START..=WRAM_END).collect(),
+        }
+    }
+
+    /// Keeps the candidates whose value compares with the last snapshot as
+    /// asked, then takes a new snapshot. Returns how many remain.
+    pub fn filter<T: Target + ?Sized>(&mut self, target: &T, comparison: Comparison) -> usize {
+        let snapshot = &self.snapshot;
+        self.candidates.retain(|&addr| {
+            let old = snapshot[(addr - WRAM_START) as usize];
+            comparison.matches(old, target.read_byte(addr))
+        });
+        for (addr, value) in (WRAM_START..=WRAM_END).zip(self.snapshot.iter_mut()) {
+            *value = target.read_byte(addr);
+        }
+        self.candidates.len()
+    }
+
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+
+    /// The value of work ram address `addr` at the last snapshot.
+    pub fn snapshot_value(&self, addr: u16) -> Option<u8> {
+        let offset = addr.checked_sub(WRAM_START)?;
+        self.snapshot.get(offset as usize).copied()
+    }
+}
+
+/// Bytes held at fixed values.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Cheats {
+    frozen: BTreeMap<u16, u8>,
+}
+
+impl Cheats {
+    pub fn new() -> Cheats {
+        Cheats::default()
+    }
+
+    /// Parses a cheat file, one `address:value` pair per line.
+    pub fn parse(text: &str) -> Result<Cheats, Error> {
+        let mut cheats = Cheats::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split([';', '#']).next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = |reason| Error::InvalidCheats {
+                line: i + 1,
+                reason,
+            };
+            let (addr, value) = line
+                .split_once(':')
+                .ok_or_else(|| invalid("expected address:value"))?;
+            let addr = parse_hex(addr.trim()).ok_or_else(|| invalid("invalid address"))?;
+            let value = parse_hex(value.trim())
+                .filter(|&v| v <= 0xff)
+                .ok_or_else(|| invalid("invalid value"))?;
+            cheats.freeze(addr, value as u8);
+        }
+        Ok(cheats)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Cheats, Error> {
+        Cheats::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn freeze(&mut self, addr: u16, value: u8) {
+        self.frozen.insert(addr, value);
+    }
+
+    /// Returns whether `addr` was frozen.
+    pub fn unfreeze(&mut self, addr: u16) -> bool {
+        self.frozen.remove(&addr).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.frozen.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.frozen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frozen.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u16, u8)> + '_ {
+        self.frozen.iter().map(|(&addr, &value)| (addr, value))
+    }
+
+    /// Writes every frozen byte, as the cpu would.
+    pub fn apply<B: Bus>(&self, bus: &mut B) {
+        for (addr, value) in self.iter() {
+            bus.write_byte(addr, value);
+        }
+    }
+}
+
+/// Writes the cheats in the cheat file format.
+impl Display for Cheats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (addr, value) in self.iter() {
+            writeln!(f, "{:04x}:{:02x}", addr, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs ram searches and edits cheats from text commands:
+///
+/// ```text
+/// search                  start a new search over work ram
+/// search = VALUE          keep addresses holding VALUE
+/// search changed          ... that changed since the last search
+/// search unchanged        ... that stayed the same
+/// search increased        ... that went up
+/// search decreased        ... that went down
+/// search list             show the candidates
+/// freeze ADDR [VALUE]     hold ADDR at VALUE, or at what it holds now
+/// unfreeze ADDR|all       let go of ADDR, or of everything
+/// cheats                  list the cheats in the cheat file format
+/// ```
+///
+/// Addresses are hex. Values are decimal, or hex with a `0x` or `$` prefix.
+#[derive(Default)]
+pub struct CheatConsole {
+    search: Option<RamSearch>,
+}
+
+impl CheatConsole {
+    pub fn new() -> CheatConsole {
+        CheatConsole::default()
+    }
+
+    pub fn search(&self) -> Option<&RamSearch> {
+        self.search.as_ref()
+    }
+
+    /// Runs `line` against `target`, returning the text to show.
+    pub fn command<T: Target + ?Sized>(&mut self, target: &mut T, line: &str) -> String {
+        match self.run(target, line) {
+            Ok(output) => output,
+            Err(message) => message.to_string(),
+        }
+    }
+
+    fn run<T: Target + ?Sized>(
+        &mut self,
+        target: &mut T,
+        line: &str,
+    ) -> Result<String, &'static str> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words[..] {
+            ["search"] => {
+                self.search = Some(RamSearch::new(target));
+                Ok(format!(
+                    "searching {} bytes of work ram",
+                    WRAM_END - WRAM_START + 1
+                ))
+            }
+            ["search", "list"] => Ok(self.list(target)),
+            ["search", ref rest @ ..] => {
+                let comparison = match rest {
+                    ["=", value] | ["eq", value] => {
+                        Comparison::Equal(parse_value(value).ok_or("invalid value")?)
+                    }
+                    ["changed"] => Comparison::Changed,
+                    ["unchanged"] => Comparison::Unchanged,
+                    ["increased"] => Comparison::Increased,
+                    ["decreased"] => Comparison::Decreased,
+                    _ => return Err("unknown search; try search changed or search = 3"),
+                };
+                let search = self
+                    .search
+                    .as_mut()
+                    .ok_or("no search started; run search first")?;
+                search.filter(target, comparison);
+                Ok(self.list(target))
+            }
+            ["freeze", addr] | ["freeze", addr, _] => {
+                let addr = parse_hex(addr).ok_or("invalid address")?;
+                let value = match words.get(2) {
+                    Some(value) => parse_value(value).ok_or("invalid value")?,
+                    None => target.read_byte(addr),
+                };
+                target
+                    .cheats_mut()
+                    .ok_or("this target has no frames to apply cheats on")?
+                    .freeze(addr, value);
+                target.write_byte(addr, value);
+                Ok(format!("froze 0x{:04x} at 0x{:02x}", addr, value))
+            }
+            ["unfreeze", "all"] => {
+                let cheats = target.cheats_mut().ok_or("no cheats")?;
+                let count = cheats.len();
+                cheats.clear();
+                Ok(format!("unfroze {} addresses", count))
+            }
+            ["unfreeze", addr] => {
+                let addr = parse_hex(addr).ok_or("invalid address")?;
+                match target.cheats_mut().map(|c| c.unfreeze(addr)) {
+                    Some(true) => Ok(format!("unfroze 0x{:04x}", addr)),
+                    _ => Err("that address is not frozen"),
+                }
+            }
+            ["cheats"] => match target.cheats_mut() {
+                Some(cheats) if !cheats.is_empty() => Ok(cheats.to_string()),
+                _ => Ok("no cheats".to_string()),
+            },
+            _ => Err(
+                "commands: search [= VALUE|changed|unchanged|increased|decreased|list], \
+                      freeze ADDR [VALUE], unfreeze ADDR|all, cheats",
+            ),
+        }
+    }
+
+    /// The number of candidates, and the first few with their values.
+    fn list<T: Target + ?Sized>(&self, target: &T) -> String {
+        let search = match &self.search {
+            Some(search) => search,
+            None => return "no search started".to_string(),
+        };
+        let candidates = search.candidates();
+        let mut output = format!("{} candidates", candidates.len());
+        for &addr in candidates.iter().take(LISTED_CANDIDATES) {
+            let value = target.read_byte(addr);
+            let _ = write!(output, "\n0x{:04x} = {} (0x{:02x})", addr, value, value);
+        }
+        if candidates.len() > LISTED_CANDIDATES {
+            output.push_str("\n...");
+        }
+        output
+    }
+}
+
+/// Hex, with or without a `$` or `0x` prefix.
+fn parse_hex(text: &str) -> Option<u16> {
+    let digits = text
+        .strip_prefix('$')
+        .or_else(|| text.strip_prefix("0x"))
+        .unwrap_or(text);
+    u16::from_str_radix(digits, 16).ok()
+}
+
+/// Decimal, or hex with a `$` or `0x` prefix.
+fn parse_value(text: &str) -> Option<u8> {
+    if text.starts_with('$') || text.starts_with("0x") {
+        parse_hex(text).and_then(|v| u8::try_from(v).ok())
+    } else {
+        text.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CheatConsole, Cheats, Comparison, RamSearch};
+    use crate::{debugger::Target, machine::SpaceInvaders, Error};
+    use alloc::string::ToString;
+
+    /// Counts frames at 0x2010 from the video interrupt and keeps 0x2020 at 7.
+    fn machine() -> SpaceInvaders {
+        let mut rom = [0; 0x30];
+        rom[..3].copy_from_slice(&[0xc3, 0x18, 0x00]); // JMP 0x0018
+        rom[0x08..0x0a].copy_from_slice(&[0xfb, 0xc9]); // EI; RET
+        rom[0x10..0x16].copy_from_slice(&[
+            0x21, 0x10, 0x20, // LXI H, 0x2010
+            0x34, // INR M
+            0xfb, // EI
+            0xc9, // RET
+        ]);
+        rom[0x18..0x22].copy_from_slice(&[
+            0x31, 0x00, 0x24, // LXI SP, 0x2400
+            0x3e, 0x07, // MVI A, 7
+            0x32, 0x20, 0x20, // STA 0x2020
+            0xfb, // EI
+            0x76, // HLT
+        ]);
+        rom[0x22..0x25].copy_from_slice(&[0xc3, 0x21, 0x00]); // JMP 0x0021
+        SpaceInvaders::new(rom)
+    }
+
+    #[test]
+    fn narrows_down_ram() {
+        let mut machine = machine();
+        machine.run_frame().unwrap();
+        let mut search = RamSearch::new(&machine);
+        assert_eq!(search.candidates().len(), 0x400);
+        machine.run_frame().unwrap();
+        search.filter(&machine, Comparison::Increased);
+        assert_eq!(search.candidates(), [0x2010]);
+
+        let mut search = RamSearch::new(&machine);
+        machine.run_frame().unwrap();
+        search.filter(&machine, Comparison::Equal(7));
+        search.filter(&machine, Comparison::Unchanged);
+        assert_eq!(search.candidates(), [0x2020]);
+        assert_eq!(search.snapshot_value(0x2020), Some(7));
+    }
+
+    #[test]
+    fn freezes_bytes_each_frame() {
+        let mut machine = machine();
+        machine.set_cheats(Cheats::parse("; frame count\n2010:f0\n").unwrap());
+        for _ in 0..3 {
+            machine.run_frame().unwrap();
+            assert_eq!(machine.interconnect().read_byte(0x2010), 0xf0);
+        }
+        assert_eq!(machine.cheats().to_string(), "2010:f0\n");
+
+        match Cheats::parse("2010:f0\n2011 f0\n") {
+            Err(Error::InvalidCheats { line: 2, .. }) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert!(Cheats::parse("2010:100").is_err());
+    }
+
+    #[test]
+    fn console_commands() {
+        let mut machine = machine();
+        let mut console = CheatConsole::new();
+        machine.run_frame().unwrap();
+        console.command(&mut machine, "search");
+        machine.run_frame().unwrap();
+        assert_eq!(
+            console.command(&mut machine, "search increased"),
+            "1 candidates\n0x2010 = 1 (0x01)"
+        );
+        assert_eq!(
+            console.command(&mut machine, "freeze 2010 0x63"),
+            "froze 0x2010 at 0x63"
+        );
+        machine.run_frame().unwrap();
+        assert_eq!(machine.read_byte(0x2010), 0x63);
+        assert_eq!(console.command(&mut machine, "cheats"), "2010:63\n");
+        assert_eq!(
+            console.command(&mut machine, "unfreeze 2010"),
+            "unfroze 0x2010"
+        );
+        assert!(console
+            .command(&mut machine, "search sideways")
+            .starts_with("unknown search"));
+    }
+}
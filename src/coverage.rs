@@ -0,0 +1,366 @@
+//! Which bytes of a program were executed, read as data or never touched.
+//!
+//! `Coverage` watches a machine step by step. Each byte is marked as an
+//! opcode when an instruction starts there, as an operand when it follows an
+//! executed opcode, and as data when the program reads it. Executions are
+//! counted per opcode, for the lcov report.
+
+use crate::{
+    bus::Fetch, instruction::Instruction, machine::SpaceInvaders, symbols::Symbols, Bus, Emulator,
+    Error, Probe,
+};
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::fmt::{self, Display};
+
+const OPCODE: u8 = 1;
+const OPERAND: u8 = 2;
+const DATA: u8 = 4;
+
+/// Bytes shown on one line of an annotated listing outside code.
+const DATA_BYTES_PER_LINE: usize = 8;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub opcode: bool,
+    pub operand: bool,
+    pub data: bool,
+}
+
+impl Usage {
+    pub fn is_executed(&self) -> bool {
+        self.opcode || self.operand
+    }
+
+    pub fn is_untouched(&self) -> bool {
+        !self.opcode && !self.operand && !self.data
+    }
+}
+
+pub struct Coverage {
+    /// Usage flags by address.
+    usage: Vec<u8>,
+    executions: Vec<u32>,
+    fetch: Fetch,
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Coverage::new()
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage {
+            usage: vec![0; 0x10000],
+            executions: vec![0; 0x10000],
+            fetch: Fetch::default(),
+        }
+    }
+
+    pub fn usage(&self, addr: u16) -> Usage {
+        let flags = self.usage[addr as usize];
+        Usage {
+            opcode: flags & OPCODE != 0,
+            operand: flags & OPERAND != 0,
+            data: flags & DATA != 0,
+        }
+    }
+
+    /// How many times an instruction starting at `addr` was executed.
+    pub fn executions(&self, addr: u16) -> u32 {
+        self.executions[addr as usize]
+    }
+
+    /// Executes one instruction of `machine`, noting the bytes it used.
+    pub fn step(&mut self, machine: &mut SpaceInvaders) -> Result<(), Error> {
+        if !machine.cpu().is_halted() {
+            self.fetch(machine.interconnect(), machine.cpu().pc());
+        }
+        let result = machine.step_probed(self);
+        self.fetch = Fetch::default();
+        result
+    }
+
+    /// Like `step`, for a program on the bare cpu.
+    pub fn step_emulator(&mut self, emulator: &mut Emulator) -> Result<(), Error> {
+        if !emulator.is_finished() {
+            self.fetch(emulator.interconnect(), emulator.cpu().pc());
+        }
+        let result = emulator.try_step_probed(self);
+        self.fetch = Fetch::default();
+        result
+    }
+
+    fn fetch(&mut self, bus: &impl Bus, pc: u16) {
+        self.fetch = Fetch::at(bus, pc);
+        self.usage[pc as usize] |= OPCODE;
+        self.executions[pc as usize] = self.executions[pc as usize].saturating_add(1);
+        for addr in self.fetch.addrs().skip(1) {
+            self.usage[addr as usize] |= OPERAND;
+        }
+    }
+
+    /// Counts how the `len` bytes from `start` were used.
+    pub fn summary(&self, start: u16, len: usize) -> Summary {
+        let mut summary = Summary {
+            bytes: len,
+            ..Summary::default()
+        };
+        for addr in (0..len).map(|i| start.wrapping_add(i as u16)) {
+            let usage = self.usage(addr);
+            if usage.is_executed() {
+                summary.executed += 1;
+            }
+            if usage.data {
+                summary.data += 1;
+            }
+            if usage.is_untouched() {
+                summary.untouched += 1;
+            }
+        }
+        summary
+    }
+
+    /// Disassembles the `len` bytes from `start` as read through `bus`,
+    /// marking each line `*` if executed, `d` if only read as data and `-`
+    /// if never touched. Bytes never executed are shown as data.
+    pub fn annotate<'a, B: Bus>(
+        &'a self,
+        bus: &'a B,
+        start: u16,
+        len: usize,
+        symbols: Option<&'a Symbols>,
+    ) -> Annotated<'a, B> {
+        Annotated {
+            coverage: self,
+            bus,
+            start,
+            len,
+            symbols,
+        }
+    }
+
+    /// Writes line coverage of the listing's code lines in lcov's tracefile
+    /// format, each line hit as often as its instruction was executed.
+    #[cfg(feature = "std")]
+    pub fn write_lcov<W: std::io::Write>(
+        &self,
+        mut out: W,
+        listing: &crate::listing::Listing,
+    ) -> Result<(), Error> {
+        writeln!(out, "TN:")?;
+        writeln!(out, "SF:{}", listing.path().display())?;
+        let (mut found, mut hit) = (0, 0);
+        for (line, addr) in listing.code_lines() {
+            let executions = self.executions(addr);
+            writeln!(out, "DA:{},{}", line, executions)?;
+            found += 1;
+            if executions > 0 {
+                hit += 1;
+            }
+        }
+        writeln!(out, "LF:{}", found)?;
+        writeln!(out, "LH:{}", hit)?;
+        writeln!(out, "end_of_record")?;
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        *self = Coverage::new();
+    }
+}
+
+impl Probe for Coverage {
+    fn read(&mut self, addr: u16, _value: u8) {
+        if !self.fetch.contains(addr) {
+            self.usage[addr as usize] |= DATA;
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub bytes: usize,
+    pub executed: usize,
+    pub data: usize,
+    pub untouched: usize,
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let percent = |n: usize| match self.bytes {
+            0 => 0.0,
+            bytes => n as f64 * 100.0 / bytes as f64,
+        };
+        write!(
+            f,
+            "{} bytes: {} executed ({:.1}%), {} read as data ({:.1}%), {} never touched ({:.1}%)",
+            self.bytes,
+            self.executed,
+            percent(self.executed),
+            self.data,
+            percent(self.data),
+            self.untouched,
+            percent(self.untouched)
+        )
+    }
+}
+
+pub struct Annotated<'a, B> {
+    coverage: &'a Coverage,
+    bus: &'a B,
+    start: u16,
+    len: usize,
+    symbols: Option<&'a Symbols>,
+}
+
+impl<'a, B: Bus> Display for Annotated<'a, B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = |addr: u16| self.symbols.and_then(|s| s.name(addr));
+        let mut offset = 0;
+        while offset < self.len {
+            let addr = self.start.wrapping_add(offset as u16);
+            if let Some(name) = name(addr) {
+                writeln!(f, "{}:", name)?;
+            }
+            let usage = self.coverage.usage(addr);
+            if usage.opcode {
+                let instruction = Instruction::read(self.bus, addr);
+                let len = (instruction.len() as usize).min(self.len - offset);
+                let text = match self.symbols {
+                    Some(symbols) => symbols.instruction(&instruction).to_string(),
+                    None => instruction.to_string().trim_end().to_string(),
+                };
+                let bytes = hex_bytes(self.bus, addr, len);
+                writeln!(f, "{:04x} *  {:<8}   {}", addr, bytes, text)?;
+                offset += len;
+                continue;
+            }
+            // A run of bytes used alike, up to the next executed or named one.
+            let marker = if usage.data { 'd' } else { '-' };
+            let mut len = 1;
+            while len < DATA_BYTES_PER_LINE && offset + len < self.len {
+                let next = addr.wrapping_add(len as u16);
+                if self.coverage.usage(next) != usage || name(next).is_some() {
+                    break;
+                }
+                len += 1;
+            }
+            let bytes = hex_bytes(self.bus, addr, len);
+            writeln!(f, "{:04x} {}  {}", addr, marker, bytes)?;
+            offset += len;
+        }
+        Ok(())
+    }
+}
+
+/// The `len` bytes from `addr` in hex, separated by spaces.
+fn hex_bytes<B: Bus>(bus: &B, addr: u16, len: usize) -> String {
+    let bytes: Vec<String> = (0..len)
+        .map(|i| format!("{:02x}", bus.read_byte(addr.wrapping_add(i as u16))))
+        .collect();
+    bytes.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Coverage, Usage};
+    use crate::{symbols::Symbols, Emulator};
+    use alloc::string::ToString;
+
+    /// Loads a byte of the table, then halts.
+    #[cfg(feature = "std")]
+    const LISTING: &str = "\
+    1 0000 3A 08 00         LDA TABLE
+    2 0003 A7               ANA A
+    3 0004 C2 00 00         JNZ 0
+    4 0007 76               HLT
+    5 0008 00 01       TABLE: DB 0, 1
+    6 000A 3C               INR A
+";
+
+    fn run() -> (Emulator, Coverage) {
+        let bytecode = [
+            0x3a, 0x08, 0x00, 0xa7, 0xc2, 0x00, 0x00, 0x76, 0x00, 0x01, 0x3c,
+        ];
+        let mut emulator = Emulator::new(&bytecode[..]);
+        let mut coverage = Coverage::new();
+        while !emulator.is_finished() {
+            coverage.step_emulator(&mut emulator).unwrap();
+        }
+        (emulator, coverage)
+    }
+
+    #[test]
+    fn classifies_bytes() {
+        let (_, coverage) = run();
+        let opcode = Usage {
+            opcode: true,
+            ..Usage::default()
+        };
+        let operand = Usage {
+            operand: true,
+            ..Usage::default()
+        };
+        let data = Usage {
+            data: true,
+            ..Usage::default()
+        };
+        assert_eq!(coverage.usage(0x0000), opcode);
+        assert_eq!(coverage.usage(0x0001), operand);
+        assert_eq!(coverage.usage(0x0007), opcode);
+        assert_eq!(coverage.usage(0x0008), data);
+        assert!(coverage.usage(0x0009).is_untouched());
+        assert!(coverage.usage(0x000a).is_untouched());
+        assert_eq!(coverage.executions(0x0003), 1);
+
+        let summary = coverage.summary(0, 11);
+        assert_eq!(
+            (summary.executed, summary.data, summary.untouched),
+            (8, 1, 2)
+        );
+    }
+
+    #[test]
+    fn annotates() {
+        let (emulator, coverage) = run();
+        let symbols = Symbols::parse("Table = $0008").unwrap();
+        let annotated = coverage
+            .annotate(emulator.interconnect(), 0, 11, Some(&symbols))
+            .to_string();
+        assert_eq!(
+            annotated,
+            "\
+0000 *  3a 08 00   LDA    Table
+0003 *  a7         ANA    A
+0004 *  c2 00 00   JNZ    0x0000
+0007 *  76         HLT
+Table:
+0008 d  00
+0009 -  01 3c
+"
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn writes_lcov() {
+        use crate::listing::Listing;
+        use std::path::PathBuf;
+
+        let (_, coverage) = run();
+        let listing = Listing::parse(PathBuf::from("table.lst"), LISTING);
+        let mut lcov = Vec::new();
+        coverage.write_lcov(&mut lcov, &listing).unwrap();
+        assert_eq!(
+            String::from_utf8(lcov).unwrap(),
+            "TN:\nSF:table.lst\nDA:1,1\nDA:2,1\nDA:3,1\nDA:4,1\nDA:6,0\nLF:5\nLH:4\nend_of_record\n"
+        );
+    }
+}
@@ -0,0 +1,999 @@
+//! A Debug Adapter Protocol server, for debugging roms from VS Code and
+//! other DAP clients.
+//!
+//! The `launch` request takes the rom in `program`, which can also be an
+//! assembler's Intel HEX output, and optionally:
+//!
+//! - `listing`: the assembler listing, as read by `Listing`, so breakpoints
+//!   can be set in the listing and stepping shows where the program is.
+//! - `symbols`: a symbol file, naming stack frames and disassembly and
+//!   letting function breakpoints be set by name, like `DrawSprite+0x12`.
+//! - `invaders`: run the rom in the Space Invaders machine, with its video
+//!   interrupts, rather than on the bare cpu.
+//! - `stopOnEntry`: stop before the first instruction.
+//! - `stopOnAnomaly`: stop when a return doesn't match its call or the stack
+//!   is switched, rather than only logging it to the console.
+//! - `cheats`: a cheat file of bytes to hold each frame, for `invaders`.
+//!
+//! Expressions typed into the debug console run as `CheatConsole` commands,
+//! so work ram can be searched and bytes frozen while debugging.
+//!
+//! Registers are shown as variables, and `HL`, `SP` and `PC` carry memory
+//! references for the memory and disassembly views. The stack trace follows
+//! the cpu's shadow call stack.
+
+use crate::{
+    cheats::{CheatConsole, Cheats},
+    debugger::Target,
+    i8080::{ConditionalFlags, Register},
+    instruction::{self, Instruction, Opcode},
+    interconnect::Rom,
+    listing::Listing,
+    machine::SpaceInvaders,
+    symbols::Symbols,
+    Emulator, Error,
+};
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::TryFrom,
+    io::{self, BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, TryRecvError},
+        Arc,
+    },
+    thread,
+};
+
+/// Instructions run between checks for new requests while running.
+const BATCH: u32 = 10_000;
+const THREAD_ID: u64 = 1;
+const REGISTERS_REFERENCE: u64 = 1;
+/// The largest message body accepted, far more than any request needs.
+const MAX_MESSAGE: usize = 16 << 20;
+/// The most instructions disassembled at once, enough to fill the address
+/// space.
+const MAX_INSTRUCTIONS: i64 = 0x10000;
+
+/// How far a resumed program runs.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Run {
+    Continue,
+    Step,
+    /// Until `pc` returns to `addr` with the stack no deeper than `sp`.
+    Until {
+        addr: u16,
+        sp: u16,
+    },
+    /// Until a return takes the stack above `sp`.
+    Out {
+        sp: u16,
+    },
+}
+
+struct Session {
+    target: Box<dyn Target>,
+    listing: Option<Listing>,
+    symbols: Arc<Symbols>,
+    stop_on_entry: bool,
+    stop_on_anomaly: bool,
+    /// Breakpoints set by source line, by source path.
+    source_breakpoints: BTreeMap<PathBuf, BTreeSet<u16>>,
+    function_breakpoints: BTreeSet<u16>,
+    instruction_breakpoints: BTreeSet<u16>,
+    console: CheatConsole,
+}
+
+impl Session {
+    fn is_breakpoint(&self, addr: u16) -> bool {
+        self.instruction_breakpoints.contains(&addr)
+            || self.function_breakpoints.contains(&addr)
+            || self.source_breakpoints.values().any(|s| s.contains(&addr))
+    }
+}
+
+pub struct DapServer<W> {
+    output: W,
+    seq: u64,
+    session: Option<Session>,
+    running: Option<Run>,
+    /// Whether the client has sent `configurationDone`.
+    configured: bool,
+    started: bool,
+}
+
+impl<W: Write> DapServer<W> {
+    pub fn new(output: W) -> DapServer<W> {
+        DapServer {
+            output,
+            seq: 0,
+            session: None,
+            running: None,
+            configured: false,
+            started: false,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.is_some()
+    }
+
+    /// Handles one request. Returns false once the client has disconnected.
+    pub fn handle(&mut self, message: &Value) -> Result<bool, Error> {
+        let command = message["command"].as_str().unwrap_or("");
+        let args = &message["arguments"];
+        let result = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsDisassembleRequest": true,
+                "supportsFunctionBreakpoints": true,
+                "supportsInstructionBreakpoints": true,
+                "supportsReadMemoryRequest": true,
+                "supportsSetVariable": true,
+            })),
+            "launch" => self.launch(args),
+            "configurationDone" => {
+                self.configured = true;
+                Ok(Value::Null)
+            }
+            "disconnect" | "terminate" => {
+                self.respond(message, Ok(Value::Null))?;
+                return Ok(false);
+            }
+            _ => match self.session.as_mut() {
+                Some(session) => request(session, &mut self.running, command, args),
+                None => Err(format!("{} before launch", command)),
+            },
+        };
+        self.respond(message, result)?;
+        match command {
+            "initialize" => self.event("initialized", Value::Null)?,
+            "pause" if self.session.is_some() => self.stopped("pause", None)?,
+            _ => {}
+        }
+        self.start()
+    }
+
+    /// Starts the program once it is launched and the client has finished
+    /// configuring, in whichever order those arrive.
+    fn start(&mut self) -> Result<bool, Error> {
+        let stop_on_entry = match &self.session {
+            Some(session) if self.configured && !self.started => session.stop_on_entry,
+            _ => return Ok(true),
+        };
+        self.started = true;
+        if stop_on_entry {
+            self.stopped("entry", None)?;
+        } else {
+            self.running = Some(Run::Continue);
+        }
+        Ok(true)
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let program = args["program"].as_str().ok_or("no program to launch")?;
+        let rom = Rom::from_path(program).map_err(|e| e.to_string())?;
+        let mut target: Box<dyn Target> = match args["invaders"].as_bool() {
+            Some(true) => Box::new(SpaceInvaders::new(rom)),
+            _ => Box::new(Emulator::new(rom)),
+        };
+        target.cpu_mut().set_call_tracking(true);
+        let listing = match args["listing"].as_str() {
+            Some(path) => Some(Listing::load(Path::new(path)).map_err(|e| e.to_string())?),
+            None => None,
+        };
+        let symbols = match args["symbols"].as_str() {
+            Some(path) => {
+                let symbols = Arc::new(Symbols::load(path).map_err(|e| e.to_string())?);
+                target.cpu_mut().set_symbols(Some(symbols.clone()));
+                symbols
+            }
+            None => Arc::new(Symbols::new()),
+        };
+        if let Some(path) = args["cheats"].as_str() {
+            let cheats = Cheats::load(path).map_err(|e| e.to_string())?;
+            let frozen = target
+                .cheats_mut()
+                .ok_or("cheats need the invaders machine")?;
+            *frozen = cheats;
+        }
+        self.started = false;
+        self.session = Some(Session {
+            target,
+            listing,
+            symbols,
+            stop_on_entry: args["stopOnEntry"].as_bool().unwrap_or(false),
+            stop_on_anomaly: args["stopOnAnomaly"].as_bool().unwrap_or(false),
+            source_breakpoints: BTreeMap::new(),
+            function_breakpoints: BTreeSet::new(),
+            instruction_breakpoints: BTreeSet::new(),
+            console: CheatConsole::new(),
+        });
+        Ok(Value::Null)
+    }
+
+    /// Runs up to a batch of instructions, reporting any stop.
+    pub fn run_batch(&mut self) -> Result<(), Error> {
+        let run = match self.running {
+            Some(run) if self.session.is_some() => run,
+            _ => return Ok(()),
+        };
+        for _ in 0..BATCH {
+            let session = self.session.as_mut().unwrap();
+            let pc = session.target.cpu().pc();
+            match session.target.step() {
+                Ok(true) => {}
+                Ok(false) => {
+                    self.running = None;
+                    self.event("terminated", Value::Null)?;
+                    return self.event("exited", json!({ "exitCode": 0 }));
+                }
+                Err(e) => return self.stopped("exception", Some(e.to_string())),
+            }
+            let anomalies = match session.target.cpu_mut().call_stack_mut() {
+                Some(stack) => stack.take_anomalies(),
+                None => Vec::new(),
+            };
+            let stop_on_anomaly = session.stop_on_anomaly;
+            let cpu = session.target.cpu();
+            let reason = match run {
+                _ if session.is_breakpoint(cpu.pc()) => Some("breakpoint"),
+                Run::Step => Some("step"),
+                Run::Until { addr, sp } if cpu.pc() == addr && cpu.sp() >= sp => Some("step"),
+                // A return, rather than a POP falling through.
+                Run::Out { sp } if cpu.sp() > sp && cpu.pc() != pc.wrapping_add(1) => Some("step"),
+                _ => None,
+            };
+            for anomaly in &anomalies {
+                let output = format!("call stack: {}\n", anomaly);
+                self.event("output", json!({ "category": "console", "output": output }))?;
+            }
+            match anomalies.first() {
+                Some(anomaly) if stop_on_anomaly => {
+                    return self.stopped("exception", Some(anomaly.to_string()))
+                }
+                _ => {}
+            }
+            if let Some(reason) = reason {
+                return self.stopped(reason, None);
+            }
+        }
+        Ok(())
+    }
+
+    fn stopped(&mut self, reason: &str, text: Option<String>) -> Result<(), Error> {
+        self.running = None;
+        let mut body = json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        });
+        if let Some(text) = text {
+            body["text"] = json!(text);
+        }
+        self.event("stopped", body)
+    }
+
+    fn respond(&mut self, request: &Value, result: Result<Value, String>) -> Result<(), Error> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(Value::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> Result<(), Error> {
+        let mut message = json!({ "type": "event", "event": event });
+        if !body.is_null() {
+            message["body"] = body;
+        }
+        self.send(message)
+    }
+
+    fn send(&mut self, mut message: Value) -> Result<(), Error> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        let body = message.to_string();
+        write!(
+            self.output,
+            "Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )?;
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+/// Handles a request that needs a launched program.
+fn request(
+    session: &mut Session,
+    running: &mut Option<Run>,
+    command: &str,
+    args: &Value,
+) -> Result<Value, String> {
+    match command {
+        "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "8080" }] })),
+        "setBreakpoints" => Ok(set_breakpoints(session, args)),
+        "setFunctionBreakpoints" => {
+            session.function_breakpoints.clear();
+            let breakpoints: Vec<Value> = array(&args["breakpoints"])
+                .map(|b| {
+                    let name = b["name"].as_str().unwrap_or("");
+                    match session.symbols.resolve(name) {
+                        Some(addr) => {
+                            session.function_breakpoints.insert(addr);
+                            json!({
+                                "verified": true,
+                                "instructionReference": format!("0x{:04x}", addr),
+                            })
+                        }
+                        None => json!({ "verified": false, "message": "no such symbol" }),
+                    }
+                })
+                .collect();
+            Ok(json!({ "breakpoints": breakpoints }))
+        }
+        "setInstructionBreakpoints" => {
+            session.instruction_breakpoints.clear();
+            let breakpoints: Vec<Value> = array(&args["breakpoints"])
+                .map(|b| {
+                    let addr = b["instructionReference"]
+                        .as_str()
+                        .and_then(parse_reference)
+                        .map(|a| a.wrapping_add(b["offset"].as_i64().unwrap_or(0) as u16));
+                    if let Some(addr) = addr {
+                        session.instruction_breakpoints.insert(addr);
+                    }
+                    json!({ "verified": addr.is_some() })
+                })
+                .collect();
+            Ok(json!({ "breakpoints": breakpoints }))
+        }
+        "continue" => {
+            *running = Some(Run::Continue);
+            Ok(json!({ "allThreadsContinued": true }))
+        }
+        "next" => {
+            let cpu = session.target.cpu();
+            let instruction = Instruction::read(&TargetBus(&*session.target), cpu.pc());
+            *running = Some(match instruction.opcode() {
+                Opcode::CALL
+                | Opcode::CNZ
+                | Opcode::CZ
+                | Opcode::CNC
+                | Opcode::CC
+                | Opcode::CPO
+                | Opcode::CPE
+                | Opcode::CP
+                | Opcode::CM
+                | Opcode::RST(_) => Run::Until {
+                    addr: cpu.pc().wrapping_add(instruction.len()),
+                    sp: cpu.sp(),
+                },
+                _ => Run::Step,
+            });
+            Ok(Value::Null)
+        }
+        "stepIn" => {
+            *running = Some(Run::Step);
+            Ok(Value::Null)
+        }
+        "stepOut" => {
+            *running = Some(Run::Out {
+                sp: session.target.cpu().sp(),
+            });
+            Ok(Value::Null)
+        }
+        "pause" => {
+            *running = None;
+            Ok(Value::Null)
+        }
+        "stackTrace" => {
+            let cpu = session.target.cpu();
+            let mut addresses = vec![cpu.pc()];
+            if let Some(stack) = cpu.call_stack() {
+                addresses.extend(stack.frames().iter().rev().map(|f| f.call_site));
+            }
+            let start = args["startFrame"].as_u64().unwrap_or(0) as usize;
+            let levels = match args["levels"].as_u64() {
+                Some(levels) if levels > 0 => levels as usize,
+                _ => addresses.len(),
+            };
+            let frames: Vec<Value> = addresses
+                .iter()
+                .enumerate()
+                .skip(start)
+                .take(levels)
+                .map(|(id, &addr)| stack_frame(session, id, addr))
+                .collect();
+            Ok(json!({ "stackFrames": frames, "totalFrames": addresses.len() }))
+        }
+        "scopes" => Ok(json!({ "scopes": [{
+            "name": "Registers",
+            "presentationHint": "registers",
+            "variablesReference": REGISTERS_REFERENCE,
+            "expensive": false,
+        }] })),
+        "variables" => Ok(json!({ "variables": registers(session) })),
+        "setVariable" => {
+            let name = args["name"].as_str().unwrap_or("");
+            let value = args["value"].as_str().and_then(parse_number);
+            let value = value.ok_or("values are numbers, like 0x12 or 18")?;
+            set_register(session, name, value)?;
+            let variable = registers(session)
+                .into_iter()
+                .find(|v| v["name"] == name)
+                .unwrap_or(Value::Null);
+            Ok(json!({ "value": variable["value"] }))
+        }
+        "readMemory" => {
+            let base = args["memoryReference"].as_str().and_then(parse_reference);
+            let base = base.ok_or("bad memory reference")?;
+            let offset = args["offset"].as_i64().unwrap_or(0);
+            let addr = i64::from(base).checked_add(offset);
+            let addr = addr.ok_or("offset out of range")?;
+            let count = args["count"].as_i64().unwrap_or(0).max(0);
+            let start = addr.clamp(0, 0x10000);
+            let end = addr.saturating_add(count).clamp(start, 0x10000);
+            let data: Vec<u8> = (start..end)
+                .map(|a| session.target.read_byte(a as u16))
+                .collect();
+            Ok(json!({
+                "address": format!("0x{:04x}", start),
+                "data": base64(&data),
+                "unreadableBytes": count - data.len() as i64,
+            }))
+        }
+        "disassemble" => {
+            let base = args["memoryReference"].as_str().and_then(parse_reference);
+            let base = base.ok_or("bad memory reference")?;
+            let base = base.wrapping_add(args["offset"].as_i64().unwrap_or(0) as u16);
+            let offset = args["instructionOffset"].as_i64().unwrap_or(0);
+            let count = args["instructionCount"].as_i64().unwrap_or(0);
+            let count = count.clamp(0, MAX_INSTRUCTIONS) as usize;
+            Ok(json!({ "instructions": disassemble(session, base, offset, count) }))
+        }
+        "evaluate" => {
+            let expression = args["expression"].as_str().unwrap_or("");
+            let result = session.console.command(&mut *session.target, expression);
+            Ok(json!({ "result": result, "variablesReference": 0 }))
+        }
+        _ => Err(format!("{} is not supported", command)),
+    }
+}
+
+/// Frame `id` of the backtrace, at `addr`.
+fn stack_frame(session: &Session, id: usize, addr: u16) -> Value {
+    let mut frame = json!({
+        "id": id,
+        "name": session.symbols.address(addr).to_string(),
+        "line": 0,
+        "column": 0,
+        "instructionPointerReference": format!("0x{:04x}", addr),
+    });
+    if let Some(listing) = &session.listing {
+        if let Some(line) = listing.address_line(addr) {
+            frame["source"] = json!({ "path": listing.path() });
+            frame["line"] = json!(line);
+            frame["column"] = json!(1);
+        }
+    }
+    frame
+}
+
+fn set_breakpoints(session: &mut Session, args: &Value) -> Value {
+    let path = PathBuf::from(args["source"]["path"].as_str().unwrap_or(""));
+    let listing = session
+        .listing
+        .as_ref()
+        .filter(|l| same_file(l.path(), &path));
+    let mut addresses = BTreeSet::new();
+    let breakpoints: Vec<Value> = array(&args["breakpoints"])
+        .map(|b| {
+            let line = b["line"].as_u64().unwrap_or(0);
+            match listing.and_then(|l| l.line_address(line)) {
+                Some((line, addr)) => {
+                    addresses.insert(addr);
+                    json!({
+                        "verified": true,
+                        "line": line,
+                        "instructionReference": format!("0x{:04x}", addr),
+                    })
+                }
+                None => json!({
+                    "verified": false,
+                    "line": line,
+                    "message": "no code at or after this line in the listing",
+                }),
+            }
+        })
+        .collect();
+    session.source_breakpoints.insert(path, addresses);
+    json!({ "breakpoints": breakpoints })
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+fn registers(session: &Session) -> Vec<Value> {
+    let cpu = session.target.cpu();
+    let get = |r| cpu.get_8bit_register(r).unwrap();
+    let pair = |h, l| u16::from(get(h)) << 8 | u16::from(get(l));
+    let flags = cpu.flags();
+    let flag_names: Vec<&str> = [
+        (flags.s(), "S"),
+        (flags.z(), "Z"),
+        (flags.ac(), "AC"),
+        (flags.p(), "P"),
+        (flags.cy(), "CY"),
+    ]
+    .iter()
+    .filter(|(set, _)| *set)
+    .map(|&(_, name)| name)
+    .collect();
+    let word = |name: &str, value: u16, reference: bool| {
+        let mut variable = json!({
+            "name": name,
+            "value": format!("0x{:04x}", value),
+            "variablesReference": 0,
+        });
+        if reference {
+            variable["memoryReference"] = json!(format!("0x{:04x}", value));
+        }
+        variable
+    };
+    vec![
+        json!({
+            "name": "A",
+            "value": format!("0x{:02x}", get(Register::A)),
+            "variablesReference": 0,
+        }),
+        word("BC", pair(Register::B, Register::C), false),
+        word("DE", pair(Register::D, Register::E), false),
+        word("HL", pair(Register::H, Register::L), true),
+        word("SP", cpu.sp(), true),
+        word("PC", cpu.pc(), true),
+        json!({
+            "name": "flags",
+            "value": format!("0x{:02x} {}", u8::from(flags), flag_names.join(" ")),
+            "variablesReference": 0,
+        }),
+    ]
+}
+
+fn set_register(session: &mut Session, name: &str, value: u32) -> Result<(), String> {
+    let cpu = session.target.cpu_mut();
+    let (high, low) = ((value >> 8) as u8, value as u8);
+    let mut pair = |h, l| {
+        cpu.set_register(h, high).unwrap();
+        cpu.set_register(l, low).unwrap();
+    };
+    match name {
+        "A" => cpu.set_register(Register::A, low).unwrap(),
+        "BC" => pair(Register::B, Register::C),
+        "DE" => pair(Register::D, Register::E),
+        "HL" => pair(Register::H, Register::L),
+        "SP" => cpu.set_sp(value as u16),
+        "PC" => cpu.set_pc(value as u16),
+        "flags" => cpu.set_flags(ConditionalFlags::from(low)),
+        _ => return Err(format!("no register called {}", name)),
+    }
+    Ok(())
+}
+
+fn disassemble(session: &Session, base: u16, offset: i64, count: usize) -> Vec<Value> {
+    let bus = TargetBus(&*session.target);
+    // Instructions are 1 to 3 bytes, so decoding from three bytes per
+    // instruction back usually falls into step before reaching `base`.
+    let back = (-offset).clamp(0, 0x5555) as usize;
+    let start = base.wrapping_sub(3 * back as u16);
+    let before: Vec<_> = instruction::disassemble(&bus, start)
+        .take_while(|&(addr, _)| addr.wrapping_sub(start) < base.wrapping_sub(start))
+        .collect();
+    let skip = before.len().saturating_sub(back);
+    before
+        .into_iter()
+        .skip(skip)
+        .chain(instruction::disassemble(&bus, base))
+        .skip(offset.max(0) as usize)
+        .take(count)
+        .map(|(addr, instruction)| {
+            let bytes: Vec<String> = (0..instruction.len())
+                .map(|i| format!("{:02x}", session.target.read_byte(addr.wrapping_add(i))))
+                .collect();
+            let mut value = json!({
+                "address": format!("0x{:04x}", addr),
+                "instructionBytes": bytes.join(" "),
+                "instruction": session.symbols.instruction(&instruction).to_string(),
+            });
+            if let Some(name) = session.symbols.name(addr) {
+                value["symbol"] = json!(name);
+            }
+            if let Some(listing) = &session.listing {
+                if let Some(line) = listing.address_line(addr) {
+                    value["location"] = json!({ "path": listing.path() });
+                    value["line"] = json!(line);
+                }
+            }
+            value
+        })
+        .collect()
+}
+
+/// Lets the decoder read a target's memory.
+struct TargetBus<'a>(&'a dyn Target);
+
+impl<'a> crate::Bus for TargetBus<'a> {
+    fn read_byte(&self, addr: u16) -> u8 {
+        self.0.read_byte(addr)
+    }
+
+    fn write_byte(&mut self, _addr: u16, _value: u8) {}
+}
+
+fn array(value: &Value) -> impl Iterator<Item = &Value> {
+    value.as_array().into_iter().flatten()
+}
+
+/// Parses a memory or instruction reference, `0x1234`.
+fn parse_reference(text: &str) -> Option<u16> {
+    parse_number(text).and_then(|n| u16::try_from(n).ok())
+}
+
+fn parse_number(text: &str) -> Option<u32> {
+    let text = text.trim();
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::new();
+    for chunk in data.chunks(3) {
+        let bits = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |bits, (i, &b)| bits | u32::from(b) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+/// Reads one message, or None at the end of the input.
+fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse().ok();
+        }
+    }
+    let length = length.ok_or_else(|| invalid_data("missing Content-Length"))?;
+    if length > MAX_MESSAGE {
+        return Err(invalid_data("Content-Length too large"));
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| invalid_data(&e.to_string()))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Serves one client until it disconnects.
+///
+/// Requests are read on their own thread so that `pause` and breakpoint
+/// changes arrive while the program runs.
+pub fn serve<R, W>(input: R, output: W) -> Result<(), Error>
+where
+    R: Read + Send + 'static,
+    W: Write,
+{
+    let (sender, messages) = mpsc::channel();
+    thread::spawn(move || {
+        let mut input = BufReader::new(input);
+        loop {
+            let message = read_message(&mut input);
+            let end = !matches!(message, Ok(Some(_)));
+            if sender.send(message).is_err() || end {
+                break;
+            }
+        }
+    });
+    let mut server = DapServer::new(output);
+    loop {
+        let message = if server.is_running() {
+            match messages.try_recv() {
+                Ok(message) => Some(message),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        } else {
+            match messages.recv() {
+                Ok(message) => Some(message),
+                Err(_) => return Ok(()),
+            }
+        };
+        match message {
+            Some(Ok(Some(message))) => {
+                if !server.handle(&message)? {
+                    return Ok(());
+                }
+            }
+            Some(Ok(None)) => return Ok(()),
+            Some(Err(e)) => return Err(e.into()),
+            None => server.run_batch()?,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{base64, read_message, DapServer};
+    use serde_json::{json, Value};
+    use std::io::Cursor;
+
+    const LISTING: &str = "\
+; counter
+    1 0000 31 00 24         LXI SP,2400H
+    2 0003 3E 00            MVI A,0
+    3                  LOOP:
+    4 0005 3C               INR A
+    5 0006 32 00 20         STA 2000H
+    6 0009 CD 0F 00         CALL SUB
+    7 000C C3 05 00         JMP LOOP
+    8 000F C9          SUB: RET
+";
+
+    fn rom() -> Vec<u8> {
+        vec![
+            0x31, 0x00, 0x24, 0x3e, 0x00, 0x3c, 0x32, 0x00, 0x20, 0xcd, 0x0f, 0x00, 0xc3, 0x05,
+            0x00, 0xc9,
+        ]
+    }
+
+    /// Decodes everything the server wrote.
+    fn messages(output: &[u8]) -> Vec<Value> {
+        let mut input = Cursor::new(output);
+        let mut messages = Vec::new();
+        while let Some(message) = read_message(&mut input).unwrap() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    fn request(server: &mut DapServer<Vec<u8>>, command: &str, arguments: Value) -> Value {
+        let before = server.output.len();
+        assert!(server
+            .handle(
+                &json!({ "seq": 1, "type": "request", "command": command, "arguments": arguments })
+            )
+            .unwrap());
+        while server.is_running() {
+            server.run_batch().unwrap();
+        }
+        let sent = messages(&server.output[before..]);
+        let response = sent.iter().find(|m| m["type"] == "response").unwrap();
+        assert_eq!(response["success"], true, "{}", response);
+        json!({ "response": response, "events": sent.iter().filter(|m| m["type"] == "event").collect::<Vec<_>>() })
+    }
+
+    #[test]
+    fn debugs_a_rom() {
+        let dir = std::env::temp_dir().join(format!("i8080-dap-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let program = dir.join("counter.bin");
+        let listing = dir.join("counter.lst");
+        std::fs::write(&program, rom()).unwrap();
+        std::fs::write(&listing, LISTING).unwrap();
+
+        let mut server = DapServer::new(Vec::new());
+        request(&mut server, "initialize", json!({}));
+        request(
+            &mut server,
+            "launch",
+            json!({ "program": program, "listing": listing, "stopOnEntry": true }),
+        );
+        let done = request(&mut server, "configurationDone", json!({}));
+        assert_eq!(done["events"][0]["body"]["reason"], "entry");
+
+        let set = request(
+            &mut server,
+            "setBreakpoints",
+            json!({ "source": { "path": listing }, "breakpoints": [{ "line": 6 }, { "line": 20 }] }),
+        );
+        let breakpoints = &set["response"]["body"]["breakpoints"];
+        assert_eq!(breakpoints[0]["line"], 6);
+        assert_eq!(breakpoints[0]["verified"], true);
+        assert_eq!(breakpoints[1]["verified"], false);
+
+        let run = request(&mut server, "continue", json!({ "threadId": 1 }));
+        assert_eq!(run["events"][0]["body"]["reason"], "breakpoint");
+        let trace = request(&mut server, "stackTrace", json!({ "threadId": 1 }));
+        let frame = &trace["response"]["body"]["stackFrames"][0];
+        assert_eq!(frame["line"], 6);
+        assert_eq!(frame["instructionPointerReference"], "0x0006");
+
+        // Stepping over the CALL lands on the JMP, stepping into it on RET.
+        request(&mut server, "next", json!({ "threadId": 1 }));
+        request(&mut server, "next", json!({ "threadId": 1 }));
+        let vars = request(&mut server, "variables", json!({ "variablesReference": 1 }));
+        let vars = &vars["response"]["body"]["variables"];
+        assert_eq!(vars[0]["value"], "0x01");
+        assert_eq!(vars[5]["value"], "0x000c");
+        request(&mut server, "continue", json!({ "threadId": 1 }));
+        request(&mut server, "stepIn", json!({ "threadId": 1 }));
+        request(&mut server, "stepIn", json!({ "threadId": 1 }));
+        let vars = request(&mut server, "variables", json!({ "variablesReference": 1 }));
+        assert_eq!(vars["response"]["body"]["variables"][5]["value"], "0x000f");
+        let trace = request(&mut server, "stackTrace", json!({ "threadId": 1 }));
+        let frames = &trace["response"]["body"]["stackFrames"];
+        assert_eq!(frames[1]["instructionPointerReference"], "0x0009");
+        assert_eq!(frames[1]["line"], 7);
+        request(&mut server, "stepOut", json!({ "threadId": 1 }));
+        let vars = request(&mut server, "variables", json!({ "variablesReference": 1 }));
+        assert_eq!(vars["response"]["body"]["variables"][5]["value"], "0x000c");
+
+        let memory = request(
+            &mut server,
+            "readMemory",
+            json!({ "memoryReference": "0x2000", "count": 1 }),
+        );
+        assert_eq!(memory["response"]["body"]["data"], base64(&[2]));
+
+        let code = request(
+            &mut server,
+            "disassemble",
+            json!({ "memoryReference": "0x0009", "instructionOffset": -2, "instructionCount": 3 }),
+        );
+        let code = &code["response"]["body"]["instructions"];
+        assert_eq!(code[0]["address"], "0x0005");
+        assert_eq!(code[1]["instructionBytes"], "32 00 20");
+        assert_eq!(code[2]["line"], 7);
+
+        let search = request(
+            &mut server,
+            "evaluate",
+            json!({ "expression": "search", "context": "repl" }),
+        );
+        assert_eq!(
+            search["response"]["body"]["result"],
+            "searching 1024 bytes of work ram"
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn bounds_memory_requests() {
+        let dir = std::env::temp_dir().join(format!("i8080-dap-bounds-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let program = dir.join("counter.bin");
+        std::fs::write(&program, rom()).unwrap();
+
+        let mut server = DapServer::new(Vec::new());
+        request(&mut server, "initialize", json!({}));
+        request(
+            &mut server,
+            "launch",
+            json!({ "program": program, "stopOnEntry": true }),
+        );
+        request(&mut server, "configurationDone", json!({}));
+
+        let before = server.output.len();
+        let arguments = json!({ "memoryReference": "0x0001", "offset": i64::MAX, "count": 1 });
+        server
+            .handle(&json!({ "seq": 1, "type": "request", "command": "readMemory", "arguments": arguments }))
+            .unwrap();
+        let sent = messages(&server.output[before..]);
+        assert_eq!(sent[0]["success"], false);
+
+        let memory = request(
+            &mut server,
+            "readMemory",
+            json!({ "memoryReference": "0x2000", "count": -5 }),
+        );
+        assert_eq!(memory["response"]["body"]["data"], "");
+        assert_eq!(memory["response"]["body"]["unreadableBytes"], 0);
+
+        let code = request(
+            &mut server,
+            "disassemble",
+            json!({ "memoryReference": "0x0000", "instructionCount": i64::MAX }),
+        );
+        let code = code["response"]["body"]["instructions"].as_array().unwrap();
+        assert_eq!(code.len(), 0x10000);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn names_code_with_symbols() {
+        let dir = std::env::temp_dir().join(format!("i8080-dap-symbols-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let program = dir.join("counter.bin");
+        let symbols = dir.join("counter.sym");
+        std::fs::write(&program, rom()).unwrap();
+        std::fs::write(&symbols, "Loop = $0005\nSub = $000f\n").unwrap();
+
+        let mut server = DapServer::new(Vec::new());
+        request(
+            &mut server,
+            "launch",
+            json!({ "program": program, "symbols": symbols, "stopOnEntry": true }),
+        );
+        request(&mut server, "configurationDone", json!({}));
+        let set = request(
+            &mut server,
+            "setFunctionBreakpoints",
+            json!({ "breakpoints": [{ "name": "Loop+0x1" }, { "name": "Nowhere" }] }),
+        );
+        let breakpoints = &set["response"]["body"]["breakpoints"];
+        assert_eq!(breakpoints[0]["instructionReference"], "0x0006");
+        assert_eq!(breakpoints[1]["verified"], false);
+
+        request(&mut server, "continue", json!({ "threadId": 1 }));
+        let trace = request(&mut server, "stackTrace", json!({ "threadId": 1 }));
+        assert_eq!(
+            trace["response"]["body"]["stackFrames"][0]["name"],
+            "Loop+0x1"
+        );
+        let code = request(
+            &mut server,
+            "disassemble",
+            json!({ "memoryReference": "0x0009", "instructionCount": 3 }),
+        );
+        let code = &code["response"]["body"]["instructions"];
+        assert_eq!(code[0]["instruction"], "CALL   Sub");
+        assert_eq!(code[1]["instruction"], "JMP    Loop");
+        assert_eq!(code[2]["symbol"], "Sub");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_huge_messages() {
+        let mut input = Cursor::new(&b"Content-Length: 18446744073709551615\r\n\r\n{}"[..]);
+        assert!(read_message(&mut input).is_err());
+        let mut input = Cursor::new(&b"Content-Length: 2\r\n\r\n{}"[..]);
+        assert_eq!(read_message(&mut input).unwrap(), Some(json!({})));
+    }
+
+    #[test]
+    fn encodes_base64() {
+        assert_eq!(base64(b"Man"), "TWFu");
+        assert_eq!(base64(b"Ma"), "TWE=");
+        assert_eq!(base64(b"M"), "TQ==");
+    }
+}
@@ -0,0 +1,495 @@
+//! Breakpoints, watchpoints and reverse execution for `SpaceInvaders`.
+//!
+//! Every instruction the debugger executes is journaled: the registers,
+//! watchdog and frame timing from before it, the shift register and sound
+//! latches if it changed them, and the old value of each byte it wrote.
+//! Undoing the newest entry puts the machine back exactly where it was, so
+//! the debugger can step and continue backwards as well as forwards.
+
+use crate::{
+    bus::Fetch,
+    cheats::Cheats,
+    i8080::I8080,
+    machine::{Checkpoint, SpaceInvaders},
+    Emulator, Error, Probe,
+};
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+
+/// Instructions of history kept by `Debugger::new`, about a second of game
+/// time.
+pub const DEFAULT_HISTORY: usize = 1 << 18;
+
+/// A machine a debugging front end can drive one instruction at a time.
+pub trait Target {
+    fn cpu(&self) -> &I8080;
+    fn cpu_mut(&mut self) -> &mut I8080;
+    fn read_byte(&self, addr: u16) -> u8;
+    fn write_byte(&mut self, addr: u16, value: u8);
+
+    /// Executes one instruction. Returns false if the program has finished
+    /// and nothing was executed.
+    fn step(&mut self) -> Result<bool, Error>;
+
+    /// The bytes held each frame, if the target runs in frames.
+    fn cheats_mut(&mut self) -> Option<&mut Cheats> {
+        None
+    }
+}
+
+impl Target for Emulator {
+    fn cpu(&self) -> &I8080 {
+        Emulator::cpu(self)
+    }
+
+    fn cpu_mut(&mut self) -> &mut I8080 {
+        Emulator::cpu_mut(self)
+    }
+
+    fn read_byte(&self, addr: u16) -> u8 {
+        self.interconnect().read_byte(addr)
+    }
+
+    fn write_byte(&mut self, addr: u16, value: u8) {
+        self.interconnect_mut().write_byte(addr, value)
+    }
+
+    fn step(&mut self) -> Result<bool, Error> {
+        if self.is_finished() {
+            return Ok(false);
+        }
+        self.try_step()?;
+        Ok(true)
+    }
+}
+
+impl Target for SpaceInvaders {
+    fn cpu(&self) -> &I8080 {
+        SpaceInvaders::cpu(self)
+    }
+
+    fn cpu_mut(&mut self) -> &mut I8080 {
+        SpaceInvaders::cpu_mut(self)
+    }
+
+    fn read_byte(&self, addr: u16) -> u8 {
+        self.interconnect().read_byte(addr)
+    }
+
+    fn write_byte(&mut self, addr: u16, value: u8) {
+        self.interconnect_mut().write_byte(addr, value)
+    }
+
+    fn step(&mut self) -> Result<bool, Error> {
+        SpaceInvaders::step(self)?;
+        Ok(true)
+    }
+
+    fn cheats_mut(&mut self) -> Option<&mut Cheats> {
+        Some(SpaceInvaders::cheats_mut(self))
+    }
+}
+
+/// Which accesses to an address stop execution.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access,
+}
+
+impl WatchKind {
+    fn matches(self, write: bool) -> bool {
+        match self {
+            WatchKind::Read => !write,
+            WatchKind::Write => write,
+            WatchKind::Access => true,
+        }
+    }
+}
+
+/// Why the debugger handed control back.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stop {
+    /// A single step finished.
+    Step,
+    /// `pc` reached a breakpoint.
+    Breakpoint(u16),
+    /// An instruction touched a watched address. Going forwards the machine
+    /// stops after the instruction, going backwards before it.
+    Watchpoint { addr: u16, write: bool },
+    /// The step limit given to `resume` ran out.
+    Limit,
+    /// There is no more history to go back through.
+    HistoryStart,
+}
+
+/// One executed instruction, enough to undo it.
+struct Undo {
+    before: Checkpoint,
+    /// How many entries at this instruction's end of `Debugger::writes` it
+    /// made.
+    writes: usize,
+    /// The first watched access the instruction made, if any.
+    watch: Option<(u16, bool)>,
+}
+
+/// Records an instruction's writes and any watched access.
+struct Recorder<'a> {
+    writes: &'a mut VecDeque<(u16, u8)>,
+    count: usize,
+    watchpoints: &'a BTreeMap<u16, WatchKind>,
+    fetch: Fetch,
+    watch: Option<(u16, bool)>,
+}
+
+impl<'a> Recorder<'a> {
+    fn access(&mut self, addr: u16, write: bool) {
+        if self.watch.is_none() {
+            if let Some(kind) = self.watchpoints.get(&addr) {
+                if kind.matches(write) {
+                    self.watch = Some((addr, write));
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Probe for Recorder<'a> {
+    fn read(&mut self, addr: u16, _value: u8) {
+        if !self.fetch.contains(addr) {
+            self.access(addr, false);
+        }
+    }
+
+    fn write(&mut self, addr: u16, old: u8, _new: u8) {
+        self.writes.push_back((addr, old));
+        self.count += 1;
+        self.access(addr, true);
+    }
+}
+
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    watchpoints: BTreeMap<u16, WatchKind>,
+    journal: VecDeque<Undo>,
+    /// `(addr, old value)` for every journaled write, oldest first, shared
+    /// by all the entries rather than a buffer for each. While call tracking
+    /// is on, each entry still allocates a copy of the call stack.
+    writes: VecDeque<(u16, u8)>,
+    history: usize,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Debugger::new()
+    }
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger::with_history(DEFAULT_HISTORY)
+    }
+
+    /// Keeps the last `history` instructions for reverse execution. Zero
+    /// turns the journal off.
+    pub fn with_history(history: usize) -> Debugger {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeMap::new(),
+            journal: VecDeque::new(),
+            writes: VecDeque::new(),
+            history,
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    /// Returns whether there was a breakpoint at `addr`.
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn add_watchpoint(&mut self, addr: u16, kind: WatchKind) {
+        self.watchpoints.insert(addr, kind);
+    }
+
+    /// Returns whether there was a watchpoint on `addr`.
+    pub fn remove_watchpoint(&mut self, addr: u16) -> bool {
+        self.watchpoints.remove(&addr).is_some()
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = (u16, WatchKind)> + '_ {
+        self.watchpoints.iter().map(|(&addr, &kind)| (addr, kind))
+    }
+
+    /// Instructions that can currently be undone.
+    pub fn history_len(&self) -> usize {
+        self.journal.len()
+    }
+
+    /// Forgets the history, for instance after loading a state behind the
+    /// debugger's back.
+    pub fn clear_history(&mut self) {
+        self.journal.clear();
+        self.writes.clear();
+    }
+
+    /// Executes one instruction, and any interrupt that follows it.
+    pub fn step(&mut self, machine: &mut SpaceInvaders) -> Result<Stop, Error> {
+        let before = machine.checkpoint();
+        let pc = machine.cpu().pc();
+        let fetch = match machine.cpu().is_halted() {
+            true => Fetch::default(),
+            false => Fetch::at(machine.interconnect(), pc),
+        };
+        let mut recorder = Recorder {
+            writes: &mut self.writes,
+            count: 0,
+            watchpoints: &self.watchpoints,
+            fetch,
+            watch: None,
+        };
+        let result = machine.step_probed(&mut recorder);
+        let mut undo = Undo {
+            before,
+            writes: recorder.count,
+            watch: recorder.watch,
+        };
+        if let Err(e) = result {
+            // Leave the machine as it was before the failed instruction.
+            Debugger::undo(machine, &undo, &mut self.writes);
+            return Err(e);
+        }
+        let stop = match undo.watch {
+            Some((addr, write)) => Stop::Watchpoint { addr, write },
+            None => Stop::Step,
+        };
+        if self.history == 0 {
+            self.writes.clear();
+            return Ok(stop);
+        }
+        if self.journal.len() == self.history {
+            if let Some(oldest) = self.journal.pop_front() {
+                self.writes.drain(..oldest.writes);
+            }
+        }
+        machine.forget_unchanged_latches(&mut undo.before);
+        self.journal.push_back(undo);
+        Ok(stop)
+    }
+
+    /// Runs until a breakpoint or watchpoint is hit, or `limit` instructions
+    /// have been executed. A breakpoint at the starting `pc` is stepped over.
+    pub fn resume(&mut self, machine: &mut SpaceInvaders, limit: u64) -> Result<Stop, Error> {
+        for _ in 0..limit {
+            if let stop @ Stop::Watchpoint { .. } = self.step(machine)? {
+                return Ok(stop);
+            }
+            let pc = machine.cpu().pc();
+            if self.breakpoints.contains(&pc) {
+                return Ok(Stop::Breakpoint(pc));
+            }
+        }
+        Ok(Stop::Limit)
+    }
+
+    /// Undoes the last instruction executed.
+    pub fn reverse_step(&mut self, machine: &mut SpaceInvaders) -> Stop {
+        match self.journal.pop_back() {
+            Some(undo) => {
+                Debugger::undo(machine, &undo, &mut self.writes);
+                match undo.watch {
+                    Some((addr, write)) => Stop::Watchpoint { addr, write },
+                    None => Stop::Step,
+                }
+            }
+            None => Stop::HistoryStart,
+        }
+    }
+
+    /// Runs backwards until `pc` reaches a breakpoint, an undone instruction
+    /// touched a watched address, or the history runs out.
+    ///
+    /// Watchpoints are checked against the watch list as it was when each
+    /// instruction ran.
+    pub fn reverse_continue(&mut self, machine: &mut SpaceInvaders) -> Stop {
+        loop {
+            match self.reverse_step(machine) {
+                Stop::Step => {}
+                stop => return stop,
+            }
+            let pc = machine.cpu().pc();
+            if self.breakpoints.contains(&pc) {
+                return Stop::Breakpoint(pc);
+            }
+        }
+    }
+
+    /// Reverts `undo`, taking its writes off the end of `writes`.
+    fn undo(machine: &mut SpaceInvaders, undo: &Undo, writes: &mut VecDeque<(u16, u8)>) {
+        let memory = machine.interconnect_mut();
+        for _ in 0..undo.writes {
+            if let Some((addr, old)) = writes.pop_back() {
+                memory.write_byte(addr, old);
+            }
+        }
+        machine.restore(&undo.before);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Debugger, Stop, WatchKind};
+    use crate::machine::{SpaceInvaders, CYCLES_PER_FRAME};
+    use alloc::vec;
+
+    /// Counts in 0x2000 and calls a subroutine that copies the count to
+    /// 0x2001, with both video interrupts enabled.
+    fn program() -> [u8; 0x33] {
+        let mut rom = [0; 0x33];
+        rom[..3].copy_from_slice(&[0xc3, 0x20, 0x00]); // JMP 0x0020
+        rom[0x08..0x0a].copy_from_slice(&[0xfb, 0xc9]); // EI; RET
+        rom[0x10..0x12].copy_from_slice(&[0xfb, 0xc9]); // EI; RET
+        rom[0x20..].copy_from_slice(&[
+            0x31, 0x00, 0x24, // 0x20 LXI SP, 0x2400
+            0xfb, // 0x23 EI
+            0x21, 0x00, 0x20, // 0x24 LXI H, 0x2000
+            0x34, // 0x27 INR M
+            0xcd, 0x2e, 0x00, // 0x28 CALL 0x002e
+            0xc3, 0x27, 0x00, // 0x2b JMP 0x0027
+            0x7e, // 0x2e MOV A, M
+            0x32, 0x01, 0x20, // 0x2f STA 0x2001
+            0xc9, // 0x32 RET
+        ]);
+        rom
+    }
+
+    #[test]
+    fn reverse_step_undoes_everything() {
+        let mut machine = SpaceInvaders::new(program());
+        let mut debugger = Debugger::new();
+        let mut states = vec![crc32fast::hash(&machine.save_state())];
+        // Run through the interrupts of the first two frames.
+        while machine.frame_count() < 2 {
+            assert_eq!(debugger.step(&mut machine).unwrap(), Stop::Step);
+            states.push(crc32fast::hash(&machine.save_state()));
+        }
+        assert!(machine.cpu().cycles() > CYCLES_PER_FRAME);
+        states.pop();
+        while let Some(state) = states.pop() {
+            assert_eq!(debugger.reverse_step(&mut machine), Stop::Step);
+            assert_eq!(crc32fast::hash(&machine.save_state()), state);
+        }
+        assert_eq!(debugger.reverse_step(&mut machine), Stop::HistoryStart);
+        assert_eq!(machine.frame_count(), 0);
+    }
+
+    #[test]
+    fn breakpoints_both_ways() {
+        let mut machine = SpaceInvaders::new(program());
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0x002e);
+        assert_eq!(
+            debugger.resume(&mut machine, 100).unwrap(),
+            Stop::Breakpoint(0x002e)
+        );
+        assert_eq!(
+            debugger.resume(&mut machine, 100).unwrap(),
+            Stop::Breakpoint(0x002e)
+        );
+        assert_eq!(machine.interconnect().read_byte(0x2000), 2);
+        assert_eq!(
+            debugger.reverse_continue(&mut machine),
+            Stop::Breakpoint(0x002e)
+        );
+        assert_eq!(machine.interconnect().read_byte(0x2000), 1);
+        assert_eq!(debugger.reverse_continue(&mut machine), Stop::HistoryStart);
+        assert_eq!(machine.cpu().pc(), 0);
+        assert_eq!(debugger.resume(&mut machine, 3).unwrap(), Stop::Limit);
+    }
+
+    #[test]
+    fn watchpoints_both_ways() {
+        let mut machine = SpaceInvaders::new(program());
+        let mut debugger = Debugger::new();
+        debugger.add_watchpoint(0x2001, WatchKind::Write);
+        let write = Stop::Watchpoint {
+            addr: 0x2001,
+            write: true,
+        };
+        assert_eq!(debugger.resume(&mut machine, 100).unwrap(), write);
+        // Stopped just after the STA.
+        assert_eq!(machine.cpu().pc(), 0x0032);
+        assert_eq!(debugger.resume(&mut machine, 100).unwrap(), write);
+        assert_eq!(machine.interconnect().read_byte(0x2001), 2);
+
+        // Backwards, the STA is undone as well.
+        assert_eq!(debugger.reverse_continue(&mut machine), write);
+        assert_eq!(machine.cpu().pc(), 0x002f);
+        assert_eq!(machine.interconnect().read_byte(0x2001), 1);
+
+        // Fetching the watched bytes as code is not a read.
+        debugger.add_watchpoint(0x0032, WatchKind::Read);
+        assert_eq!(
+            debugger.resume(&mut machine, 100).unwrap(),
+            write,
+            "RET at 0x0032 is executed, not read"
+        );
+    }
+
+    #[test]
+    fn reverse_step_undoes_latches_and_calls() {
+        let mut rom = [0; 0x20];
+        rom[..0x19].copy_from_slice(&[
+            0x31, 0x00, 0x24, // LXI SP, 0x2400
+            0x3e, 0x12, // MVI A, 0x12
+            0xd3, 0x04, // OUT 4
+            0x3e, 0x35, // MVI A, 0x35
+            0xd3, 0x04, // OUT 4
+            0xd3, 0x02, // OUT 2
+            0xcd, 0x12, 0x00, // CALL 0x0012
+            0x76, // HLT
+            0x00, // NOP
+            0xd3, 0x03, // 0x12 OUT 3
+            0xdb, 0x03, // IN 3
+            0xd3, 0x05, // OUT 5
+            0xc9, // RET
+        ]);
+        let mut machine = SpaceInvaders::new(rom);
+        machine.cpu_mut().set_call_tracking(true);
+        let mut debugger = Debugger::new();
+        let mut states = vec![];
+        for _ in 0..11 {
+            let stack = machine.cpu().call_stack().unwrap().clone();
+            states.push((crc32fast::hash(&machine.save_state()), stack));
+            debugger.step(&mut machine).unwrap();
+        }
+        assert_eq!(machine.cpu().pc(), 0x0010);
+        while let Some((state, stack)) = states.pop() {
+            assert_eq!(debugger.reverse_step(&mut machine), Stop::Step);
+            assert_eq!(crc32fast::hash(&machine.save_state()), state);
+            assert_eq!(machine.cpu().call_stack(), Some(&stack));
+        }
+    }
+
+    #[test]
+    fn limited_history() {
+        let mut machine = SpaceInvaders::new(program());
+        let mut debugger = Debugger::with_history(4);
+        debugger.resume(&mut machine, 6).unwrap();
+        let state = machine.save_state();
+        debugger.resume(&mut machine, 4).unwrap();
+        assert_eq!(debugger.history_len(), 4);
+        for _ in 0..4 {
+            assert_eq!(debugger.reverse_step(&mut machine), Stop::Step);
+        }
+        assert_eq!(debugger.reverse_step(&mut machine), Stop::HistoryStart);
+        assert_eq!(machine.save_state(), state);
+    }
+}
@@ -0,0 +1,224 @@
+use crate::{
+    i8080::EmulateError,
+    instruction::{opcode::OpcodeSize, Instruction, Opcode},
+    manifest::Verification,
+};
+use core::fmt::{self, Display};
+
+/// Errors surfaced by the public `Emulator` and `Instruction` APIs.
+#[derive(Debug)]
+pub enum Error {
+    /// The cpu failed to execute the instruction fetched from `pc`.
+    ///
+    /// `bytes` holds the raw opcode and operand bytes as read from memory;
+    /// only the first `instruction.len()` of them are meaningful.
+    Emulate {
+        pc: u16,
+        bytes: [u8; 3],
+        instruction: Instruction,
+        source: EmulateError,
+    },
+    /// An `Instruction` was constructed from an opcode of the wrong size.
+    OpcodeSize {
+        opcode: Opcode,
+        expected: OpcodeSize,
+    },
+    /// A rom chunk would extend past the end of the address space.
+    RomTooLarge { origin: u16, len: usize },
+    /// Two rom chunks were loaded over the same address.
+    RomOverlap { addr: u16 },
+    /// An Intel HEX record could not be parsed.
+    InvalidHex { line: usize, reason: &'static str },
+    /// An Intel HEX record's checksum did not match its contents.
+    HexChecksum {
+        line: usize,
+        expected: u8,
+        found: u8,
+    },
+    /// An IPS or BPS patch is malformed or does not fit the image.
+    InvalidPatch { reason: &'static str },
+    /// A BPS patch's checksum of the `source`, `target` or `patch` did not match.
+    PatchChecksum {
+        which: &'static str,
+        expected: u32,
+        found: u32,
+    },
+    /// A line of a rom set manifest could not be parsed.
+    InvalidManifest { line: usize, reason: &'static str },
+    /// A line of a symbol file could not be parsed.
+    InvalidSymbols { line: usize, reason: &'static str },
+    /// A line of a cheat file could not be parsed.
+    InvalidCheats { line: usize, reason: &'static str },
+    /// A rom set is missing files or contains bad dumps.
+    BadRomSet(Verification),
+    /// A save state is corrupt or was made with a different rom.
+    InvalidState { reason: &'static str },
+    /// A movie file is corrupt or was recorded with a different rom.
+    InvalidMovie { reason: &'static str },
+    /// Replaying a movie produced a different picture than was recorded.
+    MovieDesync {
+        frame: u64,
+        expected: u32,
+        found: u32,
+    },
+    /// An image could not be read as a screenshot.
+    InvalidImage { reason: &'static str },
+    /// A sound sample is not a PCM WAV file the mixer can play.
+    #[cfg(feature = "audio")]
+    InvalidWav { reason: &'static str },
+    /// A script failed to compile, or raised an error while running.
+    #[cfg(feature = "scripting")]
+    Script(Box<rhai::EvalAltResult>),
+    #[cfg(feature = "std")]
+    Io(std::io::Error),
+}
+
+impl Error {
+    /// The address of the failing instruction, if the error came from the cpu.
+    pub fn pc(&self) -> Option<u16> {
+        match self {
+            Error::Emulate { pc, .. } => Some(*pc),
+            _ => None,
+        }
+    }
+
+    /// The raw bytes of the failing instruction, if the error came from the cpu.
+    pub fn bytes(&self) -> Option<&[u8]> {
+        match self {
+            Error::Emulate {
+                bytes, instruction, ..
+            } => Some(&bytes[..instruction.len() as usize]),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Emulate {
+                pc,
+                instruction,
+                source,
+                ..
+            } => {
+                write!(f, "0x{:04x}:", pc)?;
+                for byte in self.bytes().unwrap_or(&[]) {
+                    write!(f, " {:02x}", byte)?;
+                }
+                write!(f, " ({}): {}", instruction.opcode(), source)
+            }
+            Error::RomTooLarge { origin, len } => write!(
+                f,
+                "{} bytes at 0x{:04x} do not fit in the address space",
+                len, origin
+            ),
+            Error::RomOverlap { addr } => write!(f, "rom chunks overlap at 0x{:04x}", addr),
+            Error::InvalidHex { line, reason } => {
+                write!(f, "invalid Intel HEX on line {}: {}", line, reason)
+            }
+            Error::HexChecksum {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Intel HEX checksum mismatch on line {}: expected 0x{:02x}, found 0x{:02x}",
+                line, expected, found
+            ),
+            Error::InvalidPatch { reason } => write!(f, "invalid patch: {}", reason),
+            Error::PatchChecksum {
+                which,
+                expected,
+                found,
+            } => write!(
+                f,
+                "patch {} checksum mismatch: expected 0x{:08x}, found 0x{:08x}",
+                which, expected, found
+            ),
+            Error::InvalidManifest { line, reason } => {
+                write!(f, "invalid manifest on line {}: {}", line, reason)
+            }
+            Error::InvalidSymbols { line, reason } => {
+                write!(f, "invalid symbol file on line {}: {}", line, reason)
+            }
+            Error::InvalidCheats { line, reason } => {
+                write!(f, "invalid cheat file on line {}: {}", line, reason)
+            }
+            Error::BadRomSet(verification) => {
+                write!(f, "rom set {} cannot be loaded:", verification.set)?;
+                for (name, status) in verification.problems() {
+                    write!(f, " {} {};", name, status)?;
+                }
+                Ok(())
+            }
+            Error::InvalidState { reason } => write!(f, "invalid save state: {}", reason),
+            Error::InvalidMovie { reason } => write!(f, "invalid movie: {}", reason),
+            Error::MovieDesync {
+                frame,
+                expected,
+                found,
+            } => write!(
+                f,
+                "replay diverged at frame {}: expected picture crc 0x{:08x}, found 0x{:08x}",
+                frame, expected, found
+            ),
+            Error::InvalidImage { reason } => write!(f, "invalid image: {}", reason),
+            #[cfg(feature = "audio")]
+            Error::InvalidWav { reason } => write!(f, "invalid WAV file: {}", reason),
+            #[cfg(feature = "scripting")]
+            Error::Script(e) => write!(f, "script error: {}", e),
+            #[cfg(feature = "std")]
+            Error::Io(e) => write!(f, "{}", e),
+            Error::OpcodeSize { opcode, expected } => {
+                write!(f, "{} is not a {:?} opcode", opcode, expected)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Emulate { source, .. } => Some(source),
+            Error::Io(e) => Some(e),
+            #[cfg(feature = "scripting")]
+            Error::Script(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Error;
+    use crate::{i8080::EmulateError, Emulator};
+
+    #[test]
+    fn emulate_error_carries_context() {
+        let bytecode = [
+            0x31, 0x00, 0x20, // LXI SP, 0x2000
+            0xcd, 0x34, 0x12, // CALL 0x1234
+        ];
+        let mut system = Emulator::new(bytecode);
+        system.try_step().unwrap();
+        let err = system.try_step().unwrap_err();
+        assert_eq!(err.pc(), Some(0x0003));
+        assert_eq!(err.bytes(), Some(&[0xcd, 0x34, 0x12][..]));
+        match err {
+            Error::Emulate {
+                source: EmulateError::StackOverflow,
+                ..
+            } => {}
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+}
@@ -0,0 +1,502 @@
+//! A GDB remote serial protocol server.
+//!
+//! Lets gdb-multiarch or any other RSP client debug a rom: register and
+//! memory access, software breakpoints, single step and continue. The 8080
+//! isn't an architecture gdb knows, so the stub describes its registers in a
+//! target description: `af`, `bc`, `de`, `hl`, `sp` and `pc`, each 16 bits
+//! and sent little endian, with the flags in the low byte of `af`.
+//!
+//! ```text
+//! (gdb) set architecture auto
+//! (gdb) target remote localhost:1234
+//! ```
+//!
+//! `monitor` passes its arguments to a `CheatConsole`, so ram searches and
+//! cheats work from the gdb prompt: `monitor search`, `monitor search
+//! increased`, `monitor freeze 20f8 0x99`.
+
+use crate::{
+    cheats::CheatConsole,
+    debugger::Target,
+    i8080::{ConditionalFlags, Register},
+    Error,
+};
+use std::{
+    collections::BTreeSet,
+    io::{BufReader, Read, Write},
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+};
+
+/// Instructions run between checks for a Ctrl-C from the client.
+const POLL_INTERVAL: u32 = 10_000;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.i8080.cpu">
+    <reg name="af" bitsize="16" type="int" regnum="0"/>
+    <reg name="bc" bitsize="16" type="int"/>
+    <reg name="de" bitsize="16" type="int"/>
+    <reg name="hl" bitsize="16" type="data_ptr"/>
+    <reg name="sp" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+/// What to do after a packet.
+enum Action {
+    Reply(String),
+    Resume {
+        step: bool,
+    },
+    /// Reply if given, then end the session.
+    Exit(Option<String>),
+}
+
+pub struct GdbStub<T> {
+    target: T,
+    breakpoints: BTreeSet<u16>,
+    no_ack: bool,
+    console: CheatConsole,
+}
+
+impl<T: Target> GdbStub<T> {
+    pub fn new(target: T) -> GdbStub<T> {
+        GdbStub {
+            target,
+            breakpoints: BTreeSet::new(),
+            no_ack: false,
+            console: CheatConsole::new(),
+        }
+    }
+
+    pub fn target(&self) -> &T {
+        &self.target
+    }
+
+    pub fn into_target(self) -> T {
+        self.target
+    }
+
+    /// Talks to one client until it detaches or kills the session, or the
+    /// connection closes.
+    ///
+    /// `input` is read on its own thread so that a Ctrl-C from the client
+    /// can interrupt a running target.
+    pub fn serve<R, W>(&mut self, input: R, mut output: W) -> Result<(), Error>
+    where
+        R: Read + Send + 'static,
+        W: Write,
+    {
+        let (sender, bytes) = mpsc::channel();
+        thread::spawn(move || {
+            for byte in BufReader::new(input).bytes() {
+                match byte {
+                    Ok(byte) if sender.send(byte).is_ok() => {}
+                    _ => break,
+                }
+            }
+        });
+        self.no_ack = false;
+        while let Some(packet) = self.next_packet(&bytes, &mut output)? {
+            let reply = match self.command(&packet) {
+                Action::Reply(reply) => reply,
+                Action::Resume { step } => self.resume(step, &bytes),
+                Action::Exit(reply) => {
+                    if let Some(reply) = reply {
+                        self.send(&mut output, &reply)?;
+                    }
+                    return Ok(());
+                }
+            };
+            self.send(&mut output, &reply)?;
+        }
+        Ok(())
+    }
+
+    /// Waits for a well formed packet, acknowledging it. Returns None once the
+    /// client has gone.
+    fn next_packet<W: Write>(
+        &self,
+        bytes: &Receiver<u8>,
+        output: &mut W,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        loop {
+            match bytes.recv() {
+                Ok(b'$') => {}
+                // Acks, and Ctrl-C while already stopped.
+                Ok(_) => continue,
+                Err(_) => return Ok(None),
+            }
+            let mut packet = Vec::new();
+            loop {
+                match bytes.recv() {
+                    Ok(b'#') => break,
+                    Ok(byte) => packet.push(byte),
+                    Err(_) => return Ok(None),
+                }
+            }
+            let mut checksum = [0; 2];
+            for digit in checksum.iter_mut() {
+                match bytes.recv() {
+                    Ok(byte) => *digit = byte,
+                    Err(_) => return Ok(None),
+                }
+            }
+            let valid = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|c| u8::from_str_radix(c, 16).ok())
+                == Some(checksum_of(&packet));
+            if !self.no_ack {
+                output.write_all(if valid { b"+" } else { b"-" })?;
+                output.flush()?;
+            }
+            if valid || self.no_ack {
+                return Ok(Some(packet));
+            }
+        }
+    }
+
+    fn send<W: Write>(&self, output: &mut W, reply: &str) -> Result<(), Error> {
+        write!(output, "${}#{:02x}", reply, checksum_of(reply.as_bytes()))?;
+        output.flush()?;
+        Ok(())
+    }
+
+    fn command(&mut self, packet: &[u8]) -> Action {
+        let packet = String::from_utf8_lossy(packet);
+        let (kind, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        let reply = match kind {
+            "?" => "S05".to_string(),
+            "g" => self.registers(),
+            "G" => ok_or_error(self.set_registers(args)),
+            "p" => match usize::from_str_radix(args, 16).ok().filter(|&n| n < 6) {
+                Some(n) => little_endian(self.register_pair(n)),
+                None => "E01".to_string(),
+            },
+            "P" => ok_or_error(self.set_register(args)),
+            "m" => self.read_memory(args).unwrap_or_else(|| "E01".to_string()),
+            "M" => ok_or_error(self.write_memory(args)),
+            "c" | "s" => {
+                if let Some(addr) = parse_hex(args) {
+                    self.target.cpu_mut().set_pc(addr);
+                }
+                return Action::Resume { step: kind == "s" };
+            }
+            "Z" | "z" => self.breakpoint(kind == "Z", args),
+            "H" => "OK".to_string(),
+            "T" => "OK".to_string(),
+            "q" | "Q" => self.query(&packet),
+            "k" => return Action::Exit(None),
+            "D" => return Action::Exit(Some("OK".to_string())),
+            _ => String::new(),
+        };
+        Action::Reply(reply)
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            "PacketSize=1000;qXfer:features:read+;QStartNoAckMode+".to_string()
+        } else if packet == "QStartNoAckMode" {
+            self.no_ack = true;
+            "OK".to_string()
+        } else if let Some(command) = packet.strip_prefix("qRcmd,") {
+            match decode_hex(command).and_then(|c| String::from_utf8(c).ok()) {
+                Some(command) => {
+                    let output = self.console.command(&mut self.target, &command);
+                    format!("{}\n", output)
+                        .bytes()
+                        .map(|b| format!("{:02x}", b))
+                        .collect()
+                }
+                None => "E01".to_string(),
+            }
+        } else if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            match parse_pair(range) {
+                Some((offset, len)) => {
+                    let data = TARGET_XML.get(offset as usize..).unwrap_or("");
+                    let chunk = &data[..data.len().min(len as usize)];
+                    let more = if chunk.len() < data.len() { 'm' } else { 'l' };
+                    format!("{}{}", more, chunk)
+                }
+                None => "E01".to_string(),
+            }
+        } else {
+            match packet {
+                "qAttached" => "1".to_string(),
+                "qC" => "QC1".to_string(),
+                "qfThreadInfo" => "m1".to_string(),
+                "qsThreadInfo" => "l".to_string(),
+                _ => String::new(),
+            }
+        }
+    }
+
+    /// Runs the target until it stops, and returns the stop reply.
+    fn resume(&mut self, step: bool, bytes: &Receiver<u8>) -> String {
+        let mut polls = 0;
+        loop {
+            match self.target.step() {
+                Ok(true) => {}
+                Ok(false) => return "W00".to_string(),
+                Err(e) => {
+                    log::error!("{}", e);
+                    return "S04".to_string();
+                }
+            }
+            if step || self.breakpoints.contains(&self.target.cpu().pc()) {
+                return "S05".to_string();
+            }
+            polls += 1;
+            if polls == POLL_INTERVAL {
+                polls = 0;
+                match bytes.try_recv() {
+                    Ok(0x03) | Err(TryRecvError::Disconnected) => return "S02".to_string(),
+                    Ok(_) | Err(TryRecvError::Empty) => {}
+                }
+            }
+        }
+    }
+
+    fn breakpoint(&mut self, insert: bool, args: &str) -> String {
+        let mut fields = args.split(',');
+        let kind = fields.next();
+        let addr = fields.next().and_then(parse_hex);
+        match (kind, addr) {
+            // Software and hardware breakpoints behave the same here.
+            (Some("0"), Some(addr)) | (Some("1"), Some(addr)) => {
+                if insert {
+                    self.breakpoints.insert(addr);
+                } else {
+                    self.breakpoints.remove(&addr);
+                }
+                "OK".to_string()
+            }
+            _ => String::new(),
+        }
+    }
+
+    fn register_pair(&self, n: usize) -> u16 {
+        let cpu = self.target.cpu();
+        let pair = |high, low| {
+            u16::from(cpu.get_8bit_register(high).unwrap()) << 8
+                | u16::from(cpu.get_8bit_register(low).unwrap())
+        };
+        match n {
+            0 => pair(Register::A, Register::A) & 0xff00 | u16::from(u8::from(cpu.flags())),
+            1 => pair(Register::B, Register::C),
+            2 => pair(Register::D, Register::E),
+            3 => pair(Register::H, Register::L),
+            4 => cpu.sp(),
+            _ => cpu.pc(),
+        }
+    }
+
+    fn set_register_pair(&mut self, n: usize, value: u16) {
+        let cpu = self.target.cpu_mut();
+        let (high, low) = ((value >> 8) as u8, value as u8);
+        let mut set = |high_register, low_register| {
+            cpu.set_register(high_register, high).unwrap();
+            cpu.set_register(low_register, low).unwrap();
+        };
+        match n {
+            0 => {
+                cpu.set_register(Register::A, high).unwrap();
+                cpu.set_flags(ConditionalFlags::from(low));
+            }
+            1 => set(Register::B, Register::C),
+            2 => set(Register::D, Register::E),
+            3 => set(Register::H, Register::L),
+            4 => cpu.set_sp(value),
+            _ => cpu.set_pc(value),
+        }
+    }
+
+    fn registers(&self) -> String {
+        (0..6)
+            .map(|n| little_endian(self.register_pair(n)))
+            .collect()
+    }
+
+    fn set_registers(&mut self, hex: &str) -> Option<()> {
+        let bytes = decode_hex(hex).filter(|b| b.len() == 12)?;
+        for (n, pair) in bytes.chunks(2).enumerate() {
+            self.set_register_pair(n, u16::from_le_bytes([pair[0], pair[1]]));
+        }
+        Some(())
+    }
+
+    fn set_register(&mut self, args: &str) -> Option<()> {
+        let (n, value) = args.split_once('=')?;
+        let n = usize::from_str_radix(n, 16).ok().filter(|&n| n < 6)?;
+        let bytes = decode_hex(value).filter(|b| b.len() == 2)?;
+        self.set_register_pair(n, u16::from_le_bytes([bytes[0], bytes[1]]));
+        Some(())
+    }
+
+    fn read_memory(&self, args: &str) -> Option<String> {
+        let (addr, len) = parse_pair(args)?;
+        Some(
+            (0..len)
+                .map(|i| format!("{:02x}", self.target.read_byte(addr.wrapping_add(i))))
+                .collect(),
+        )
+    }
+
+    fn write_memory(&mut self, args: &str) -> Option<()> {
+        let (range, data) = args.split_once(':')?;
+        let (addr, len) = parse_pair(range)?;
+        let data = decode_hex(data).filter(|d| d.len() == len as usize)?;
+        for (i, byte) in data.into_iter().enumerate() {
+            self.target.write_byte(addr.wrapping_add(i as u16), byte);
+        }
+        Some(())
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &b| sum.wrapping_add(b))
+}
+
+fn ok_or_error(result: Option<()>) -> String {
+    match result {
+        Some(()) => "OK".to_string(),
+        None => "E01".to_string(),
+    }
+}
+
+fn little_endian(value: u16) -> String {
+    format!("{:02x}{:02x}", value as u8, value >> 8)
+}
+
+fn parse_hex(text: &str) -> Option<u16> {
+    u16::from_str_radix(text, 16).ok()
+}
+
+/// Parses `addr,len`.
+fn parse_pair(text: &str) -> Option<(u16, u16)> {
+    let (a, b) = text.split_once(',')?;
+    Some((parse_hex(a)?, parse_hex(b)?))
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{checksum_of, GdbStub};
+    use crate::Emulator;
+    use std::io::Cursor;
+
+    fn packet(data: &str) -> String {
+        format!("${}#{:02x}", data, checksum_of(data.as_bytes()))
+    }
+
+    /// Sends `packets` and returns the replies, acks stripped.
+    fn session(stub: &mut GdbStub<Emulator>, packets: &[&str]) -> Vec<String> {
+        let input: String = packets.iter().map(|p| packet(p)).collect();
+        let mut output = Vec::new();
+        stub.serve(Cursor::new(input.into_bytes()), &mut output)
+            .unwrap();
+        String::from_utf8(output)
+            .unwrap()
+            .split('$')
+            .skip(1)
+            .map(|reply| reply.split('#').next().unwrap().to_string())
+            .collect()
+    }
+
+    const PROGRAM: [u8; 10] = [
+        0x31, 0x00, 0x24, // 0x00 LXI SP, 0x2400
+        0x3e, 0x42, // 0x03 MVI A, 0x42
+        0x32, 0x00, 0x20, // 0x05 STA 0x2000
+        0x76, // 0x08 HLT
+        0x00,
+    ];
+
+    #[test]
+    fn registers_and_memory() {
+        let mut stub = GdbStub::new(Emulator::new(PROGRAM));
+        let replies = session(
+            &mut stub,
+            &[
+                "qSupported:multiprocess+",
+                "s",
+                "s",
+                "g",
+                "P3=3412",
+                "p3",
+                "m0,3",
+                "M2000,2:beef",
+                "m2000,2",
+                "qXfer:features:read:target.xml:0,10",
+                "D",
+            ],
+        );
+        assert!(replies[0].contains("qXfer:features:read+"));
+        assert_eq!(replies[1], "S05");
+        assert_eq!(replies[2], "S05");
+        // af with only the always-set flag bit, bc, de, hl, sp, pc.
+        assert_eq!(replies[3], "024200000000000000240500");
+        assert_eq!(replies[4], "OK");
+        assert_eq!(replies[5], "3412");
+        assert_eq!(stub.target().cpu().m(), 0x1234);
+        assert_eq!(replies[6], "310024");
+        assert_eq!(replies[8], "beef");
+        assert_eq!(replies[9], "m<?xml version=\"1");
+        assert_eq!(replies[10], "OK");
+    }
+
+    #[test]
+    fn breakpoints_and_continue() {
+        let mut stub = GdbStub::new(Emulator::new(PROGRAM));
+        let replies = session(&mut stub, &["Z0,5,1", "c", "p5", "z0,5,1", "c", "m2000,1"]);
+        assert_eq!(replies[..4], ["OK", "S05", "0500", "OK"]);
+        // The program halts, which ends it.
+        assert_eq!(replies[4], "W00");
+        assert_eq!(replies[5], "42");
+    }
+
+    #[test]
+    fn monitor_commands() {
+        let mut stub = GdbStub::new(Emulator::new(PROGRAM));
+        let hex = |text: &str| -> String { text.bytes().map(|b| format!("{:02x}", b)).collect() };
+        let replies = session(
+            &mut stub,
+            &[
+                &format!("qRcmd,{}", hex("search")),
+                "c",
+                &format!("qRcmd,{}", hex("search = 0x42")),
+            ],
+        );
+        assert_eq!(replies[0], hex("searching 1024 bytes of work ram\n"));
+        assert_eq!(replies[2], hex("1 candidates\n0x2000 = 66 (0x42)\n"));
+    }
+
+    #[test]
+    fn rejects_bad_checksums() {
+        let mut stub = GdbStub::new(Emulator::new(PROGRAM));
+        let mut output = Vec::new();
+        stub.serve(Cursor::new(b"$g#00$?#3f".to_vec()), &mut output)
+            .unwrap();
+        assert_eq!(output, b"-+$S05#b8");
+    }
+
+    #[test]
+    fn ignores_unknown_bytes() {
+        let mut stub = GdbStub::new(Emulator::new(PROGRAM));
+        let mut output = Vec::new();
+        stub.serve(Cursor::new(b"$\xff#ff".to_vec()), &mut output)
+            .unwrap();
+        assert_eq!(output, b"+$#00");
+    }
+}
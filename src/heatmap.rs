@@ -0,0 +1,405 @@
+//! Counts of every memory access, and a picture of what each frame drew.
+//!
+//! Attached to a machine with `SpaceInvaders::set_heatmap`, a `Heatmap`
+//! counts the data reads, writes and instruction fetches made at each of the
+//! 65536 addresses, either over the whole run or, with `set_per_frame`, for
+//! the last frame only. Addresses are counted as the cpu issued them, so
+//! accesses through the ram mirror show up above `0x4000`.
+//!
+//! It also records which screen pixels video ram writes touched during the
+//! current frame, telling pixels drawn from pixels erased, and pixels both
+//! drawn and erased in the same frame, which flicker.
+
+use crate::{
+    bus::Fetch,
+    interconnect::mirror,
+    machine::{screen_position, SCREEN_HEIGHT, SCREEN_WIDTH},
+    mem_map::{VRAM_END, VRAM_START},
+    Bus, Probe,
+};
+use alloc::{vec, vec::Vec};
+use core::fmt;
+
+/// Width and height of the heatmap picture, one pixel per address with a
+/// row for each 256 byte page.
+pub const HEATMAP_SIZE: usize = 256;
+
+const VRAM_LEN: usize = (VRAM_END - VRAM_START + 1) as usize;
+
+const REWRITTEN: u32 = 0x50_50_50;
+const DRAWN: u32 = 0x20_ff_20;
+const ERASED: u32 = 0xff_20_20;
+const FLICKERED: u32 = 0xff_ff_20;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub reads: u32,
+    pub writes: u32,
+    pub executes: u32,
+}
+
+impl Counts {
+    pub fn is_zero(&self) -> bool {
+        *self == Counts::default()
+    }
+}
+
+#[derive(Clone)]
+pub struct Heatmap {
+    reads: Vec<u32>,
+    writes: Vec<u32>,
+    executes: Vec<u32>,
+    fetch: Fetch,
+    per_frame: bool,
+    /// Whether a frame has ended since the last step, so the next one
+    /// starts the counts afresh.
+    frame_done: bool,
+    vram: VramWrites,
+}
+
+impl Default for Heatmap {
+    fn default() -> Self {
+        Heatmap::new()
+    }
+}
+
+impl Heatmap {
+    pub fn new() -> Heatmap {
+        Heatmap {
+            reads: vec![0; 0x10000],
+            writes: vec![0; 0x10000],
+            executes: vec![0; 0x10000],
+            fetch: Fetch::default(),
+            per_frame: false,
+            frame_done: false,
+            vram: VramWrites::new(),
+        }
+    }
+
+    /// Whether the counts start again with each frame, rather than covering
+    /// the whole run. Either way they hold the finished frame until the
+    /// machine steps into the next.
+    pub fn set_per_frame(&mut self, per_frame: bool) {
+        self.per_frame = per_frame;
+    }
+
+    pub fn counts(&self, addr: u16) -> Counts {
+        let addr = addr as usize;
+        Counts {
+            reads: self.reads[addr],
+            writes: self.writes[addr],
+            executes: self.executes[addr],
+        }
+    }
+
+    /// The video ram writes of the current frame, or of the frame just
+    /// finished.
+    pub fn vram_writes(&self) -> &VramWrites {
+        &self.vram
+    }
+
+    pub fn clear(&mut self) {
+        for counts in [&mut self.reads, &mut self.writes, &mut self.executes] {
+            counts.iter_mut().for_each(|c| *c = 0);
+        }
+        self.vram.clear();
+    }
+
+    /// Called by the machine before each instruction, with the address of
+    /// the one about to be fetched unless the cpu is halted.
+    pub(crate) fn begin_step<B: Bus>(&mut self, bus: &B, pc: Option<u16>) {
+        if self.frame_done {
+            if self.per_frame {
+                self.clear();
+            } else {
+                self.vram.clear();
+            }
+            self.frame_done = false;
+        }
+        self.fetch = pc.map_or_else(Fetch::default, |pc| Fetch::at(bus, pc));
+        for addr in self.fetch.addrs() {
+            let addr = addr as usize;
+            self.executes[addr] = self.executes[addr].saturating_add(1);
+        }
+    }
+
+    pub(crate) fn end_frame(&mut self) {
+        self.frame_done = true;
+    }
+
+    /// The counts as a picture, an address per pixel from `0x0000` at the
+    /// top left to `0xffff` at the bottom right. Writes light the red
+    /// channel, reads the green and fetches the blue, each on a log scale up
+    /// to the busiest address.
+    pub fn to_rgb(&self) -> Vec<u32> {
+        let scale = |counts: &[u32]| {
+            let max = bit_length(counts.iter().copied().max().unwrap_or(0)).max(1);
+            move |count: u32| bit_length(count) * 255 / max
+        };
+        let (red, green, blue) = (
+            scale(&self.writes),
+            scale(&self.reads),
+            scale(&self.executes),
+        );
+        (0..0x10000)
+            .map(|i| red(self.writes[i]) << 16 | green(self.reads[i]) << 8 | blue(self.executes[i]))
+            .collect()
+    }
+
+    /// Writes the counts of every address accessed as CSV, with a header.
+    pub fn write_csv<W: fmt::Write>(&self, out: &mut W) -> fmt::Result {
+        writeln!(out, "address,reads,writes,executes")?;
+        for addr in 0..=0xffff {
+            let counts = self.counts(addr);
+            if !counts.is_zero() {
+                writeln!(
+                    out,
+                    "0x{:04x},{},{},{}",
+                    addr, counts.reads, counts.writes, counts.executes
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves the counts to `path`, as CSV if it ends in `.csv` and otherwise
+    /// as an image of `to_rgb` in the format its extension names.
+    #[cfg(feature = "std")]
+    pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> Result<(), crate::Error> {
+        let path = path.as_ref();
+        if path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("csv"))
+        {
+            let mut csv = alloc::string::String::new();
+            self.write_csv(&mut csv).expect("writing to a String");
+            std::fs::write(path, csv)?;
+            Ok(())
+        } else {
+            crate::screenshot::save_rgb(path, HEATMAP_SIZE, HEATMAP_SIZE, &self.to_rgb())
+        }
+    }
+}
+
+impl Probe for Heatmap {
+    fn read(&mut self, addr: u16, _value: u8) {
+        if !self.fetch.contains(addr) {
+            self.reads[addr as usize] = self.reads[addr as usize].saturating_add(1);
+        }
+    }
+
+    fn write(&mut self, addr: u16, old: u8, new: u8) {
+        self.writes[addr as usize] = self.writes[addr as usize].saturating_add(1);
+        let addr = if addr > VRAM_END { mirror(addr) } else { addr };
+        if (VRAM_START..=VRAM_END).contains(&addr) {
+            self.vram.write((addr - VRAM_START) as usize, old, new);
+        }
+    }
+}
+
+fn bit_length(n: u32) -> u32 {
+    32 - n.leading_zeros()
+}
+
+/// How a frame's video ram writes left a pixel.
+///
+/// Ordered by how much the pixel changed, so a view showing several pixels
+/// as one keeps the greatest: flickering outranks erasing, then drawing,
+/// then rewriting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PixelWrite {
+    Untouched,
+    /// Written with the value it already had.
+    Rewritten,
+    Drawn,
+    Erased,
+    /// Both drawn and erased during the frame.
+    Flickered,
+}
+
+impl PixelWrite {
+    /// The colour `VramWrites::to_rgb` shows this pixel in, as `0xRRGGBB`.
+    pub fn rgb(self) -> u32 {
+        match self {
+            PixelWrite::Untouched => 0,
+            PixelWrite::Rewritten => REWRITTEN,
+            PixelWrite::Drawn => DRAWN,
+            PixelWrite::Erased => ERASED,
+            PixelWrite::Flickered => FLICKERED,
+        }
+    }
+}
+
+/// The pixels video ram writes touched over a frame.
+#[derive(Clone)]
+pub struct VramWrites {
+    written: Vec<bool>,
+    /// Bits turned on and off, by video ram byte.
+    drawn: Vec<u8>,
+    erased: Vec<u8>,
+}
+
+impl VramWrites {
+    fn new() -> VramWrites {
+        VramWrites {
+            written: vec![false; VRAM_LEN],
+            drawn: vec![0; VRAM_LEN],
+            erased: vec![0; VRAM_LEN],
+        }
+    }
+
+    fn write(&mut self, offset: usize, old: u8, new: u8) {
+        self.written[offset] = true;
+        self.drawn[offset] |= new & !old;
+        self.erased[offset] |= old & !new;
+    }
+
+    fn clear(&mut self) {
+        self.written.iter_mut().for_each(|w| *w = false);
+        self.drawn.iter_mut().for_each(|b| *b = 0);
+        self.erased.iter_mut().for_each(|b| *b = 0);
+    }
+
+    /// Number of video ram bytes written.
+    pub fn bytes_written(&self) -> usize {
+        self.written.iter().filter(|&&w| w).count()
+    }
+
+    /// Every pixel of the upright screen, row by row from the top left.
+    pub fn pixels(&self) -> Vec<PixelWrite> {
+        let mut pixels = vec![PixelWrite::Untouched; SCREEN_WIDTH * SCREEN_HEIGHT];
+        for offset in (0..SCREEN_WIDTH * 32).filter(|&i| self.written[i]) {
+            for bit in 0..8 {
+                let mask = 1 << bit;
+                let write = match (self.drawn[offset] & mask, self.erased[offset] & mask) {
+                    (0, 0) => PixelWrite::Rewritten,
+                    (_, 0) => PixelWrite::Drawn,
+                    (0, _) => PixelWrite::Erased,
+                    _ => PixelWrite::Flickered,
+                };
+                let (x, y) = screen_position(offset, bit);
+                pixels[y * SCREEN_WIDTH + x] = write;
+            }
+        }
+        pixels
+    }
+
+    /// The pixels as `0xRRGGBB`: black if untouched, grey if rewritten
+    /// unchanged, green if drawn, red if erased and yellow if both.
+    pub fn to_rgb(&self) -> Vec<u32> {
+        self.pixels().into_iter().map(PixelWrite::rgb).collect()
+    }
+
+    /// The greatest write in each `width` by `height` block of pixels, row
+    /// by row from the top left, for views drawing a block as one cell.
+    pub fn cells(&self, width: usize, height: usize) -> Vec<PixelWrite> {
+        let columns = SCREEN_WIDTH.div_ceil(width);
+        let rows = SCREEN_HEIGHT.div_ceil(height);
+        let mut cells = vec![PixelWrite::Untouched; columns * rows];
+        for (i, &write) in self.pixels().iter().enumerate() {
+            let (x, y) = (i % SCREEN_WIDTH, i / SCREEN_WIDTH);
+            let cell = &mut cells[y / height * columns + x / width];
+            *cell = (*cell).max(write);
+        }
+        cells
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Counts, Heatmap, PixelWrite};
+    use crate::machine::{SpaceInvaders, SCREEN_HEIGHT, SCREEN_WIDTH};
+    use alloc::{string::String, vec, vec::Vec};
+
+    /// Draws into the bottom left corner of the screen, reads a byte of rom
+    /// and spins.
+    fn rom() -> Vec<u8> {
+        let mut rom = vec![
+            0x21, 0x00, 0x24, // LXI H, 0x2400
+            0x36, 0x0f, // MVI M, 0x0f
+            0x36, 0x03, // MVI M, 0x03
+            0x3a, 0x20, 0x00, // LDA 0x0020
+            0x32, 0x01, 0x24, // STA 0x2401
+            0xc3, 0x0d, 0x00, // JMP 0x000d
+        ];
+        rom.resize(0x21, 0);
+        rom
+    }
+
+    #[test]
+    fn counts_accesses() {
+        let mut machine = SpaceInvaders::new(rom());
+        machine.set_heatmap(Some(Heatmap::new()));
+        machine.run_frame().unwrap();
+        let heatmap = machine.heatmap().unwrap();
+        let once = Counts {
+            executes: 1,
+            ..Counts::default()
+        };
+        assert_eq!(heatmap.counts(0x0000), once);
+        assert_eq!(heatmap.counts(0x0001), once);
+        assert_eq!(heatmap.counts(0x0020).reads, 1);
+        assert_eq!(heatmap.counts(0x2400).writes, 2);
+        assert!(heatmap.counts(0x000d).executes > 1000);
+        assert!(heatmap.counts(0x0021).is_zero());
+
+        let mut csv = String::new();
+        heatmap.write_csv(&mut csv).unwrap();
+        assert!(csv.starts_with("address,reads,writes,executes\n0x0000,0,0,1\n"));
+        assert!(csv.contains("\n0x2400,0,2,0\n"));
+        let rgb = heatmap.to_rgb();
+        assert_eq!(rgb[0x2400], 0xff_00_00);
+        assert_eq!(rgb[0x0021], 0);
+    }
+
+    #[test]
+    fn shows_frame_writes() {
+        let mut machine = SpaceInvaders::new(rom());
+        let mut heatmap = Heatmap::new();
+        heatmap.set_per_frame(true);
+        machine.set_heatmap(Some(heatmap));
+        machine.run_frame().unwrap();
+
+        let writes = machine.heatmap().unwrap().vram_writes();
+        assert_eq!(writes.bytes_written(), 2);
+        let pixels = writes.pixels();
+        let pixel = |x: usize, y: usize| pixels[y * SCREEN_WIDTH + x];
+        assert_eq!(pixel(0, 255), PixelWrite::Drawn);
+        assert_eq!(pixel(0, 253), PixelWrite::Flickered);
+        assert_eq!(pixel(0, 251), PixelWrite::Rewritten);
+        assert_eq!(pixel(0, 247), PixelWrite::Rewritten);
+        assert_eq!(pixel(1, 255), PixelWrite::Untouched);
+
+        // The next frame only spins.
+        machine.run_frame().unwrap();
+        let heatmap = machine.heatmap().unwrap();
+        assert_eq!(heatmap.vram_writes().bytes_written(), 0);
+        assert!(heatmap.counts(0x0000).is_zero());
+        assert!(heatmap.counts(0x000d).executes > 1000);
+    }
+
+    #[test]
+    fn cells_keep_the_greatest_write() {
+        let mut machine = SpaceInvaders::new([
+            0x3e, 0x01, // MVI A, 0x01
+            0x32, 0x00, 0x24, // STA 0x2400
+            0xc3, 0x05, 0x00, // JMP 0x0005
+        ]);
+        machine.set_heatmap(Some(Heatmap::new()));
+        machine.run_frame().unwrap();
+
+        // The bottom left braille cell holds one drawn pixel, at (0, 255),
+        // and three rewritten above it.
+        let writes = machine.heatmap().unwrap().vram_writes();
+        let columns = SCREEN_WIDTH / 2;
+        let cells = writes.cells(2, 4);
+        assert_eq!(cells.len(), columns * SCREEN_HEIGHT / 4);
+        assert_eq!(cells[63 * columns], PixelWrite::Drawn);
+        assert_eq!(
+            cells[63 * columns].rgb(),
+            writes.to_rgb()[255 * SCREEN_WIDTH]
+        );
+        assert_eq!(cells[62 * columns], PixelWrite::Rewritten);
+        assert_eq!(cells[63 * columns + 1], PixelWrite::Untouched);
+    }
+}
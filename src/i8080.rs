@@ -1,7 +1,13 @@
 use crate::instruction::{Instruction, Opcode};
-use crate::interconnect::Interconnect;
+use crate::state::{StateReader, StateWriter};
+use crate::symbols::Symbols;
+use crate::Bus;
+use alloc::sync::Arc;
+use core::fmt::{self, Display};
 use log::info;
-use std::fmt::{self, Display};
+
+mod call_stack;
+pub use self::call_stack::{Anomaly, Backtrace, CallFrame, CallStack, FrameKind};
 
 mod flags;
 pub use self::flags::ConditionalFlags;
@@ -10,13 +16,51 @@ mod register;
 pub use self::register::Register;
 
 mod error;
-use self::error::EmulateError;
+pub use self::error::EmulateError;
+
+#[cfg(feature = "color")]
+mod color;
+#[cfg(feature = "color")]
+pub use self::color::Colored;
 
-type Result<T> = std::result::Result<T, EmulateError>;
+type Result<T> = core::result::Result<T, EmulateError>;
 
 // Instruction Implementations
 mod implementations;
 
+/// Registers tracked by `rc`, in index order.
+const TRACKED_REGISTERS: [Register; 8] = [
+    Register::A,
+    Register::B,
+    Register::C,
+    Register::D,
+    Register::E,
+    Register::H,
+    Register::L,
+    Register::SP,
+];
+
+/// The cpu apart from its symbols and call stack: what an instruction can
+/// change, copied without allocating.
+#[derive(Clone, Copy)]
+pub(crate) struct Registers {
+    a: u8,
+    b: u8,
+    c: u8,
+    d: u8,
+    e: u8,
+    h: u8,
+    l: u8,
+    sp: u16,
+    pc: u16,
+    flags: ConditionalFlags,
+    rc: [bool; 8],
+    interrupts_enabled: bool,
+    halted: bool,
+    cycles: u64,
+}
+
+#[derive(Clone)]
 pub struct I8080 {
     a: u8,
     b: u8,
@@ -30,6 +74,17 @@ pub struct I8080 {
     flags: ConditionalFlags,
     rc: [bool; 8],
     interrupts_enabled: bool,
+    halted: bool,
+    cycles: u64,
+    /// Names for the addresses in trace lines.
+    symbols: Option<Arc<Symbols>>,
+    call_stack: Option<CallStack>,
+}
+
+impl Default for I8080 {
+    fn default() -> Self {
+        I8080::new()
+    }
 }
 
 impl I8080 {
@@ -47,62 +102,289 @@ impl I8080 {
             flags: ConditionalFlags::new(),
             rc: [false; 8],
             interrupts_enabled: true,
+            halted: false,
+            cycles: 0,
+            symbols: None,
+            call_stack: None,
+        }
+    }
+
+    /// Returns the cpu to its power-on state at address 0 with interrupts
+    /// disabled, as the reset line does. Other registers are left untouched.
+    pub fn reset(&mut self) {
+        self.pc = 0;
+        self.interrupts_enabled = false;
+        self.halted = false;
+        self.reset_rc();
+        if let Some(stack) = &mut self.call_stack {
+            stack.abandon();
         }
     }
 
+    /// Fetches, decodes and executes the instruction at `pc`.
+    ///
+    /// A halted cpu executes nothing and only lets four cycles pass, waiting
+    /// for an interrupt.
+    pub fn step(&mut self, bus: &mut impl Bus) -> core::result::Result<(), crate::Error> {
+        if self.halted {
+            self.cycles += 4;
+            return Ok(());
+        }
+        let pc = self.pc;
+        let instruction = Instruction::read(bus, pc);
+        let mut bytes = [0; 3];
+        for (offset, byte) in bytes
+            .iter_mut()
+            .enumerate()
+            .take(instruction.len() as usize)
+        {
+            *byte = bus.read_byte(pc.wrapping_add(offset as u16));
+        }
+        self.emulate_instruction(instruction, bus)
+            .map_err(|source| crate::Error::Emulate {
+                pc,
+                bytes,
+                instruction,
+                source,
+            })
+    }
+
+    /// Requests an interrupt, as a device placing `RST vector` on the data bus
+    /// would.
+    ///
+    /// Returns false without doing anything if interrupts are disabled.
+    /// Otherwise the cpu leaves any halt, disables further interrupts and
+    /// calls `8 * vector`. Only the low three bits of `vector` are used, as
+    /// only they fit in the opcode.
+    pub fn interrupt(
+        &mut self,
+        vector: u8,
+        bus: &mut impl Bus,
+    ) -> core::result::Result<bool, crate::Error> {
+        if !self.interrupts_enabled {
+            return Ok(false);
+        }
+        let vector = vector & 0x07;
+        let pc = self.pc;
+        let opcode = Opcode::RST(vector);
+        self.halted = false;
+        self.interrupts_enabled = false;
+        self.reset_rc();
+        self.rst(vector, bus)
+            .map_err(|source| crate::Error::Emulate {
+                pc,
+                bytes: [0xc7 | vector << 3, 0, 0],
+                instruction: Instruction::new_unary(opcode).unwrap(),
+                source,
+            })?;
+        self.cycles += opcode.cycles() as u64;
+        if let Some(stack) = &mut self.call_stack {
+            let target = self.pc;
+            stack.enter(FrameKind::Interrupt(vector), pc, target, pc, self.sp);
+        }
+        Ok(true)
+    }
+
     pub fn emulate_instruction(
         &mut self,
         instruction: Instruction,
-        ic: &mut Interconnect,
+        bus: &mut impl Bus,
     ) -> Result<()> {
         let old_pc = self.pc;
-        self.pc += instruction.len();
+        let old_sp = self.sp;
+        self.pc = self.pc.wrapping_add(instruction.len());
         use self::Opcode::*;
         self.reset_rc();
         let r = match instruction.opcode() {
             NOP => Ok(()),
             // Data transfer Instructions
             LXI(r) => self.lxi(r, instruction.data()),
-            LDAX(r) => self.ldax(r, ic),
-            LDA => self.lda(instruction.data(), ic),
-            STA => self.sta(instruction.data(), ic),
-            MOV(d, s) => self.mov(d, s, ic),
-            MVI(r) => self.mvi(r, instruction.data(), ic),
+            LDAX(r) => self.ldax(r, bus),
+            STAX(r) => self.stax(r, bus),
+            LDA => self.lda(instruction.data(), bus),
+            STA => self.sta(instruction.data(), bus),
+            LHLD => self.lhld(instruction.data(), bus),
+            SHLD => self.shld(instruction.data(), bus),
+            MOV(d, s) => self.mov(d, s, bus),
+            MVI(r) => self.mvi(r, instruction.data(), bus),
             XCHG => self.xchg(),
-            PUSH(r) => self.push(r, ic),
-            POP(r) => self.pop(r, ic),
+            PUSH(r) => self.push(r, bus),
+            POP(r) => self.pop(r, bus),
+            // Stack Instructions
+            XTHL => self.xthl(bus),
+            SPHL => self.sphl(),
             // Arithmetic Instructions
             INX(r) => self.inx(r),
-            DCR(r) => self.dcr(r, ic),
-            ADD(r) => self.add(r, ic),
+            DCX(r) => self.dcx(r),
+            INR(r) => self.inr(r, bus),
+            DCR(r) => self.dcr(r, bus),
+            ADD(r) => self.add(r, bus),
+            ADC(r) => self.adc(r, bus),
             ADI => self.adi(instruction.data()),
+            ACI => self.aci(instruction.data()),
             DAD(r) => self.dad(r),
-            SUB(r) => self.sub(r, ic),
+            SUB(r) => self.sub(r, bus),
+            SBB(r) => self.sbb(r, bus),
             SUI => self.sui(instruction.data()),
+            SBI => self.sbi(instruction.data()),
+            DAA => self.daa(),
+            RLC => self.rlc(),
             RRC => self.rrc(),
+            RAL => self.ral(),
+            RAR => self.rar(),
             // Logical Instructions
+            CMP(r) => self.cmp(r, bus),
             CPI => self.cpi(instruction.data()),
+            ANA(r) => self.ana(r, bus),
             ANI => self.ani(instruction.data()),
-            ANA(r) => self.ana(r, ic),
-            XRA(r) => self.xra(r, ic),
+            XRA(r) => self.xra(r, bus),
+            XRI => self.xri(instruction.data()),
+            ORA(r) => self.ora(r, bus),
+            ORI => self.ori(instruction.data()),
+            CMA => self.cma(),
+            CMC => self.cmc(),
+            STC => self.stc(),
             // IO Instructions
-            OUT => self.out(instruction.data()),
+            IN => self.input(instruction.data(), bus),
+            OUT => self.out(instruction.data(), bus),
             // Branch Instructions
             JMP => self.jmp(instruction.data()),
-            JNZ => self.jnz(instruction.data()),
-            CALL => self.call(instruction.data(), ic),
-            RET => self.ret(ic),
+            JNZ | JZ | JNC | JC | JPO | JPE | JP | JM => {
+                self.jump_if(instruction.opcode(), instruction.data())
+            }
+            CALL => self.call(instruction.data(), bus),
+            CNZ | CZ | CNC | CC | CPO | CPE | CP | CM => {
+                self.call_if(instruction.opcode(), instruction.data(), bus)
+            }
+            RET => self.ret(bus),
+            RNZ | RZ | RNC | RC | RPO | RPE | RP | RM => self.ret_if(instruction.opcode(), bus),
+            RST(n) => self.rst(n, bus),
+            PCHL => self.pchl(),
             // Special Instructions
             EI => self.ei(),
+            DI => self.di(),
+            HLT => self.hlt(),
             _op => return Err(EmulateError::UnimplementedInstruction { instruction }),
         };
 
         if let Ok(()) = r {
-            info!("{}: {}; {}", old_pc, instruction, self);
+            self.cycles += instruction.opcode().cycles() as u64;
+            if let Some(stack) = &mut self.call_stack {
+                stack.executed(&instruction, old_pc, old_sp, self.pc, self.sp);
+            }
+            #[cfg(feature = "color")]
+            let registers = self.colored();
+            #[cfg(not(feature = "color"))]
+            let registers = &*self;
+            match &self.symbols {
+                Some(symbols) => info!(
+                    "{}: {}; {}",
+                    symbols.address(old_pc),
+                    symbols.instruction(&instruction),
+                    registers
+                ),
+                None => info!("{}: {}; {}", old_pc, instruction, registers),
+            }
         }
         r
     }
 
+    pub(crate) fn registers(&self) -> Registers {
+        Registers {
+            a: self.a,
+            b: self.b,
+            c: self.c,
+            d: self.d,
+            e: self.e,
+            h: self.h,
+            l: self.l,
+            sp: self.sp,
+            pc: self.pc,
+            flags: self.flags,
+            rc: self.rc,
+            interrupts_enabled: self.interrupts_enabled,
+            halted: self.halted,
+            cycles: self.cycles,
+        }
+    }
+
+    /// Puts back registers taken with `registers`. The call stack is left
+    /// alone.
+    pub(crate) fn set_registers(&mut self, registers: Registers) {
+        let Registers {
+            a,
+            b,
+            c,
+            d,
+            e,
+            h,
+            l,
+            sp,
+            pc,
+            flags,
+            rc,
+            interrupts_enabled,
+            halted,
+            cycles,
+        } = registers;
+        self.a = a;
+        self.b = b;
+        self.c = c;
+        self.d = d;
+        self.e = e;
+        self.h = h;
+        self.l = l;
+        self.sp = sp;
+        self.pc = pc;
+        self.flags = flags;
+        self.rc = rc;
+        self.interrupts_enabled = interrupts_enabled;
+        self.halted = halted;
+        self.cycles = cycles;
+    }
+
+    pub(crate) fn save_state(&self, w: &mut StateWriter) {
+        for &register in &[self.a, self.b, self.c, self.d, self.e, self.h, self.l] {
+            w.u8(register);
+        }
+        w.u16(self.sp);
+        w.u16(self.pc);
+        w.u8(u8::from(self.flags));
+        w.bool(self.interrupts_enabled);
+        w.bool(self.halted);
+        w.u64(self.cycles);
+    }
+
+    pub(crate) fn load_state(
+        &mut self,
+        r: &mut StateReader,
+    ) -> core::result::Result<(), crate::Error> {
+        for register in [
+            &mut self.a,
+            &mut self.b,
+            &mut self.c,
+            &mut self.d,
+            &mut self.e,
+            &mut self.h,
+            &mut self.l,
+        ]
+        .iter_mut()
+        {
+            **register = r.u8()?;
+        }
+        self.sp = r.u16()?;
+        self.pc = r.u16()?;
+        self.flags = ConditionalFlags::from(r.u8()?);
+        self.interrupts_enabled = r.bool()?;
+        self.halted = r.bool()?;
+        self.cycles = r.u64()?;
+        self.reset_rc();
+        if let Some(stack) = &mut self.call_stack {
+            stack.abandon();
+        }
+        Ok(())
+    }
+
     fn set_8bit_register(&mut self, register: Register, value: u8) {
         self.register_changed(register);
         match register {
@@ -130,7 +412,24 @@ impl I8080 {
             Register::E => Ok(self.e),
             Register::H => Ok(self.h),
             Register::L => Ok(self.l),
-            _r => return Err(EmulateError::RegisterNot8Bit { register }),
+            _r => Err(EmulateError::RegisterNot8Bit { register }),
+        }
+    }
+
+    /// Sets one of `A` to `L` from outside the cpu, as a debugger would.
+    pub fn set_register(&mut self, register: Register, value: u8) -> Result<()> {
+        self.get_8bit_register(register)?;
+        self.set_8bit_register(register, value);
+        Ok(())
+    }
+
+    /// Reads the operand of a register or memory instruction, the byte at
+    /// (HL) standing in for `M`.
+    fn operand(&self, opcode: Opcode, register: Register, bus: &impl Bus) -> Result<u8> {
+        match register {
+            Register::SP => Err(EmulateError::UnsupportedRegister { opcode, register }),
+            Register::M => Ok(bus.read_byte(self.m())),
+            _r => self.get_8bit_register(_r),
         }
     }
 
@@ -146,7 +445,7 @@ impl I8080 {
         self.set_8bit_register(Register::L, low);
     }
 
-    fn set_sp(&mut self, value: u16) {
+    pub fn set_sp(&mut self, value: u16) {
         self.register_changed(Register::SP);
         self.sp = value;
     }
@@ -159,42 +458,89 @@ impl I8080 {
         self.pc
     }
 
+    pub fn set_pc(&mut self, value: u16) {
+        self.pc = value;
+    }
+
     pub fn flags(&self) -> ConditionalFlags {
         self.flags
     }
 
+    pub fn set_flags(&mut self, flags: ConditionalFlags) {
+        self.flags = flags;
+    }
+
     pub fn interrupts_enabled(&self) -> bool {
         self.interrupts_enabled
     }
 
-    fn push_u16(&mut self, value: u16, interconnect: &mut Interconnect) -> Result<()> {
+    /// Names addresses in the trace lines logged for each instruction, as
+    /// `DrawSprite+0x12` rather than a bare number.
+    pub fn set_symbols(&mut self, symbols: Option<Arc<Symbols>>) {
+        self.symbols = symbols;
+    }
+
+    pub fn symbols(&self) -> Option<&Symbols> {
+        self.symbols.as_deref()
+    }
+
+    /// Starts or stops keeping a shadow call stack, for backtraces and to
+    /// catch returns that don't match their calls.
+    pub fn set_call_tracking(&mut self, enabled: bool) {
+        if !enabled {
+            self.call_stack = None;
+        } else if self.call_stack.is_none() {
+            self.call_stack = Some(CallStack::new());
+        }
+    }
+
+    /// The shadow call stack, if tracking is on.
+    pub fn call_stack(&self) -> Option<&CallStack> {
+        self.call_stack.as_ref()
+    }
+
+    pub fn call_stack_mut(&mut self) -> Option<&mut CallStack> {
+        self.call_stack.as_mut()
+    }
+
+    /// Whether the cpu has executed `HLT` and is waiting for an interrupt.
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Clock cycles elapsed since the cpu was created.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    fn push_u16(&mut self, value: u16, bus: &mut impl Bus) -> Result<()> {
         let (high, low) = split_bytes(value);
-        self.push_u8(high, interconnect)?;
-        self.push_u8(low, interconnect)?;
+        self.push_u8(high, bus)?;
+        self.push_u8(low, bus)?;
         Ok(())
     }
 
-    fn push_u8(&mut self, value: u8, interconnect: &mut Interconnect) -> Result<()> {
-        let loc = self.sp - 1;
+    fn push_u8(&mut self, value: u8, bus: &mut impl Bus) -> Result<()> {
+        let loc = self.sp.wrapping_sub(1);
         if loc < 0x2000 {
             return Err(EmulateError::StackOverflow);
         };
-        interconnect.write_byte(loc, value);
-        self.sp -= 1;
+        bus.write_byte(loc, value);
+        self.sp = loc;
         self.register_changed(Register::SP);
         Ok(())
     }
 
-    fn pop_u8(&mut self, interconnect: &Interconnect) -> Result<u8> {
-        let value = interconnect.read_byte(self.sp);
-        self.sp += 1;
+    fn pop_u8(&mut self, bus: &impl Bus) -> Result<u8> {
+        let value = bus.read_byte(self.sp);
+        self.sp = self.sp.wrapping_add(1);
         self.register_changed(Register::SP);
         Ok(value)
     }
 
-    fn pop_u16(&mut self, interconnect: &Interconnect) -> Result<u16> {
-        let low = self.pop_u8(interconnect)?;
-        let high = self.pop_u8(interconnect)?;
+    fn pop_u16(&mut self, bus: &impl Bus) -> Result<u16> {
+        let low = self.pop_u8(bus)?;
+        let high = self.pop_u8(bus)?;
         Ok(concat_bytes(high, low))
     }
 
@@ -215,6 +561,17 @@ impl I8080 {
         }
     }
 
+    /// Registers written by the most recently emulated instruction.
+    ///
+    /// Writes to `M` are reported as changes to both `H` and `L`.
+    pub fn changed_registers(&self) -> impl Iterator<Item = Register> + '_ {
+        TRACKED_REGISTERS
+            .iter()
+            .zip(self.rc.iter())
+            .filter(|(_, &changed)| changed)
+            .map(|(&register, _)| register)
+    }
+
     fn reset_rc(&mut self) {
         for i in self.rc.iter_mut() {
             *i = false;
@@ -241,58 +598,25 @@ trait TwosComplement<RHS = Self> {
 impl TwosComplement for u8 {
     type Output = (u8, bool);
     fn complement_sub(self, subtrahend: Self) -> Self::Output {
-        let complement = !subtrahend + 1;
-        let (value, carry) = self.overflowing_add(complement);
-        (value, !carry)
+        self.overflowing_sub(subtrahend)
     }
 }
 
 impl Display for I8080 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        use colored::*;
-        let a = match self.rc[0] {
-            true => format!("{:02x}", self.a).blue(),
-            false => format!("{:02x}", self.a).white(),
-        };
-        let b = match self.rc[1] {
-            true => format!("{:02x}", self.b).blue(),
-            false => format!("{:02x}", self.b).white(),
-        };
-        let c = match self.rc[2] {
-            true => format!("{:02x}", self.c).blue(),
-            false => format!("{:02x}", self.c).white(),
-        };
-        let d = match self.rc[3] {
-            true => format!("{:02x}", self.d).blue(),
-            false => format!("{:02x}", self.d).white(),
-        };
-        let e = match self.rc[4] {
-            true => format!("{:02x}", self.e).blue(),
-            false => format!("{:02x}", self.e).white(),
-        };
-        let h = match self.rc[5] {
-            true => format!("{:02x}", self.h).blue(),
-            false => format!("{:02x}", self.h).white(),
-        };
-        let l = match self.rc[6] {
-            true => format!("{:02x}", self.l).blue(),
-            false => format!("{:02x}", self.l).white(),
-        };
-        let s = match self.rc[7] {
-            true => format!("{:02x}", self.sp).blue(),
-            false => format!("{:02x}", self.sp).white(),
-        };
         write!(
             f,
-            "CPU: a={}|b={}|c={}|d={}|e={}|h={}|l={}|sp={}",
-            a, b, c, d, e, h, l, s,
+            "CPU: a={:02x}|b={:02x}|c={:02x}|d={:02x}|e={:02x}|h={:02x}|l={:02x}|sp={:02x}",
+            self.a, self.b, self.c, self.d, self.e, self.h, self.l, self.sp,
         )
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{concat_bytes, split_bytes};
+    use super::{concat_bytes, split_bytes, Register};
+    use crate::Emulator;
+    use alloc::{string::ToString, vec::Vec};
     #[test]
     fn can_split_bytes() {
         let (high, low) = split_bytes(0xea14);
@@ -306,4 +630,42 @@ mod tests {
         let high = 0xea;
         assert_eq!(concat_bytes(high, low), 0xea14);
     }
+
+    #[test]
+    fn changed_registers() {
+        let bytecode = [
+            0x21, 0x34, 0x12, // LXI H, 0x1234
+            0x31, 0x00, 0x24, // LXI SP, 0x2400
+            0x00, // NOP
+        ];
+        let mut system = Emulator::new(bytecode);
+        system.step();
+        let changed: Vec<Register> = system.cpu.changed_registers().collect();
+        assert_eq!(changed, [Register::H, Register::L]);
+        system.step();
+        let changed: Vec<Register> = system.cpu.changed_registers().collect();
+        assert_eq!(changed, [Register::SP]);
+        system.step();
+        assert_eq!(system.cpu.changed_registers().count(), 0);
+        assert_eq!(
+            system.cpu.to_string(),
+            "CPU: a=00|b=00|c=00|d=00|e=00|h=12|l=34|sp=2400"
+        );
+    }
+
+    #[test]
+    fn counts_cycles() {
+        let bytecode = [
+            0x00, // NOP
+            0x31, 0x00, 0x24, // LXI SP, 0x2400
+            0xcd, 0x08, 0x00, // CALL 0x0008
+            0x00, // NOP
+            0xc9, // RET
+        ];
+        let mut system = Emulator::new(bytecode);
+        for _ in 0..4 {
+            system.step();
+        }
+        assert_eq!(system.cpu.cycles(), 4 + 10 + 17 + 10);
+    }
 }
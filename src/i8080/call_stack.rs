@@ -0,0 +1,398 @@
+use crate::{
+    i8080::Register,
+    instruction::{Instruction, Opcode},
+    symbols::Symbols,
+};
+use alloc::{collections::VecDeque, vec::Vec};
+use core::fmt::{self, Display};
+
+/// Anomalies kept before the oldest are dropped.
+const MAX_ANOMALIES: usize = 256;
+
+/// How a frame was entered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameKind {
+    Call,
+    /// An `RST n` instruction in the program.
+    Restart(u8),
+    /// A device interrupt, `RST n` on the data bus.
+    Interrupt(u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CallFrame {
+    pub kind: FrameKind,
+    /// The calling instruction, or the instruction an interrupt came before.
+    pub call_site: u16,
+    /// Where the call went.
+    pub target: u16,
+    pub return_addr: u16,
+    /// Where the return address was pushed.
+    pub sp: u16,
+}
+
+/// Control flow that the shadow stack could not follow.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Anomaly {
+    /// A return went somewhere other than where the innermost call would
+    /// have returned to, or there was no call to return from.
+    MismatchedReturn {
+        pc: u16,
+        expected: Option<u16>,
+        found: u16,
+    },
+    /// `XTHL` swapped HL with a return address on top of the stack.
+    ReturnAddressExchanged { pc: u16, return_addr: u16 },
+    /// `SPHL` moved the stack pointer.
+    StackSwitched { pc: u16, from: u16, to: u16 },
+}
+
+impl Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Anomaly::MismatchedReturn {
+                pc,
+                expected: Some(expected),
+                found,
+            } => write!(
+                f,
+                "0x{:04x}: returned to 0x{:04x}, expected 0x{:04x}",
+                pc, found, expected
+            ),
+            Anomaly::MismatchedReturn {
+                pc,
+                expected: None,
+                found,
+            } => write!(f, "0x{:04x}: returned to 0x{:04x} with no call", pc, found),
+            Anomaly::ReturnAddressExchanged { pc, return_addr } => write!(
+                f,
+                "0x{:04x}: XTHL exchanged return address 0x{:04x}",
+                pc, return_addr
+            ),
+            Anomaly::StackSwitched { pc, from, to } => write!(
+                f,
+                "0x{:04x}: SPHL moved the stack from 0x{:04x} to 0x{:04x}",
+                pc, from, to
+            ),
+        }
+    }
+}
+
+/// A shadow of the return addresses on the 8080's stack, kept alongside the
+/// real one as calls, restarts, interrupts and returns execute.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CallStack {
+    /// Innermost last.
+    frames: Vec<CallFrame>,
+    anomalies: VecDeque<Anomaly>,
+}
+
+impl CallStack {
+    pub fn new() -> CallStack {
+        CallStack::default()
+    }
+
+    /// The active frames, innermost last.
+    pub fn frames(&self) -> &[CallFrame] {
+        &self.frames
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Anomalies seen since they were last taken, oldest first.
+    pub fn anomalies(&self) -> impl Iterator<Item = &Anomaly> {
+        self.anomalies.iter()
+    }
+
+    pub fn take_anomalies(&mut self) -> Vec<Anomaly> {
+        self.anomalies.drain(..).collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.anomalies.clear();
+    }
+
+    /// Displays the frames as a backtrace from `pc`, one per line, naming
+    /// addresses with `symbols` if given.
+    pub fn backtrace<'a>(&'a self, pc: u16, symbols: Option<&'a Symbols>) -> Backtrace<'a> {
+        Backtrace {
+            stack: self,
+            pc,
+            symbols,
+        }
+    }
+
+    /// Follows `instruction`, executed at `pc`, which moved the stack pointer
+    /// from `old_sp` and left the cpu at `new_pc` and `sp`.
+    pub(crate) fn executed(
+        &mut self,
+        instruction: &Instruction,
+        pc: u16,
+        old_sp: u16,
+        new_pc: u16,
+        sp: u16,
+    ) {
+        use self::Opcode::*;
+        match instruction.opcode() {
+            CALL | CNZ | CZ | CNC | CC | CPO | CPE | CP | CM if sp == old_sp.wrapping_sub(2) => {
+                self.enter(FrameKind::Call, pc, new_pc, pc.wrapping_add(3), sp)
+            }
+            RST(n) => self.enter(FrameKind::Restart(n), pc, new_pc, pc.wrapping_add(1), sp),
+            RET | RNZ | RZ | RNC | RC | RPO | RPE | RP | RM if sp == old_sp.wrapping_add(2) => {
+                self.leave(pc, old_sp, new_pc)
+            }
+            XTHL => {
+                if let Some(frame) = self.frames.iter().find(|f| f.sp == sp) {
+                    let return_addr = frame.return_addr;
+                    self.anomaly(Anomaly::ReturnAddressExchanged { pc, return_addr });
+                }
+            }
+            SPHL => self.anomaly(Anomaly::StackSwitched {
+                pc,
+                from: old_sp,
+                to: sp,
+            }),
+            // Loading a new stack pointer above frames abandons them, as
+            // programs do when they restart their main loop.
+            LXI(Register::SP) => self.frames.retain(|f| f.sp >= sp),
+            _ => {}
+        }
+    }
+
+    /// Drops every frame, after a reset or a loaded state leaves the real
+    /// stack unrelated to the shadow one.
+    pub(crate) fn abandon(&mut self) {
+        self.frames.clear();
+    }
+
+    pub(crate) fn enter(
+        &mut self,
+        kind: FrameKind,
+        call_site: u16,
+        target: u16,
+        return_addr: u16,
+        sp: u16,
+    ) {
+        self.frames.push(CallFrame {
+            kind,
+            call_site,
+            target,
+            return_addr,
+            sp,
+        });
+    }
+
+    /// A return at `pc` popped `found` from `sp`.
+    fn leave(&mut self, pc: u16, sp: u16, found: u16) {
+        let innermost = self.frames.last().map(|f| (f.sp, f.return_addr));
+        if innermost != Some((sp, found)) {
+            self.anomaly(Anomaly::MismatchedReturn {
+                pc,
+                expected: innermost.map(|(_, addr)| addr),
+                found,
+            });
+        }
+        // Frames whose return addresses were popped or skipped over are gone.
+        while matches!(self.frames.last(), Some(f) if f.sp <= sp) {
+            self.frames.pop();
+        }
+    }
+
+    fn anomaly(&mut self, anomaly: Anomaly) {
+        if self.anomalies.len() == MAX_ANOMALIES {
+            self.anomalies.pop_front();
+        }
+        self.anomalies.push_back(anomaly);
+    }
+}
+
+pub struct Backtrace<'a> {
+    stack: &'a CallStack,
+    pc: u16,
+    symbols: Option<&'a Symbols>,
+}
+
+impl<'a> Backtrace<'a> {
+    fn line(&self, f: &mut fmt::Formatter, depth: usize, addr: u16) -> fmt::Result {
+        write!(f, "#{:<2} 0x{:04x}", depth, addr)?;
+        if let Some(symbols) = self.symbols.filter(|s| s.lookup(addr).is_some()) {
+            write!(f, " {}", symbols.address(addr))?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Display for Backtrace<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.line(f, 0, self.pc)?;
+        for (i, frame) in self.stack.frames.iter().rev().enumerate() {
+            writeln!(f)?;
+            self.line(f, i + 1, frame.call_site)?;
+            match frame.kind {
+                FrameKind::Call => {}
+                FrameKind::Restart(n) => write!(f, " [RST {}]", n)?,
+                FrameKind::Interrupt(n) => write!(f, " [interrupt {}]", n)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Anomaly, CallFrame, FrameKind};
+    use crate::{symbols::Symbols, Emulator};
+    use alloc::string::ToString;
+
+    #[test]
+    fn follows_calls_and_interrupts() {
+        let bytecode = [
+            0x31, 0x00, 0x24, // 0x0000 LXI SP,0x2400
+            0xcd, 0x0a, 0x00, // 0x0003 CALL 0x000a
+            0x00, // 0x0006 NOP
+            0x00, 0x00, 0x00, // 0x0007
+            0xcd, 0x0e, 0x00, // 0x000a Outer: CALL 0x000e
+            0xc9, // 0x000d RET
+            0x00, // 0x000e Inner: NOP
+            0xc9, // 0x000f RET
+        ];
+        let mut emulator = Emulator::new(&bytecode[..]);
+        emulator.cpu_mut().set_call_tracking(true);
+        for _ in 0..3 {
+            emulator.step();
+        }
+        let stack = emulator.cpu().call_stack().unwrap();
+        assert_eq!(
+            stack.frames(),
+            [
+                CallFrame {
+                    kind: FrameKind::Call,
+                    call_site: 0x0003,
+                    target: 0x000a,
+                    return_addr: 0x0006,
+                    sp: 0x23fe,
+                },
+                CallFrame {
+                    kind: FrameKind::Call,
+                    call_site: 0x000a,
+                    target: 0x000e,
+                    return_addr: 0x000d,
+                    sp: 0x23fc,
+                },
+            ]
+        );
+
+        let symbols = Symbols::parse("Main = $0000\nOuter = $000a\nInner = $000e").unwrap();
+        emulator.step();
+        let backtrace = emulator
+            .cpu()
+            .call_stack()
+            .unwrap()
+            .backtrace(0x000f, Some(&symbols));
+        assert_eq!(
+            backtrace.to_string(),
+            "#0  0x000f Inner+0x1\n#1  0x000a Outer\n#2  0x0003 Main+0x3"
+        );
+
+        // An interrupt before the RET, whose handler is that same RET.
+        let mut memory = [0; 2];
+        emulator
+            .cpu_mut()
+            .interrupt(2, &mut StackBus(&mut memory))
+            .unwrap();
+        let frames = emulator.cpu().call_stack().unwrap().frames();
+        assert_eq!(frames[2].kind, FrameKind::Interrupt(2));
+        assert_eq!(frames[2].return_addr, 0x000f);
+        emulator.interconnect_mut().write_byte(0x23fa, memory[0]);
+        emulator.interconnect_mut().write_byte(0x23fb, memory[1]);
+        emulator.cpu_mut().set_pc(0x000f);
+        emulator.step();
+        emulator.step();
+        emulator.step();
+        let stack = emulator.cpu_mut().call_stack_mut().unwrap();
+        assert_eq!(stack.depth(), 0);
+        assert!(stack.take_anomalies().is_empty());
+    }
+
+    /// Catches the return address an interrupt pushes to 0x23fa.
+    struct StackBus<'a>(&'a mut [u8; 2]);
+
+    impl<'a> crate::Bus for StackBus<'a> {
+        fn read_byte(&self, addr: u16) -> u8 {
+            self.0[(addr - 0x23fa) as usize]
+        }
+
+        fn write_byte(&mut self, addr: u16, value: u8) {
+            self.0[(addr - 0x23fa) as usize] = value;
+        }
+    }
+
+    #[test]
+    fn reports_anomalies() {
+        let bytecode = [
+            0x31, 0x00, 0x24, // 0x0000 LXI SP,0x2400
+            0xcd, 0x0b, 0x00, // 0x0003 CALL 0x000b
+            0x21, 0x00, 0x23, // 0x0006 LXI H,0x2300
+            0xf9, // 0x0009 SPHL
+            0x76, // 0x000a HLT
+            0xe3, // 0x000b XTHL
+            0xe3, // 0x000c XTHL
+            0xe1, // 0x000d POP H
+            0xe9, // 0x000e PCHL, back to 0x0006 without returning
+        ];
+        let mut emulator = Emulator::new(&bytecode[..]);
+        emulator.cpu_mut().set_call_tracking(true);
+        emulator.run();
+        let stack = emulator.cpu_mut().call_stack_mut().unwrap();
+        assert_eq!(
+            stack.take_anomalies(),
+            [
+                Anomaly::ReturnAddressExchanged {
+                    pc: 0x000b,
+                    return_addr: 0x0006,
+                },
+                Anomaly::ReturnAddressExchanged {
+                    pc: 0x000c,
+                    return_addr: 0x0006,
+                },
+                Anomaly::StackSwitched {
+                    pc: 0x0009,
+                    from: 0x2400,
+                    to: 0x2300,
+                },
+            ]
+        );
+        // The popped return address leaves a stale frame behind.
+        assert_eq!(stack.depth(), 1);
+    }
+
+    #[test]
+    fn reports_mismatched_returns() {
+        let bytecode = [
+            0x31, 0x00, 0x24, // 0x0000 LXI SP,0x2400
+            0xcd, 0x07, 0x00, // 0x0003 CALL 0x0007
+            0x76, // 0x0006 HLT
+            0x21, 0x0d, 0x00, // 0x0007 LXI H,0x000d
+            0xe5, // 0x000a PUSH H
+            0xc9, // 0x000b RET, to 0x000d rather than 0x0006
+            0x00, // 0x000c NOP
+            0xc9, // 0x000d RET, the real return
+        ];
+        let mut emulator = Emulator::new(&bytecode[..]);
+        emulator.cpu_mut().set_call_tracking(true);
+        emulator.run();
+        let stack = emulator.cpu_mut().call_stack_mut().unwrap();
+        assert_eq!(
+            stack.take_anomalies(),
+            [Anomaly::MismatchedReturn {
+                pc: 0x000b,
+                expected: Some(0x0006),
+                found: 0x000d,
+            }]
+        );
+        assert_eq!(stack.depth(), 0);
+    }
+}
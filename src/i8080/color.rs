@@ -0,0 +1,42 @@
+use super::{Register, I8080, TRACKED_REGISTERS};
+use colored::*;
+use std::fmt::{self, Display};
+
+/// Displays the cpu registers, highlighting those changed by the last instruction.
+pub struct Colored<'a> {
+    cpu: &'a I8080,
+}
+
+impl I8080 {
+    pub fn colored(&self) -> Colored<'_> {
+        Colored { cpu: self }
+    }
+}
+
+impl<'a> Display for Colored<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CPU: ")?;
+        for (i, (register, changed)) in TRACKED_REGISTERS.iter().zip(self.cpu.rc.iter()).enumerate()
+        {
+            let (name, value) = match register {
+                Register::A => ("a", self.cpu.a as u16),
+                Register::B => ("b", self.cpu.b as u16),
+                Register::C => ("c", self.cpu.c as u16),
+                Register::D => ("d", self.cpu.d as u16),
+                Register::E => ("e", self.cpu.e as u16),
+                Register::H => ("h", self.cpu.h as u16),
+                Register::L => ("l", self.cpu.l as u16),
+                _ => ("sp", self.cpu.sp),
+            };
+            let value = match changed {
+                true => format!("{:02x}", value).blue(),
+                false => format!("{:02x}", value).white(),
+            };
+            if i > 0 {
+                write!(f, "|")?;
+            }
+            write!(f, "{}={}", name, value)?;
+        }
+        Ok(())
+    }
+}
@@ -2,31 +2,48 @@ use crate::{
     i8080::Register,
     instruction::{Instruction, InstructionData, Opcode},
 };
-use failure::Fail;
+use core::fmt::{self, Display};
 
-#[derive(Debug, Fail)]
+#[derive(Debug)]
 pub enum EmulateError {
-    #[fail(
-        display = "{:?} is unsupported for Opcode {}",
-        register,
-        opcode
-    )]
-    UnsupportedRegister { opcode: Opcode, register: Register },
-    #[fail(
-        display = "bad instruction data: {} for opcode: {}",
-        data,
-        opcode
-    )]
+    UnsupportedRegister {
+        opcode: Opcode,
+        register: Register,
+    },
     InvalidInstructionData {
         data: InstructionData,
         opcode: Opcode,
     },
-    #[fail(display = "Instruciton not yet implemented: {}", instruction)]
-    UnimplementedInstruction { instruction: Instruction },
-    #[fail(display = "{:?} is not an 8 bit register", register)]
-    RegisterNot8Bit { register: Register },
-    #[fail(display = "Stack Overflow")]
+    UnimplementedInstruction {
+        instruction: Instruction,
+    },
+    RegisterNot8Bit {
+        register: Register,
+    },
     StackOverflow,
-    #[fail(display = "Tring to write to ROM")]
     WriteToROM,
 }
+
+impl Display for EmulateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EmulateError::UnsupportedRegister { opcode, register } => {
+                write!(f, "{:?} is unsupported for Opcode {}", register, opcode)
+            }
+            EmulateError::InvalidInstructionData { data, opcode } => {
+                write!(f, "bad instruction data: {} for opcode: {}", data, opcode)
+            }
+            EmulateError::UnimplementedInstruction { instruction } => {
+                write!(f, "Instruction not yet implemented: {}", instruction)
+            }
+            EmulateError::RegisterNot8Bit { register } => {
+                write!(f, "{:?} is not an 8 bit register", register)
+            }
+            EmulateError::StackOverflow => write!(f, "Stack Overflow"),
+            EmulateError::WriteToROM => write!(f, "Trying to write to ROM"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for EmulateError {}
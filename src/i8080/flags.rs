@@ -7,6 +7,12 @@ pub struct ConditionalFlags {
     pub(crate) ac: bool,
 }
 
+impl Default for ConditionalFlags {
+    fn default() -> Self {
+        ConditionalFlags::new()
+    }
+}
+
 impl ConditionalFlags {
     pub fn new() -> ConditionalFlags {
         ConditionalFlags {
@@ -1,92 +1,100 @@
 use crate::i8080::*;
 use crate::instruction::{InstructionData, Opcode};
-use crate::interconnect::Interconnect;
+use crate::Bus;
 
 impl I8080 {
     pub(crate) fn inx(&mut self, register: Register) -> Result<()> {
+        self.step_pair(Opcode::INX(register), register, 1)
+    }
+
+    pub(crate) fn dcx(&mut self, register: Register) -> Result<()> {
+        self.step_pair(Opcode::DCX(register), register, 0xffff)
+    }
+
+    /// Adds `delta` to a register pair with wraparound, for INX and DCX.
+    fn step_pair(&mut self, opcode: Opcode, register: Register, delta: u16) -> Result<()> {
         if let Some(r2) = register.get_pair() {
             let low = self.get_8bit_register(r2).unwrap();
             let high = self.get_8bit_register(register).unwrap();
-            let mut value = concat_bytes(high, low);
-            value += 1;
+            let value = concat_bytes(high, low).wrapping_add(delta);
             let (high, low) = split_bytes(value);
             self.set_8bit_register(r2, low);
             self.set_8bit_register(register, high);
         } else if register == Register::SP {
-            self.sp += 1;
+            self.set_sp(self.sp.wrapping_add(delta));
         } else {
-            return Err(EmulateError::UnsupportedRegister {
-                opcode: Opcode::INX(register),
-                register,
-            });
+            return Err(EmulateError::UnsupportedRegister { opcode, register });
         }
         Ok(())
     }
 
-    pub(crate) fn dcr(
-        &mut self,
-        register: Register,
-        interconnect: &mut Interconnect,
-    ) -> Result<()> {
-        let value = match register {
-            Register::SP => {
-                return Err(EmulateError::UnsupportedRegister {
-                    opcode: Opcode::DCR(register),
-                    register,
-                })
-            }
-            Register::M => {
-                let (v, _c) = interconnect.read_byte(self.m()).complement_sub(1);
-                interconnect.write_byte(self.m(), v);
-                v
-            }
-            _r => {
-                let (v, _c) = self.get_8bit_register(_r).unwrap().complement_sub(1);
-                self.set_8bit_register(_r, v);
-                v
-            }
-        };
+    /// #INR - Increment Register or Memory
+    ///
+    /// Flags affected: Z, S, P, AC. Carry is left alone.
+    pub(crate) fn inr(&mut self, register: Register, bus: &mut impl Bus) -> Result<()> {
+        let value = self
+            .operand(Opcode::INR(register), register, bus)?
+            .wrapping_add(1);
+        self.write_operand(register, value, bus);
         self.flags.set_non_carry_flags(value);
+        self.flags.ac = value & 0x0f == 0;
         Ok(())
     }
 
-    pub(crate) fn add(&mut self, register: Register, interconnect: &Interconnect) -> Result<()> {
-        let (result, cy) = match register {
-            Register::SP => {
-                return Err(EmulateError::UnsupportedRegister {
-                    opcode: Opcode::ADD(register),
-                    register,
-                })
-            }
-            Register::M => self
-                .get_8bit_register(Register::A)?
-                .overflowing_add(interconnect.read_byte(self.m())),
-            _r => self
-                .get_8bit_register(Register::A)?
-                .overflowing_add(self.get_8bit_register(_r)?),
-        };
+    /// #DCR - Decrement Register or Memory
+    ///
+    /// Flags affected: Z, S, P, AC. Carry is left alone.
+    pub(crate) fn dcr(&mut self, register: Register, bus: &mut impl Bus) -> Result<()> {
+        let value = self
+            .operand(Opcode::DCR(register), register, bus)?
+            .wrapping_sub(1);
+        self.write_operand(register, value, bus);
+        self.flags.set_non_carry_flags(value);
+        self.flags.ac = value & 0x0f != 0x0f;
+        Ok(())
+    }
 
-        self.flags.set_non_carry_flags(result);
-        self.flags.cy = cy;
-        self.set_8bit_register(Register::A, result);
+    fn write_operand(&mut self, register: Register, value: u8, bus: &mut impl Bus) {
+        match register {
+            Register::M => bus.write_byte(self.m(), value),
+            _r => self.set_8bit_register(_r, value),
+        }
+    }
+
+    pub(crate) fn add(&mut self, register: Register, bus: &impl Bus) -> Result<()> {
+        let value = self.operand(Opcode::ADD(register), register, bus)?;
+        self.add_to_a(value, false);
+        Ok(())
+    }
+
+    pub(crate) fn adc(&mut self, register: Register, bus: &impl Bus) -> Result<()> {
+        let value = self.operand(Opcode::ADC(register), register, bus)?;
+        self.add_to_a(value, self.flags.cy);
         Ok(())
     }
 
     pub(crate) fn adi(&mut self, data: InstructionData) -> Result<()> {
-        if let Some(value) = data.first() {
-            let (result, cy) = self.get_8bit_register(Register::A)?.overflowing_add(value);
-            self.flags.set_non_carry_flags(result);
-            self.flags.cy = cy;
-            self.set_8bit_register(Register::A, result);
-        } else {
-            return Err(EmulateError::InvalidInstructionData {
-                opcode: Opcode::ADI,
-                data,
-            });
-        }
+        let value = immediate(Opcode::ADI, data)?;
+        self.add_to_a(value, false);
+        Ok(())
+    }
+
+    pub(crate) fn aci(&mut self, data: InstructionData) -> Result<()> {
+        let value = immediate(Opcode::ACI, data)?;
+        self.add_to_a(value, self.flags.cy);
         Ok(())
     }
 
+    /// Adds `value` and `carry` into the accumulator, setting every flag.
+    fn add_to_a(&mut self, value: u8, carry: bool) {
+        let sum = self.a as u16 + value as u16 + carry as u16;
+        let result = sum as u8;
+        self.flags.set_non_carry_flags(result);
+        self.flags.cy = sum > 0xff;
+        self.flags.ac = (self.a & 0x0f) + (value & 0x0f) + carry as u8 > 0x0f;
+        self.set_8bit_register(Register::A, result);
+    }
+
     pub(crate) fn dad(&mut self, reg: Register) -> Result<()> {
         let addend1 = self.m();
         let addend2 = match (reg, reg.get_pair()) {
@@ -107,40 +115,70 @@ impl I8080 {
         Ok(())
     }
 
-    pub(crate) fn sub(&mut self, register: Register, interconnect: &Interconnect) -> Result<()> {
-        let (result, cy) = match register {
-            Register::SP => {
-                return Err(EmulateError::UnsupportedRegister {
-                    opcode: Opcode::SUB(register),
-                    register,
-                })
-            }
-            Register::M => self
-                .get_8bit_register(Register::A)?
-                .complement_sub(interconnect.read_byte(self.m())),
-            _r => self
-                .get_8bit_register(Register::A)?
-                .complement_sub(self.get_8bit_register(_r)?),
-        };
+    pub(crate) fn sub(&mut self, register: Register, bus: &impl Bus) -> Result<()> {
+        let value = self.operand(Opcode::SUB(register), register, bus)?;
+        let result = self.subtract(value, false);
+        self.set_8bit_register(Register::A, result);
+        Ok(())
+    }
 
-        self.flags.set_non_carry_flags(result);
-        self.flags.cy = cy;
+    pub(crate) fn sbb(&mut self, register: Register, bus: &impl Bus) -> Result<()> {
+        let value = self.operand(Opcode::SBB(register), register, bus)?;
+        let result = self.subtract(value, self.flags.cy);
         self.set_8bit_register(Register::A, result);
         Ok(())
     }
 
     pub(crate) fn sui(&mut self, data: InstructionData) -> Result<()> {
-        if let Some(value) = data.first() {
-            let (result, cy) = self.get_8bit_register(Register::A)?.complement_sub(value);
-            self.flags.set_non_carry_flags(result);
-            self.flags.cy = cy;
-            self.set_8bit_register(Register::A, result);
-        } else {
-            return Err(EmulateError::InvalidInstructionData {
-                opcode: Opcode::SUI,
-                data,
-            });
+        let value = immediate(Opcode::SUI, data)?;
+        let result = self.subtract(value, false);
+        self.set_8bit_register(Register::A, result);
+        Ok(())
+    }
+
+    pub(crate) fn sbi(&mut self, data: InstructionData) -> Result<()> {
+        let value = immediate(Opcode::SBI, data)?;
+        let result = self.subtract(value, self.flags.cy);
+        self.set_8bit_register(Register::A, result);
+        Ok(())
+    }
+
+    /// Subtracts `value` and `borrow` from the accumulator, setting every flag
+    /// but leaving the accumulator alone.
+    ///
+    /// The 8080 subtracts by adding the two's complement, so auxiliary carry
+    /// is the carry out of the low nibble of that addition.
+    pub(crate) fn subtract(&mut self, value: u8, borrow: bool) -> u8 {
+        let (partial, borrow1) = self.a.complement_sub(value);
+        let (result, borrow2) = partial.complement_sub(borrow as u8);
+        self.flags.set_non_carry_flags(result);
+        self.flags.cy = borrow1 || borrow2;
+        self.flags.ac = (self.a & 0x0f) + (!value & 0x0f) + !borrow as u8 > 0x0f;
+        result
+    }
+
+    /// #DAA - Decimal Adjust Accumulator
+    ///
+    /// Turns the binary sum of two BCD numbers into a BCD result, adding 6 to
+    /// each nibble that overflowed a decimal digit.
+    pub(crate) fn daa(&mut self) -> Result<()> {
+        let mut correction = 0;
+        let mut cy = self.flags.cy;
+        if self.flags.ac || self.a & 0x0f > 9 {
+            correction |= 0x06;
         }
+        if self.flags.cy || self.a > 0x99 {
+            correction |= 0x60;
+            cy = true;
+        }
+        self.add_to_a(correction, false);
+        self.flags.cy = cy;
+        Ok(())
+    }
+
+    pub(crate) fn rlc(&mut self) -> Result<()> {
+        self.set_8bit_register(Register::A, self.a.rotate_left(1));
+        self.flags.cy = self.a & 0x01 != 0;
         Ok(())
     }
 
@@ -149,13 +187,34 @@ impl I8080 {
         self.flags.cy = self.a & 0x80 != 0;
         Ok(())
     }
+
+    /// #RAL - Rotate Accumulator Left Through Carry
+    pub(crate) fn ral(&mut self) -> Result<()> {
+        let cy = self.a & 0x80 != 0;
+        self.set_8bit_register(Register::A, self.a << 1 | self.flags.cy as u8);
+        self.flags.cy = cy;
+        Ok(())
+    }
+
+    /// #RAR - Rotate Accumulator Right Through Carry
+    pub(crate) fn rar(&mut self) -> Result<()> {
+        let cy = self.a & 0x01 != 0;
+        self.set_8bit_register(Register::A, self.a >> 1 | (self.flags.cy as u8) << 7);
+        self.flags.cy = cy;
+        Ok(())
+    }
+}
+
+/// The data byte of an immediate instruction.
+pub(crate) fn immediate(opcode: Opcode, data: InstructionData) -> Result<u8> {
+    data.first()
+        .ok_or(EmulateError::InvalidInstructionData { opcode, data })
 }
 
 #[cfg(test)]
 mod tests {
     use crate::i8080::*;
     use crate::Emulator;
-    use std::u8;
 
     #[test]
     fn overflow_sub() {
@@ -280,4 +339,117 @@ mod tests {
         assert_eq!(system.cpu.a, 0x88);
         assert_eq!(system.cpu.flags.cy, true);
     }
+
+    #[test]
+    fn inr_dcr() {
+        let bytecode = [
+            0x3c, // INR A
+            0x35, // DCR M
+            0x05, // DCR B
+        ];
+        let mut system = Emulator::new(bytecode);
+        system.cpu.a = 0x0f;
+        system.cpu.h = 0x20;
+        system.cpu.flags.cy = true;
+        system.interconnect.write_byte(0x2000, 0x01);
+        system.step();
+        assert_eq!(system.cpu.a, 0x10);
+        assert!(system.cpu.flags.ac);
+        assert!(system.cpu.flags.cy);
+        system.step();
+        assert_eq!(system.interconnect.read_byte(0x2000), 0x00);
+        assert!(system.cpu.flags.z);
+        assert!(system.cpu.flags.ac);
+        system.step();
+        assert_eq!(system.cpu.b, 0xff);
+        assert!(system.cpu.flags.s);
+        assert!(!system.cpu.flags.ac);
+        assert!(system.cpu.flags.cy);
+    }
+
+    #[test]
+    fn dcx_wraps() {
+        let bytecode = [
+            0x0b, // DCX B
+            0x3b, // DCX SP
+            0x33, // INX SP
+        ];
+        let mut system = Emulator::new(bytecode);
+        system.run();
+        assert_eq!((system.cpu.b, system.cpu.c), (0xff, 0xff));
+        assert_eq!(system.cpu.sp, 0x0000);
+    }
+
+    #[test]
+    fn adc_sbb() {
+        let bytecode = [
+            0x89, // ADC C
+            0xce, 0x00, // ACI 0x00
+            0x99, // SBB C
+            0xde, 0x01, // SBI 0x01
+        ];
+        let mut system = Emulator::new(bytecode);
+        system.cpu.a = 0x42;
+        system.cpu.c = 0x3d;
+        system.cpu.flags.cy = true;
+        system.step();
+        assert_eq!(system.cpu.a, 0x80);
+        assert!(!system.cpu.flags.cy);
+        assert!(system.cpu.flags.ac);
+        system.step();
+        assert_eq!(system.cpu.a, 0x80);
+        system.cpu.flags.cy = true;
+        system.step();
+        assert_eq!(system.cpu.a, 0x42);
+        assert!(!system.cpu.flags.cy);
+        system.cpu.a = 0x00;
+        system.step();
+        assert_eq!(system.cpu.a, 0xff);
+        assert!(system.cpu.flags.cy);
+    }
+
+    #[test]
+    fn daa() {
+        let bytecode = [
+            0xc6, 0x19, // ADI 0x19
+            0x27, // DAA
+        ];
+        let mut system = Emulator::new(bytecode);
+        system.cpu.a = 0x28;
+        system.run();
+        assert_eq!(system.cpu.a, 0x47);
+        assert!(!system.cpu.flags.cy);
+
+        let bytecode = [0x27]; // DAA
+        let mut system = Emulator::new(bytecode);
+        system.cpu.a = 0x9b;
+        system.run();
+        assert_eq!(system.cpu.a, 0x01);
+        assert!(system.cpu.flags.cy);
+        assert!(system.cpu.flags.ac);
+    }
+
+    #[test]
+    fn rotates() {
+        let bytecode = [
+            0x07, // RLC
+            0x17, // RAL
+            0x1f, // RAR
+            0x1f, // RAR
+        ];
+        let mut system = Emulator::new(bytecode);
+        system.cpu.a = 0xb5;
+        system.step();
+        assert_eq!(system.cpu.a, 0x6b);
+        assert!(system.cpu.flags.cy);
+        system.step();
+        assert_eq!(system.cpu.a, 0xd7);
+        assert!(!system.cpu.flags.cy);
+        system.step();
+        assert_eq!(system.cpu.a, 0x6b);
+        assert!(system.cpu.flags.cy);
+        system.step();
+        assert_eq!(system.cpu.a, 0xb5);
+        assert!(system.cpu.flags.cy);
+    }
 }
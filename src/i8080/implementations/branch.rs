@@ -1,6 +1,9 @@
 use crate::i8080::{concat_bytes, error::EmulateError, Result, I8080};
 use crate::instruction::{InstructionData, Opcode};
-use crate::interconnect::Interconnect;
+use crate::Bus;
+
+/// Extra cycles taken by a conditional call or return whose condition holds.
+const TAKEN_CYCLES: u64 = 6;
 
 impl I8080 {
     pub(crate) fn jmp(&mut self, data: InstructionData) -> Result<()> {
@@ -16,21 +19,40 @@ impl I8080 {
         Ok(())
     }
 
-    pub(crate) fn jnz(&mut self, data: InstructionData) -> Result<()> {
-        if !self.flags.z {
+    /// Whether the condition of a conditional jump, call or return holds.
+    fn condition(&self, opcode: Opcode) -> bool {
+        use crate::instruction::Opcode::*;
+        match opcode {
+            JNZ | CNZ | RNZ => !self.flags.z,
+            JZ | CZ | RZ => self.flags.z,
+            JNC | CNC | RNC => !self.flags.cy,
+            JC | CC | RC => self.flags.cy,
+            JPO | CPO | RPO => !self.flags.p,
+            JPE | CPE | RPE => self.flags.p,
+            JP | CP | RP => !self.flags.s,
+            JM | CM | RM => self.flags.s,
+            _ => true,
+        }
+    }
+
+    /// #Jcc - Conditional Jump
+    ///
+    /// Opcodes: 0xc2 JNZ, 0xca JZ, 0xd2 JNC, 0xda JC,
+    ///          0xe2 JPO, 0xea JPE, 0xf2 JP, 0xfa JM
+    pub(crate) fn jump_if(&mut self, opcode: Opcode, data: InstructionData) -> Result<()> {
+        if data.addr().is_none() {
+            return Err(EmulateError::InvalidInstructionData { opcode, data });
+        }
+        if self.condition(opcode) {
             self.jmp(data)?;
         }
         Ok(())
     }
 
-    pub(crate) fn call(
-        &mut self,
-        data: InstructionData,
-        interconnect: &mut Interconnect,
-    ) -> Result<()> {
+    pub(crate) fn call(&mut self, data: InstructionData, bus: &mut impl Bus) -> Result<()> {
         if let (Some(hi), Some(lo)) = data.tuple() {
             let addr = concat_bytes(hi, lo);
-            self.push_u16(self.pc, interconnect)?;
+            self.push_u16(self.pc, bus)?;
             self.pc = addr;
         } else {
             return Err(EmulateError::InvalidInstructionData {
@@ -41,9 +63,136 @@ impl I8080 {
         Ok(())
     }
 
-    pub(crate) fn ret(&mut self, interconnect: &mut Interconnect) -> Result<()> {
-        let addr = self.pop_u16(interconnect)?;
+    /// #Ccc - Conditional Call
+    ///
+    /// Opcodes: 0xc4 CNZ, 0xcc CZ, 0xd4 CNC, 0xdc CC,
+    ///          0xe4 CPO, 0xec CPE, 0xf4 CP, 0xfc CM
+    pub(crate) fn call_if(
+        &mut self,
+        opcode: Opcode,
+        data: InstructionData,
+        bus: &mut impl Bus,
+    ) -> Result<()> {
+        if data.addr().is_none() {
+            return Err(EmulateError::InvalidInstructionData { opcode, data });
+        }
+        if self.condition(opcode) {
+            self.call(data, bus)?;
+            self.cycles += TAKEN_CYCLES;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn ret(&mut self, bus: &mut impl Bus) -> Result<()> {
+        let addr = self.pop_u16(bus)?;
         self.pc = addr;
         Ok(())
     }
+
+    /// #Rcc - Conditional Return
+    ///
+    /// Opcodes: 0xc0 RNZ, 0xc8 RZ, 0xd0 RNC, 0xd8 RC,
+    ///          0xe0 RPO, 0xe8 RPE, 0xf0 RP, 0xf8 RM
+    pub(crate) fn ret_if(&mut self, opcode: Opcode, bus: &mut impl Bus) -> Result<()> {
+        if self.condition(opcode) {
+            self.ret(bus)?;
+            self.cycles += TAKEN_CYCLES;
+        }
+        Ok(())
+    }
+
+    /// #RST - Restart
+    ///
+    /// Opcodes: 0xc7, 0xcf, 0xd7, 0xdf, 0xe7, 0xef, 0xf7, 0xff
+    ///
+    /// Calls the subroutine at `8 * n`. Interrupting devices place this
+    /// instruction on the data bus.
+    pub(crate) fn rst(&mut self, n: u8, bus: &mut impl Bus) -> Result<()> {
+        self.push_u16(self.pc, bus)?;
+        self.pc = (n as u16 & 0x07) << 3;
+        Ok(())
+    }
+
+    /// #PCHL - Jump to the address in HL
+    pub(crate) fn pchl(&mut self) -> Result<()> {
+        self.pc = self.m();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Emulator;
+
+    #[test]
+    fn conditional_jump() {
+        let bytecode = [
+            0xca, 0x06, 0x00, // JZ 0x0006
+            0xc3, 0x00, 0x00, // JMP 0x0000
+            0xd2, 0x0a, 0x00, // JNC 0x000a
+            0x00, // NOP
+        ];
+        let mut system = Emulator::new(bytecode);
+        system.cpu.flags.z = true;
+        system.cpu.flags.cy = true;
+        system.step();
+        assert_eq!(system.cpu.pc, 0x0006);
+        system.step();
+        assert_eq!(system.cpu.pc, 0x0009);
+    }
+
+    #[test]
+    fn conditional_call_and_return() {
+        let bytecode = [
+            0x31, 0x00, 0x24, // LXI SP, 0x2400
+            0xfc, 0x0c, 0x00, // CM 0x000c
+            0xf4, 0x0c, 0x00, // CP 0x000c
+            0x00, // NOP
+            0x00, // NOP
+            0x00, // NOP
+            0xe8, // RPE
+            0xe0, // RPO
+        ];
+        let mut system = Emulator::new(bytecode);
+        system.step();
+        let cycles = system.cpu.cycles();
+        system.step();
+        assert_eq!(system.cpu.pc, 0x0006);
+        assert_eq!(system.cpu.cycles() - cycles, 11);
+        system.step();
+        assert_eq!(system.cpu.pc, 0x000c);
+        assert_eq!(system.cpu.cycles() - cycles, 11 + 17);
+        system.step();
+        assert_eq!(system.cpu.pc, 0x000d);
+        system.step();
+        assert_eq!(system.cpu.pc, 0x0009);
+        assert_eq!(system.cpu.sp, 0x2400);
+        assert_eq!(system.cpu.cycles() - cycles, 11 + 17 + 5 + 11);
+    }
+
+    #[test]
+    fn rst_and_pchl() {
+        let bytecode = [
+            0x31, 0x00, 0x24, // LXI SP, 0x2400
+            0xef, // RST 5
+        ];
+        let mut system = Emulator::new(bytecode);
+        system.step();
+        system.step();
+        assert_eq!(system.cpu.pc, 0x0028);
+        assert_eq!(system.interconnect.read_byte(0x23fe), 0x04);
+
+        // Interrupts only use the vector's low three bits.
+        let mut system = Emulator::new(bytecode);
+        system.step();
+        assert!(system.cpu.interrupt(9, &mut system.interconnect).unwrap());
+        assert_eq!(system.cpu.pc, 0x0008);
+
+        let bytecode = [0xe9]; // PCHL
+        let mut system = Emulator::new(bytecode);
+        system.cpu.h = 0x12;
+        system.cpu.l = 0x34;
+        system.step();
+        assert_eq!(system.cpu.pc, 0x1234);
+    }
 }
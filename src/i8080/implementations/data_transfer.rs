@@ -2,7 +2,7 @@ use crate::{
     i8080::error::EmulateError,
     i8080::{concat_bytes, Register, Result, I8080},
     instruction::{InstructionData, Opcode},
-    interconnect::Interconnect,
+    Bus,
 };
 
 impl I8080 {
@@ -48,9 +48,9 @@ impl I8080 {
     ///
     /// Loads the byte at the memory location given into the accumulator.
     // TODO: WRITE TEST
-    pub(crate) fn lda(&mut self, data: InstructionData, interconnect: &Interconnect) -> Result<()> {
+    pub(crate) fn lda(&mut self, data: InstructionData, bus: &impl Bus) -> Result<()> {
         if let Some(addr) = data.addr() {
-            self.set_8bit_register(Register::A, interconnect.read_byte(addr));
+            self.set_8bit_register(Register::A, bus.read_byte(addr));
         } else {
             return Err(EmulateError::InvalidInstructionData {
                 opcode: Opcode::LDA,
@@ -67,13 +67,9 @@ impl I8080 {
     ///
     /// Stores the value in the accumulator into memory at the given address.
     // TODO: WRITE TEST
-    pub(crate) fn sta(
-        &mut self,
-        data: InstructionData,
-        interconnect: &mut Interconnect,
-    ) -> Result<()> {
+    pub(crate) fn sta(&mut self, data: InstructionData, bus: &mut impl Bus) -> Result<()> {
         if let Some(addr) = data.addr() {
-            interconnect.write_byte(addr, self.a);
+            bus.write_byte(addr, self.a);
         } else {
             return Err(EmulateError::InvalidInstructionData {
                 opcode: Opcode::STA,
@@ -93,7 +89,7 @@ impl I8080 {
     ///
     /// #Errors
     /// Fails if given registers A, C, E, H, L, M, SP.
-    pub(crate) fn ldax(&mut self, register: Register, interconnect: &Interconnect) -> Result<()> {
+    pub(crate) fn ldax(&mut self, register: Register, bus: &impl Bus) -> Result<()> {
         let pair = match register {
             Register::B | Register::D => register.get_pair().unwrap(),
             _r => {
@@ -107,11 +103,77 @@ impl I8080 {
             self.get_8bit_register(register)?,
             self.get_8bit_register(pair)?,
         );
-        let value = interconnect.read_byte(loc);
+        let value = bus.read_byte(loc);
         self.set_8bit_register(Register::A, value);
         Ok(())
     }
 
+    /// #STAX - Store Accumulator
+    ///
+    /// Opcodes: 0x02, 0x12
+    /// Supported Registers: B(0x02), D(0x12)
+    ///
+    /// The contents of the accumulator are stored in the memory location addressed by registers
+    /// BC or DE.
+    ///
+    /// #Errors
+    /// Fails if given registers A, C, E, H, L, M, SP.
+    pub(crate) fn stax(&mut self, register: Register, bus: &mut impl Bus) -> Result<()> {
+        let pair = match register {
+            Register::B | Register::D => register.get_pair().unwrap(),
+            _r => {
+                return Err(EmulateError::UnsupportedRegister {
+                    opcode: Opcode::STAX(register),
+                    register,
+                })
+            }
+        };
+        let loc = concat_bytes(
+            self.get_8bit_register(register)?,
+            self.get_8bit_register(pair)?,
+        );
+        bus.write_byte(loc, self.a);
+        Ok(())
+    }
+
+    /// #LHLD - Load H and L Direct
+    ///
+    /// Opcodes: 0x2a
+    /// Params: Two byte memory location following the opcode
+    ///
+    /// L is loaded from the given address and H from the address after it.
+    pub(crate) fn lhld(&mut self, data: InstructionData, bus: &impl Bus) -> Result<()> {
+        if let Some(addr) = data.addr() {
+            self.set_8bit_register(Register::L, bus.read_byte(addr));
+            self.set_8bit_register(Register::H, bus.read_byte(addr.wrapping_add(1)));
+        } else {
+            return Err(EmulateError::InvalidInstructionData {
+                opcode: Opcode::LHLD,
+                data,
+            });
+        }
+        Ok(())
+    }
+
+    /// #SHLD - Store H and L Direct
+    ///
+    /// Opcodes: 0x22
+    /// Params: Two byte memory location following the opcode
+    ///
+    /// L is stored at the given address and H at the address after it.
+    pub(crate) fn shld(&mut self, data: InstructionData, bus: &mut impl Bus) -> Result<()> {
+        if let Some(addr) = data.addr() {
+            bus.write_byte(addr, self.l);
+            bus.write_byte(addr.wrapping_add(1), self.h);
+        } else {
+            return Err(EmulateError::InvalidInstructionData {
+                opcode: Opcode::SHLD,
+                data,
+            });
+        }
+        Ok(())
+    }
+
     /// #MOV - Move
     ///
     /// Opcodes: 0x40 - 0x7f; excluding 0x76
@@ -130,7 +192,7 @@ impl I8080 {
         &mut self,
         destination: Register,
         source: Register,
-        interconnect: &mut Interconnect,
+        bus: &mut impl Bus,
     ) -> Result<()> {
         match (destination, source) {
             (Register::SP, _) | (_, Register::SP) => {
@@ -144,11 +206,11 @@ impl I8080 {
                 if _r == Register::M {
                     return Ok(());
                 };
-                interconnect.write_byte(addr, self.get_8bit_register(_r)?);
+                bus.write_byte(addr, self.get_8bit_register(_r)?);
             }
             (_r, Register::M) => {
                 let addr = self.m();
-                self.set_8bit_register(_r, interconnect.read_byte(addr));
+                self.set_8bit_register(_r, bus.read_byte(addr));
             }
             (_r1, _r2) => self.set_8bit_register(_r1, self.get_8bit_register(_r2)?),
         }
@@ -169,7 +231,7 @@ impl I8080 {
         &mut self,
         register: Register,
         data: InstructionData,
-        interconnect: &mut Interconnect,
+        bus: &mut impl Bus,
     ) -> Result<()> {
         if let (Some(value), None) = data.tuple() {
             match register {
@@ -180,7 +242,7 @@ impl I8080 {
                     })
                 }
                 Register::M => {
-                    interconnect.write_byte(self.m(), value);
+                    bus.write_byte(self.m(), value);
                 }
                 _r => {
                     self.set_8bit_register(register, value);
@@ -212,20 +274,16 @@ impl I8080 {
     ///
     /// #Errors
     /// Fails if given registers A, C, E, L, or M
-    pub(crate) fn push(
-        &mut self,
-        register: Register,
-        interconnect: &mut Interconnect,
-    ) -> Result<()> {
+    pub(crate) fn push(&mut self, register: Register, bus: &mut impl Bus) -> Result<()> {
         match (register, register.get_pair()) {
             (_r, Some(r2)) => {
                 let value = concat_bytes(self.get_8bit_register(_r)?, self.get_8bit_register(r2)?);
-                self.push_u16(value, interconnect)?;
+                self.push_u16(value, bus)?;
             }
             (Register::A, None) => {
                 let value =
                     concat_bytes(self.get_8bit_register(Register::A)?, u8::from(self.flags));
-                self.push_u16(value, interconnect)?;
+                self.push_u16(value, bus)?;
             }
             (_r, _) => {
                 return Err(EmulateError::UnsupportedRegister {
@@ -250,18 +308,18 @@ impl I8080 {
     /// is indicated, then it is loaded into the conditional flags.
     ///
     /// The Stack Pointer is incremented by 2.
-    pub(crate) fn pop(&mut self, register: Register, interconnect: &Interconnect) -> Result<()> {
+    pub(crate) fn pop(&mut self, register: Register, bus: &impl Bus) -> Result<()> {
         use crate::i8080::flags::ConditionalFlags;
         match (register, register.get_pair()) {
             (_r, Some(r2)) => {
-                let low = self.pop_u8(interconnect)?;
-                let high = self.pop_u8(interconnect)?;
+                let low = self.pop_u8(bus)?;
+                let high = self.pop_u8(bus)?;
                 self.set_8bit_register(_r, high);
                 self.set_8bit_register(r2, low);
             }
             (Register::A, None) => {
-                let flags = self.pop_u8(interconnect)?;
-                let a = self.pop_u8(interconnect)?;
+                let flags = self.pop_u8(bus)?;
+                let a = self.pop_u8(bus)?;
                 self.flags = ConditionalFlags::from(flags);
                 self.set_8bit_register(Register::A, a);
             }
@@ -439,4 +497,25 @@ mod tests {
         assert_eq!(system.cpu.d, 0x00);
         assert_eq!(system.cpu.e, 0xff);
     }
+
+    #[test]
+    fn stax_lhld_shld() {
+        let bytecode = [
+            0x12, // STAX D
+            0x2a, 0x00, 0x21, // LHLD 0x2100
+            0x22, 0x02, 0x21, // SHLD 0x2102
+        ];
+        let mut system = Emulator::new(bytecode);
+        system.cpu.a = 0x5a;
+        system.cpu.d = 0x21;
+        system.cpu.e = 0x00;
+        system.step();
+        assert_eq!(system.interconnect.read_byte(0x2100), 0x5a);
+        system.interconnect.write_byte(0x2101, 0x3c);
+        system.step();
+        assert_eq!((system.cpu.h, system.cpu.l), (0x3c, 0x5a));
+        system.step();
+        assert_eq!(system.interconnect.read_byte(0x2102), 0x5a);
+        assert_eq!(system.interconnect.read_byte(0x2103), 0x3c);
+    }
 }
@@ -1,14 +1,29 @@
 use crate::{
-    i8080::{error::EmulateError, Result, I8080},
+    i8080::{error::EmulateError, Register, Result, I8080},
     instruction::{InstructionData, Opcode},
+    Bus,
 };
-use log::warn;
 
 impl I8080 {
-    pub(crate) fn out(&mut self, data: InstructionData) -> Result<()> {
-        if let Some(_device) = data.first() {
-            // TODO: self.write_device(device, self.get_8bit_register(Register::A)?);
-            warn!("Devices unimplemented");
+    /// #IN - Input
+    ///
+    /// Reads the byte on `port` into the accumulator.
+    pub(crate) fn input(&mut self, data: InstructionData, bus: &mut impl Bus) -> Result<()> {
+        if let Some(port) = data.first() {
+            let value = bus.input(port);
+            self.set_8bit_register(Register::A, value);
+        } else {
+            return Err(EmulateError::InvalidInstructionData {
+                opcode: Opcode::IN,
+                data,
+            });
+        }
+        Ok(())
+    }
+
+    pub(crate) fn out(&mut self, data: InstructionData, bus: &mut impl Bus) -> Result<()> {
+        if let Some(port) = data.first() {
+            bus.output(port, self.a);
         } else {
             return Err(EmulateError::InvalidInstructionData {
                 opcode: Opcode::OUT,
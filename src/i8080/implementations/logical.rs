@@ -1,70 +1,92 @@
+use super::arithmetic::immediate;
 use crate::i8080::*;
 use crate::instruction::{InstructionData, Opcode};
-use crate::interconnect::Interconnect;
+use crate::Bus;
 
 impl I8080 {
+    /// #CMP - Compare Register or Memory With Accumulator
+    ///
+    /// Sets the flags as SUB would, leaving the accumulator unchanged.
+    pub(crate) fn cmp(&mut self, register: Register, bus: &impl Bus) -> Result<()> {
+        let value = self.operand(Opcode::CMP(register), register, bus)?;
+        self.subtract(value, false);
+        Ok(())
+    }
+
     pub(crate) fn cpi(&mut self, data: InstructionData) -> Result<()> {
-        if let Some(value) = data.first() {
-            let (v, c) = self.a.complement_sub(value);
-            self.flags.set_non_carry_flags(v);
-            self.flags.cy = c;
-        } else {
-            return Err(EmulateError::InvalidInstructionData {
-                opcode: Opcode::CPI,
-                data,
-            });
-        }
+        let value = immediate(Opcode::CPI, data)?;
+        self.subtract(value, false);
         Ok(())
     }
 
     pub(crate) fn ani(&mut self, data: InstructionData) -> Result<()> {
-        if let Some(value) = data.first() {
-            let result = self.get_8bit_register(Register::A)? & value;
-            self.set_8bit_register(Register::A, result);
-            self.flags.set_non_carry_flags(result);
-            self.flags.cy = false;
-        } else {
-            return Err(EmulateError::InvalidInstructionData {
-                opcode: Opcode::ANI,
-                data,
-            });
-        }
+        let value = immediate(Opcode::ANI, data)?;
+        self.and_with_a(value);
         Ok(())
     }
 
-    pub(crate) fn ana(&mut self, register: Register, interconnect: &Interconnect) -> Result<()> {
-        let value: u8 = match register {
-            Register::SP => {
-                return Err(EmulateError::UnsupportedRegister {
-                    opcode: Opcode::ANA(register),
-                    register,
-                })
-            }
-            Register::M => interconnect.read_byte(self.m()),
-            _r => self.get_8bit_register(_r)?,
-        };
+    pub(crate) fn ana(&mut self, register: Register, bus: &impl Bus) -> Result<()> {
+        let value = self.operand(Opcode::ANA(register), register, bus)?;
+        self.and_with_a(value);
+        Ok(())
+    }
+
+    /// AND on the 8080 sets auxiliary carry from bit 3 of either operand.
+    fn and_with_a(&mut self, value: u8) {
         let result = self.a & value;
         self.flags.set_non_carry_flags(result);
         self.flags.cy = false;
+        self.flags.ac = (self.a | value) & 0x08 != 0;
         self.set_8bit_register(Register::A, result);
+    }
+
+    pub(crate) fn xra(&mut self, register: Register, bus: &impl Bus) -> Result<()> {
+        let value = self.operand(Opcode::XRA(register), register, bus)?;
+        self.set_logical_result(self.a ^ value);
+        Ok(())
+    }
+
+    pub(crate) fn xri(&mut self, data: InstructionData) -> Result<()> {
+        let value = immediate(Opcode::XRI, data)?;
+        self.set_logical_result(self.a ^ value);
         Ok(())
     }
 
-    pub(crate) fn xra(&mut self, register: Register, interconnect: &Interconnect) -> Result<()> {
-        let value: u8 = match register {
-            Register::SP => {
-                return Err(EmulateError::UnsupportedRegister {
-                    opcode: Opcode::XRA(register),
-                    register,
-                })
-            }
-            Register::M => interconnect.read_byte(self.m()),
-            _r => self.get_8bit_register(_r)?,
-        };
-        let result = self.a ^ value;
+    pub(crate) fn ora(&mut self, register: Register, bus: &impl Bus) -> Result<()> {
+        let value = self.operand(Opcode::ORA(register), register, bus)?;
+        self.set_logical_result(self.a | value);
+        Ok(())
+    }
+
+    pub(crate) fn ori(&mut self, data: InstructionData) -> Result<()> {
+        let value = immediate(Opcode::ORI, data)?;
+        self.set_logical_result(self.a | value);
+        Ok(())
+    }
+
+    /// Stores the result of XRA or ORA, which clear both carries.
+    fn set_logical_result(&mut self, result: u8) {
         self.flags.set_non_carry_flags(result);
         self.flags.cy = false;
+        self.flags.ac = false;
         self.set_8bit_register(Register::A, result);
+    }
+
+    /// #CMA - Complement Accumulator
+    pub(crate) fn cma(&mut self) -> Result<()> {
+        self.set_8bit_register(Register::A, !self.a);
+        Ok(())
+    }
+
+    /// #CMC - Complement Carry
+    pub(crate) fn cmc(&mut self) -> Result<()> {
+        self.flags.cy = !self.flags.cy;
+        Ok(())
+    }
+
+    /// #STC - Set Carry
+    pub(crate) fn stc(&mut self) -> Result<()> {
+        self.flags.cy = true;
         Ok(())
     }
 }
@@ -154,4 +176,60 @@ mod tests {
         system.step();
         assert_eq!(system.cpu.a, 0x00);
     }
+
+    #[test]
+    fn cmp() {
+        let bytecode = [
+            0xbb, // CMP E
+            0xbb, // CMP E
+        ];
+        let mut system = Emulator::new(bytecode);
+        system.cpu.a = 0x0a;
+        system.cpu.e = 0x05;
+        system.step();
+        assert_eq!(system.cpu.a, 0x0a);
+        assert!(!system.cpu.flags.cy);
+        assert!(!system.cpu.flags.z);
+        system.cpu.a = 0x02;
+        system.step();
+        assert!(system.cpu.flags.cy);
+        assert!(system.cpu.flags.s);
+    }
+
+    #[test]
+    fn ora_xri() {
+        let bytecode = [
+            0xb1, // ORA C
+            0xee, 0x81, // XRI 0x81
+        ];
+        let mut system = Emulator::new(bytecode);
+        system.cpu.a = 0x33;
+        system.cpu.c = 0x0f;
+        system.cpu.flags.cy = true;
+        system.cpu.flags.ac = true;
+        system.step();
+        assert_eq!(system.cpu.a, 0x3f);
+        assert!(!system.cpu.flags.cy);
+        assert!(!system.cpu.flags.ac);
+        system.step();
+        assert_eq!(system.cpu.a, 0xbe);
+        assert!(system.cpu.flags.s);
+    }
+
+    #[test]
+    fn cma_cmc_stc() {
+        let bytecode = [
+            0x2f, // CMA
+            0x37, // STC
+            0x3f, // CMC
+        ];
+        let mut system = Emulator::new(bytecode);
+        system.cpu.a = 0x51;
+        system.step();
+        assert_eq!(system.cpu.a, 0xae);
+        system.step();
+        assert!(system.cpu.flags.cy);
+        system.step();
+        assert!(!system.cpu.flags.cy);
+    }
 }
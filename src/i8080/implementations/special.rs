@@ -5,4 +5,51 @@ impl I8080 {
         self.interrupts_enabled = true;
         Ok(())
     }
+
+    pub(crate) fn di(&mut self) -> Result<()> {
+        self.interrupts_enabled = false;
+        Ok(())
+    }
+
+    /// #HLT - Halt
+    ///
+    /// The cpu stops executing until the next interrupt.
+    pub(crate) fn hlt(&mut self) -> Result<()> {
+        self.halted = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Emulator;
+
+    #[test]
+    fn hlt_waits_for_interrupt() {
+        let bytecode = [
+            0x31, 0x00, 0x24, // LXI SP, 0x2400
+            0xf3, // DI
+            0xfb, // EI
+            0x76, // HLT
+        ];
+        let mut system = Emulator::new(bytecode);
+        system.step();
+        system.step();
+        assert!(!system.cpu.interrupts_enabled);
+        assert!(!system.cpu.interrupt(1, &mut system.interconnect).unwrap());
+        system.step();
+        system.step();
+        assert!(system.cpu.is_halted());
+
+        let cycles = system.cpu.cycles();
+        system.cpu.step(&mut system.interconnect).unwrap();
+        assert_eq!((system.cpu.pc, system.cpu.cycles() - cycles), (0x0006, 4));
+
+        assert!(system.cpu.interrupt(1, &mut system.interconnect).unwrap());
+        assert!(!system.cpu.is_halted());
+        assert!(!system.cpu.interrupts_enabled);
+        assert_eq!(system.cpu.pc, 0x0008);
+        assert_eq!(system.cpu.sp, 0x23fe);
+        assert_eq!(system.interconnect.read_byte(0x23fe), 0x06);
+    }
 }
@@ -0,0 +1,54 @@
+use crate::{
+    i8080::{Register, Result, I8080},
+    Bus,
+};
+
+impl I8080 {
+    /// #XTHL - Exchange Stack Top With H and L
+    ///
+    /// Opcodes: 0xe3
+    ///
+    /// L is exchanged with the byte at SP and H with the byte at SP + 1.
+    pub(crate) fn xthl(&mut self, bus: &mut impl Bus) -> Result<()> {
+        let low = bus.read_byte(self.sp);
+        let high = bus.read_byte(self.sp.wrapping_add(1));
+        bus.write_byte(self.sp, self.l);
+        bus.write_byte(self.sp.wrapping_add(1), self.h);
+        self.set_8bit_register(Register::L, low);
+        self.set_8bit_register(Register::H, high);
+        Ok(())
+    }
+
+    /// #SPHL - Load SP From H and L
+    ///
+    /// Opcodes: 0xf9
+    pub(crate) fn sphl(&mut self) -> Result<()> {
+        self.set_sp(self.m());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Emulator;
+
+    #[test]
+    fn xthl_sphl() {
+        let bytecode = [
+            0xe3, // XTHL
+            0xf9, // SPHL
+        ];
+        let mut system = Emulator::new(bytecode);
+        system.cpu.sp = 0x23f0;
+        system.cpu.h = 0x0b;
+        system.cpu.l = 0x3c;
+        system.interconnect.write_byte(0x23f0, 0xf0);
+        system.interconnect.write_byte(0x23f1, 0x0d);
+        system.step();
+        assert_eq!((system.cpu.h, system.cpu.l), (0x0d, 0xf0));
+        assert_eq!(system.interconnect.read_byte(0x23f0), 0x3c);
+        assert_eq!(system.interconnect.read_byte(0x23f1), 0x0b);
+        system.step();
+        assert_eq!(system.cpu.sp, 0x0df0);
+    }
+}
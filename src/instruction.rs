@@ -4,10 +4,9 @@ pub use self::opcode::Opcode;
 mod instruction_data;
 pub(crate) use self::instruction_data::InstructionData;
 
-use crate::i8080::split_bytes;
-use failure::bail;
-use failure::Error;
-use std::fmt::{self, Display};
+use self::opcode::OpcodeSize;
+use crate::{i8080::split_bytes, Bus, Error};
+use core::fmt::{self, Display};
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Instruction {
@@ -17,31 +16,37 @@ pub struct Instruction {
 
 impl Instruction {
     pub fn new_unary(opcode: Opcode) -> Result<Instruction, Error> {
-        if let self::opcode::OpcodeSize::Unary = opcode.size() {
+        if let OpcodeSize::Unary = opcode.size() {
             Ok(Instruction {
                 opcode,
                 data: InstructionData::new(None, None),
                 //params: InstructionParams::Unary,
             })
         } else {
-            bail!("Unary instructions require Unary Opcodes")
+            Err(Error::OpcodeSize {
+                opcode,
+                expected: OpcodeSize::Unary,
+            })
         }
     }
 
     pub fn new_binary(opcode: Opcode, data: u8) -> Result<Instruction, Error> {
-        if let self::opcode::OpcodeSize::Binary = opcode.size() {
+        if let OpcodeSize::Binary = opcode.size() {
             Ok(Instruction {
                 opcode,
                 data: InstructionData::new(Some(data), None),
                 //params: InstructionParams::Binary(data),
             })
         } else {
-            bail!("Binary instructions require Binary Opcodes")
+            Err(Error::OpcodeSize {
+                opcode,
+                expected: OpcodeSize::Binary,
+            })
         }
     }
 
     pub fn new_trinary(opcode: Opcode, addr: u16) -> Result<Instruction, Error> {
-        if let self::opcode::OpcodeSize::Trinary = opcode.size() {
+        if let OpcodeSize::Trinary = opcode.size() {
             let (h, l) = split_bytes(addr);
             Ok(Instruction {
                 opcode,
@@ -49,15 +54,36 @@ impl Instruction {
                 //params: InstructionParams::Trinary(addr),
             })
         } else {
-            bail!("Trinary instructions require Trinary Opcodes")
+            Err(Error::OpcodeSize {
+                opcode,
+                expected: OpcodeSize::Trinary,
+            })
+        }
+    }
+
+    /// Decodes the instruction at `addr`, reading only as many bytes as it
+    /// occupies.
+    pub fn read(bus: &impl Bus, addr: u16) -> Instruction {
+        let opcode = Opcode::from(bus.read_byte(addr));
+        match opcode.size() {
+            OpcodeSize::Unary => Instruction::new_unary(opcode).unwrap(),
+            OpcodeSize::Binary => {
+                let data = bus.read_byte(addr.wrapping_add(1));
+                Instruction::new_binary(opcode, data).unwrap()
+            }
+            OpcodeSize::Trinary => {
+                let low = bus.read_byte(addr.wrapping_add(1)) as u16;
+                let high = bus.read_byte(addr.wrapping_add(2)) as u16;
+                Instruction::new_trinary(opcode, high << 8 | low).unwrap()
+            }
         }
     }
 
     pub fn len(&self) -> u16 {
         match self.opcode.size() {
-            self::opcode::OpcodeSize::Unary => 1,
-            self::opcode::OpcodeSize::Binary => 2,
-            self::opcode::OpcodeSize::Trinary => 3,
+            OpcodeSize::Unary => 1,
+            OpcodeSize::Binary => 2,
+            OpcodeSize::Trinary => 3,
         }
     }
 
@@ -70,6 +96,29 @@ impl Instruction {
     }
 }
 
+/// Decodes consecutive instructions starting at `addr`, wrapping at the top
+/// of the address space.
+pub fn disassemble<B: Bus>(bus: &B, addr: u16) -> impl Iterator<Item = (u16, Instruction)> + '_ {
+    let mut next = addr;
+    core::iter::from_fn(move || {
+        let instruction = Instruction::read(bus, next);
+        let addr = next;
+        next = next.wrapping_add(instruction.len());
+        Some((addr, instruction))
+    })
+}
+
+impl Instruction {
+    /// Writes the instruction with `operand` in place of its data, without
+    /// the column padding.
+    pub(crate) fn fmt_operand(&self, f: &mut fmt::Formatter, operand: &dyn Display) -> fmt::Result {
+        match self.opcode.num_registers() {
+            0 => write!(f, "{}{}", self.opcode, operand),
+            _ => write!(f, "{}, {}", self.opcode, operand),
+        }
+    }
+}
+
 impl Display for Instruction {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match (self.len(), self.opcode.num_registers()) {
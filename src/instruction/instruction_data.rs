@@ -1,5 +1,5 @@
 use crate::i8080::concat_bytes;
-use std::fmt::{self, Display};
+use core::fmt::{self, Display};
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct InstructionData {
@@ -1,5 +1,5 @@
 use crate::i8080::Register;
-use std::fmt::{self, Display};
+use core::fmt::{self, Display};
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Opcode {
@@ -389,6 +389,39 @@ impl Opcode {
         }
     }
 
+    /// Clock cycles taken by the instruction.
+    ///
+    /// Conditional calls and returns take 6 more cycles when the branch is taken.
+    pub fn cycles(&self) -> u8 {
+        use self::{Opcode::*, Register::M};
+        match self {
+            MOV(M, _) | MOV(_, M) => 7,
+            MOV(_, _) => 5,
+            INR(M) | DCR(M) | MVI(M) => 10,
+            INR(_) | DCR(_) => 5,
+            MVI(_) => 7,
+            ADD(M) | ADC(M) | SUB(M) | SBB(M) | ANA(M) | XRA(M) | ORA(M) | CMP(M) => 7,
+            ADD(_) | ADC(_) | SUB(_) | SBB(_) | ANA(_) | XRA(_) | ORA(_) | CMP(_) => 4,
+            LXI(_) | DAD(_) | POP(_) => 10,
+            STAX(_) | LDAX(_) => 7,
+            INX(_) | DCX(_) => 5,
+            PUSH(_) | RST(_) => 11,
+            SHLD | LHLD => 16,
+            STA | LDA => 13,
+            JMP | JNZ | JZ | JNC | JC | JPO | JPE | JP | JM => 10,
+            CALL => 17,
+            CNZ | CZ | CNC | CC | CPO | CPE | CP | CM => 11,
+            RET => 10,
+            RNZ | RZ | RNC | RC | RPO | RPE | RP | RM => 5,
+            ADI | ACI | SUI | SBI | ANI | XRI | ORI | CPI => 7,
+            OUT | IN => 10,
+            XTHL => 18,
+            PCHL | SPHL => 5,
+            HLT => 7,
+            NOP | RLC | RRC | RAL | RAR | RIM | SIM | DAA | CMA | STC | CMC | XCHG | DI | EI => 4,
+        }
+    }
+
     pub(super) fn num_registers(&self) -> u8 {
         use self::Opcode::*;
         match self {
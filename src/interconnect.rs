@@ -2,21 +2,41 @@ use log::error;
 
 mod game_pad;
 pub mod rom;
+mod shift_register;
+mod sound;
 mod vram;
+mod watchdog;
 mod wram;
 
-use self::game_pad::GamePad;
+pub use self::game_pad::{Button, DipSwitches, GamePad};
 pub use self::rom::Rom;
+use self::shift_register::ShiftRegister;
+pub use self::sound::{Sound, SoundEvent, SoundPorts};
 use self::vram::Vram;
+pub use self::watchdog::{Watchdog, WatchdogEvent, WATCHDOG_TIMEOUT};
 use self::wram::Wram;
 
-use crate::mem_map::*;
+use crate::{
+    mem_map::*,
+    state::{StateReader, StateWriter},
+    Bus, Error,
+};
+
+/// The device state only `OUT` changes: the shift register and sound latches.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Latches {
+    shift_register: ShiftRegister,
+    sound: (u8, u8),
+}
 
 pub struct Interconnect {
     rom: Rom,
     wram: Wram,
     vram: Vram,
     game_pad: GamePad,
+    shift_register: ShiftRegister,
+    sound: SoundPorts,
+    watchdog: Watchdog,
 }
 
 impl Interconnect {
@@ -26,28 +46,148 @@ impl Interconnect {
             wram: Wram::new(),
             vram: Vram::new(),
             game_pad: GamePad::new(),
+            shift_register: ShiftRegister::new(),
+            sound: SoundPorts::new(),
+            watchdog: Watchdog::new(),
         }
     }
 
+    pub fn rom(&self) -> &Rom {
+        &self.rom
+    }
+
     pub fn rom_len(&self) -> usize {
         self.rom.len()
     }
 
+    pub fn game_pad(&self) -> &GamePad {
+        &self.game_pad
+    }
+
+    pub fn game_pad_mut(&mut self) -> &mut GamePad {
+        &mut self.game_pad
+    }
+
+    /// The bitmap at `VRAM_START`, 32 bytes per column of the rotated screen.
+    pub fn vram(&self) -> &[u8] {
+        self.vram.bytes()
+    }
+
+    pub fn sound(&self) -> &SoundPorts {
+        &self.sound
+    }
+
+    pub fn sound_mut(&mut self) -> &mut SoundPorts {
+        &mut self.sound
+    }
+
+    pub fn watchdog(&self) -> &Watchdog {
+        &self.watchdog
+    }
+
+    pub fn watchdog_mut(&mut self) -> &mut Watchdog {
+        &mut self.watchdog
+    }
+
+    /// Saves ram and device state. The rom and the host's inputs are not
+    /// part of it.
+    pub(crate) fn save_state(&self, w: &mut StateWriter) {
+        w.bytes(self.wram.bytes());
+        w.bytes(self.vram.bytes());
+        self.save_devices(w);
+    }
+
+    /// Saves the state held outside memory: the shift register, sound latches
+    /// and watchdog.
+    pub(crate) fn save_devices(&self, w: &mut StateWriter) {
+        self.shift_register.save_state(w);
+        self.sound.save_state(w);
+        self.watchdog.save_state(w);
+    }
+
+    pub(crate) fn latches(&self) -> Latches {
+        Latches {
+            shift_register: self.shift_register,
+            sound: self.sound.latches(),
+        }
+    }
+
+    pub(crate) fn set_latches(&mut self, latches: Latches) {
+        self.shift_register = latches.shift_register;
+        self.sound.set_latches(latches.sound);
+    }
+
+    pub(crate) fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        let len = self.wram.bytes().len();
+        self.wram.load(r.bytes(len)?);
+        let len = self.vram.bytes().len();
+        self.vram.load(r.bytes(len)?);
+        self.load_devices(r)
+    }
+
+    pub(crate) fn load_devices(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.shift_register.load_state(r)?;
+        self.sound.load_state(r)?;
+        self.watchdog.load_state(r)?;
+        Ok(())
+    }
+
+    /// Reads a byte from the memory map.
+    ///
+    /// Rom past the end of the image reads as zero. Above `VRAM_END`, roms
+    /// that extend that far are read directly and everything else mirrors ram.
     pub fn read_byte(&self, addr: u16) -> u8 {
         match addr {
-            ROM_START...ROM_END => self.rom.read_byte(addr - ROM_START),
-            WRAM_START...WRAM_END => self.wram.read_byte(addr - WRAM_START),
-            VRAM_START...VRAM_END => self.vram.read_byte(addr - VRAM_START),
-            _ => panic!("Unrecognized Address: 0x{:04x}", addr),
+            ROM_START..=ROM_END if (addr as usize) < self.rom.len() => {
+                self.rom.read_byte(addr - ROM_START)
+            }
+            ROM_START..=ROM_END => 0,
+            WRAM_START..=WRAM_END => self.wram.read_byte(addr - WRAM_START),
+            VRAM_START..=VRAM_END => self.vram.read_byte(addr - VRAM_START),
+            _ if (addr as usize) < self.rom.len() => self.rom.read_byte(addr),
+            _ => self.read_byte(mirror(addr)),
         }
     }
 
     pub fn write_byte(&mut self, addr: u16, value: u8) {
         match addr {
-            ROM_START...ROM_END => error!("Attempting to write to ROM"),
-            WRAM_START...WRAM_END => self.wram.write_byte(addr - WRAM_START, value),
-            VRAM_START...VRAM_END => self.vram.write_byte(addr - VRAM_START, value),
-            _ => panic!("Unrecognized Address: 0x{:04x}", addr),
+            ROM_START..=ROM_END => error!("Attempting to write to ROM"),
+            WRAM_START..=WRAM_END => self.wram.write_byte(addr - WRAM_START, value),
+            VRAM_START..=VRAM_END => self.vram.write_byte(addr - VRAM_START, value),
+            _ if (addr as usize) < self.rom.len() => error!("Attempting to write to ROM"),
+            _ => self.write_byte(mirror(addr), value),
+        }
+    }
+}
+
+impl Bus for Interconnect {
+    fn read_byte(&self, addr: u16) -> u8 {
+        Interconnect::read_byte(self, addr)
+    }
+
+    fn write_byte(&mut self, addr: u16, value: u8) {
+        Interconnect::write_byte(self, addr, value)
+    }
+
+    fn input(&mut self, port: u8) -> u8 {
+        match port {
+            shift_register::SHIFT_RESULT_PORT => self.shift_register.result(),
+            _ => self.game_pad.read(port).unwrap_or(0),
         }
     }
+
+    fn output(&mut self, port: u8, value: u8) {
+        match port {
+            shift_register::SHIFT_OFFSET_PORT => self.shift_register.set_offset(value),
+            shift_register::SHIFT_DATA_PORT => self.shift_register.write_data(value),
+            sound::SOUND_PORT_1 | sound::SOUND_PORT_2 => self.sound.write(port, value),
+            watchdog::WATCHDOG_PORT => self.watchdog.kick(),
+            _ => {}
+        }
+    }
+}
+
+/// Maps an address above the rom and ram onto the ram it mirrors.
+pub(crate) fn mirror(addr: u16) -> u16 {
+    WRAM_START | addr & (VRAM_END - WRAM_START)
 }
@@ -1,9 +1,210 @@
+/// Inputs wired to ports 1 and 2 of the Space Invaders board.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Button {
+    Coin,
+    Start1,
+    Start2,
+    Fire1,
+    Left1,
+    Right1,
+    Fire2,
+    Left2,
+    Right2,
+    Tilt,
+}
+
+impl Button {
+    pub const ALL: [Button; 10] = [
+        Button::Coin,
+        Button::Start1,
+        Button::Start2,
+        Button::Fire1,
+        Button::Left1,
+        Button::Right1,
+        Button::Fire2,
+        Button::Left2,
+        Button::Right2,
+        Button::Tilt,
+    ];
+
+    /// Lower case name used on command lines and in input files, e.g. `fire1`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Button::Coin => "coin",
+            Button::Start1 => "start1",
+            Button::Start2 => "start2",
+            Button::Fire1 => "fire1",
+            Button::Left1 => "left1",
+            Button::Right1 => "right1",
+            Button::Fire2 => "fire2",
+            Button::Left2 => "left2",
+            Button::Right2 => "right2",
+            Button::Tilt => "tilt",
+        }
+    }
+
+    /// The button called `name`, as returned by `name`.
+    pub fn from_name(name: &str) -> Option<Button> {
+        Button::ALL.iter().copied().find(|b| b.name() == name)
+    }
+
+    /// `(port, bit)` the button drives high while pressed.
+    fn bit(self) -> (u8, u8) {
+        match self {
+            Button::Coin => (1, 0),
+            Button::Start2 => (1, 1),
+            Button::Start1 => (1, 2),
+            Button::Fire1 => (1, 4),
+            Button::Left1 => (1, 5),
+            Button::Right1 => (1, 6),
+            Button::Tilt => (2, 2),
+            Button::Fire2 => (2, 4),
+            Button::Left2 => (2, 5),
+            Button::Right2 => (2, 6),
+        }
+    }
+}
+
+/// The operator settings read through port 2.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DipSwitches {
+    /// Ships per game, 3 to 6.
+    pub lives: u8,
+    /// Award the extra ship at 1000 points instead of 1500.
+    pub early_extra_life: bool,
+    /// Show the coin information on the attract screen.
+    pub coin_info: bool,
+}
+
+impl Default for DipSwitches {
+    fn default() -> Self {
+        DipSwitches {
+            lives: 3,
+            early_extra_life: false,
+            coin_info: true,
+        }
+    }
+}
+
+impl DipSwitches {
+    fn bits(&self) -> u8 {
+        let lives = self.lives.clamp(3, 6) - 3;
+        let extra_life = (self.early_extra_life as u8) << 3;
+        let coin_info = (!self.coin_info as u8) << 7;
+        lives | extra_life | coin_info
+    }
+}
+
+#[derive(Default)]
 pub struct GamePad {
-    //TODO
+    port1: u8,
+    port2: u8,
+    dip_switches: DipSwitches,
 }
 
 impl GamePad {
     pub fn new() -> GamePad {
-        GamePad {}
+        GamePad::default()
+    }
+
+    pub fn press(&mut self, button: Button) {
+        self.set(button, true);
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.set(button, false);
+    }
+
+    pub fn set(&mut self, button: Button, pressed: bool) {
+        let (port, bit) = button.bit();
+        let value = match port {
+            1 => &mut self.port1,
+            _ => &mut self.port2,
+        };
+        if pressed {
+            *value |= 1 << bit;
+        } else {
+            *value &= !(1 << bit);
+        }
+    }
+
+    pub fn is_pressed(&self, button: Button) -> bool {
+        let (port, bit) = button.bit();
+        let value = match port {
+            1 => self.port1,
+            _ => self.port2,
+        };
+        value & (1 << bit) != 0
+    }
+
+    /// Every pressed button as one bit mask, port 1 in the low byte and port 2
+    /// in the high byte.
+    pub fn buttons(&self) -> u16 {
+        u16::from(self.port1) | u16::from(self.port2) << 8
+    }
+
+    /// Presses exactly the buttons in a mask from `buttons`.
+    pub fn set_buttons(&mut self, buttons: u16) {
+        self.port1 = buttons as u8;
+        self.port2 = (buttons >> 8) as u8;
+    }
+
+    pub fn dip_switches(&self) -> DipSwitches {
+        self.dip_switches
+    }
+
+    pub fn set_dip_switches(&mut self, dip_switches: DipSwitches) {
+        self.dip_switches = dip_switches;
+    }
+
+    /// The byte read from input port `port`, or None if the pad doesn't drive it.
+    ///
+    /// Port 0 is unused by the game but wired on the board; bit 3 of port 1
+    /// is always high.
+    pub(crate) fn read(&self, port: u8) -> Option<u8> {
+        match port {
+            0 => Some(0x0e | self.port1 & 0x70),
+            1 => Some(0x08 | self.port1),
+            2 => Some(self.dip_switches.bits() | self.port2),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Button, DipSwitches, GamePad};
+
+    #[test]
+    fn buttons_drive_port_bits() {
+        let mut pad = GamePad::new();
+        pad.press(Button::Coin);
+        pad.press(Button::Left1);
+        pad.press(Button::Fire2);
+        assert_eq!(pad.read(1), Some(0x29));
+        assert_eq!(pad.read(2), Some(0x10));
+        pad.release(Button::Coin);
+        assert!(!pad.is_pressed(Button::Coin));
+        assert_eq!(pad.read(1), Some(0x28));
+        assert_eq!(pad.read(3), None);
+    }
+
+    #[test]
+    fn dip_switches() {
+        let mut pad = GamePad::new();
+        pad.set_dip_switches(DipSwitches {
+            lives: 5,
+            early_extra_life: true,
+            coin_info: false,
+        });
+        assert_eq!(pad.read(2), Some(0x8a));
+    }
+
+    #[test]
+    fn button_names() {
+        for &button in Button::ALL.iter() {
+            assert_eq!(Button::from_name(button.name()), Some(button));
+        }
+        assert_eq!(Button::from_name("fire"), None);
     }
 }
@@ -1,4 +1,7 @@
-use std::convert::From;
+use crate::{patch::Patch, Error};
+use alloc::{boxed::Box, vec::Vec};
+
+mod hex;
 
 pub struct Rom {
     bytes: Box<[u8]>,
@@ -6,6 +9,68 @@ pub struct Rom {
 }
 
 impl Rom {
+    /// Builds a rom image with `data` placed at `origin`.
+    ///
+    /// Everything below `origin` is zero filled.
+    pub fn with_origin<T: AsRef<[u8]>>(data: T, origin: u16) -> Result<Rom, Error> {
+        Rom::from_parts(Some((origin, data)))
+    }
+
+    /// Assembles a rom image from several chunks, each placed at its own address.
+    ///
+    /// Gaps between chunks are zero filled. Fails if two chunks overlap or a
+    /// chunk runs past the end of the 64K address space.
+    pub fn from_parts<I, T>(parts: I) -> Result<Rom, Error>
+    where
+        I: IntoIterator<Item = (u16, T)>,
+        T: AsRef<[u8]>,
+    {
+        let mut bytes = Vec::new();
+        let mut loaded: Vec<bool> = Vec::new();
+        for (origin, data) in parts {
+            let data = data.as_ref();
+            let start = origin as usize;
+            let end = start + data.len();
+            if end > 0x10000 {
+                return Err(Error::RomTooLarge {
+                    origin,
+                    len: data.len(),
+                });
+            }
+            if end > bytes.len() {
+                bytes.resize(end, 0);
+                loaded.resize(end, false);
+            }
+            if let Some(i) = loaded[start..end].iter().position(|&l| l) {
+                return Err(Error::RomOverlap {
+                    addr: (start + i) as u16,
+                });
+            }
+            bytes[start..end].copy_from_slice(data);
+            for l in loaded[start..end].iter_mut() {
+                *l = true;
+            }
+        }
+        Ok(Rom {
+            bytes: bytes.into_boxed_slice(),
+        })
+    }
+
+    /// Parses an Intel HEX image, validating every record's checksum.
+    pub fn from_hex(text: &str) -> Result<Rom, Error> {
+        Rom::from_parts(hex::parse(text)?)
+    }
+
+    /// Applies an IPS or BPS patch to the whole image, gaps included.
+    pub fn patched(&self, patch: &Patch) -> Result<Rom, Error> {
+        Rom::with_origin(patch.apply(&self.bytes)?, 0)
+    }
+
+    /// CRC32 of the whole image, gaps included.
+    pub fn crc32(&self) -> u32 {
+        crc32fast::hash(&self.bytes)
+    }
+
     pub(crate) fn read_byte(&self, addr: u16) -> u8 {
         self.bytes[addr as usize]
         //  let mask = (self.bytes.len() - 1) as u16;
@@ -18,6 +83,58 @@ impl Rom {
     }
 }
 
+#[cfg(feature = "std")]
+impl Rom {
+    /// Loads a raw binary file at `origin`.
+    pub fn from_binary_file<P: AsRef<std::path::Path>>(path: P, origin: u16) -> Result<Rom, Error> {
+        Rom::with_origin(std::fs::read(path)?, origin)
+    }
+
+    /// Loads an Intel HEX (`.hex`) file.
+    pub fn from_hex_file<P: AsRef<std::path::Path>>(path: P) -> Result<Rom, Error> {
+        Rom::from_hex(&std::fs::read_to_string(path)?)
+    }
+
+    /// Loads whatever `path` names: a directory holding the Space Invaders rom
+    /// set, an Intel HEX file (`.hex` or `.ihx`), or a raw image loaded at 0.
+    pub fn from_path<P: AsRef<std::path::Path>>(path: P) -> Result<Rom, Error> {
+        let path = path.as_ref();
+        if path.is_dir() {
+            crate::manifest::Manifest::known()
+                .get("invaders")
+                .unwrap()
+                .load_dir(path)
+        } else if matches!(
+            path.extension().and_then(|e| e.to_str()),
+            Some("hex") | Some("ihx")
+        ) {
+            Rom::from_hex_file(path)
+        } else {
+            Rom::from_binary_file(path, 0)
+        }
+    }
+
+    /// Applies IPS or BPS patch files in turn, e.g. a hack and then its fix.
+    pub fn with_patch_files<P: AsRef<std::path::Path>>(self, patches: &[P]) -> Result<Rom, Error> {
+        patches
+            .iter()
+            .try_fold(self, |rom, path| rom.patched(&Patch::load(path)?))
+    }
+
+    /// Assembles a rom image from several raw binary files.
+    ///
+    /// The MAME Space Invaders set, for example, is laid out as
+    /// `[("invaders.h", 0x0000), ("invaders.g", 0x0800), ("invaders.f", 0x1000),
+    /// ("invaders.e", 0x1800)]`.
+    pub fn from_files<P: AsRef<std::path::Path>>(files: &[(P, u16)]) -> Result<Rom, Error> {
+        let mut parts = Vec::with_capacity(files.len());
+        for (path, origin) in files {
+            parts.push((*origin, std::fs::read(path)?));
+        }
+        Rom::from_parts(parts)
+    }
+}
+
 impl<T> From<T> for Rom
 where
     T: AsRef<[u8]>,
@@ -28,3 +145,59 @@ where
         Rom { bytes }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Rom;
+    use crate::Error;
+    use alloc::vec;
+
+    #[test]
+    fn with_origin() {
+        let rom = Rom::with_origin([0xc3, 0x00, 0x01], 0x0100).unwrap();
+        assert_eq!(rom.len(), 0x0103);
+        assert_eq!(rom.read_byte(0x00ff), 0x00);
+        assert_eq!(rom.read_byte(0x0100), 0xc3);
+        assert_eq!(rom.read_byte(0x0102), 0x01);
+    }
+
+    #[test]
+    fn from_parts() {
+        let h = [0x11; 0x800];
+        let g = [0x22; 0x800];
+        let rom = Rom::from_parts(vec![(0x0800, &g), (0x0000, &h)]).unwrap();
+        assert_eq!(rom.len(), 0x1000);
+        assert_eq!(rom.read_byte(0x07ff), 0x11);
+        assert_eq!(rom.read_byte(0x0800), 0x22);
+    }
+
+    #[test]
+    fn overlapping_parts() {
+        let parts = vec![(0x0000, vec![0; 0x10]), (0x000c, vec![0; 0x10])];
+        match Rom::from_parts(parts) {
+            Err(Error::RomOverlap { addr: 0x000c }) => {}
+            r => panic!("expected overlap, got {:?}", r.map(|rom| rom.len())),
+        }
+        match Rom::with_origin([0; 0x10], 0xfff8) {
+            Err(Error::RomTooLarge { .. }) => {}
+            r => panic!("expected too large, got {:?}", r.map(|rom| rom.len())),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn from_files() {
+        let dir = std::env::temp_dir().join(format!("i8080_rom_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let low = dir.join("low.bin");
+        let high = dir.join("high.bin");
+        std::fs::write(&low, [0x01, 0x02]).unwrap();
+        std::fs::write(&high, [0x03]).unwrap();
+        let rom = Rom::from_files(&[(&low, 0x0000), (&high, 0x0004)]).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(rom.len(), 5);
+        assert_eq!(rom.read_byte(0x0001), 0x02);
+        assert_eq!(rom.read_byte(0x0003), 0x00);
+        assert_eq!(rom.read_byte(0x0004), 0x03);
+    }
+}
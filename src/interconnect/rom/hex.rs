@@ -0,0 +1,121 @@
+//! Intel HEX parsing.
+//!
+//! Only the record types meaningful for a 16-bit address space are accepted:
+//! data (00), end of file (01) and start address (03, 05, ignored). Extended
+//! address records (02, 04) are accepted as long as they select the first 64K.
+
+use crate::Error;
+use alloc::vec::Vec;
+
+/// Parses `text` into `(address, data)` chunks.
+pub(super) fn parse(text: &str) -> Result<Vec<(u16, Vec<u8>)>, Error> {
+    let mut chunks = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line_no = i + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let invalid = |reason| Error::InvalidHex {
+            line: line_no,
+            reason,
+        };
+        let record = line
+            .strip_prefix(':')
+            .ok_or_else(|| invalid("record does not start with ':'"))?;
+        if record.len() % 2 != 0 || record.len() < 10 {
+            return Err(invalid("truncated record"));
+        }
+        // Decoded from raw bytes, so multi-byte characters cannot split a
+        // pair and are rejected like any other non hex digit.
+        let bytes = record
+            .as_bytes()
+            .chunks(2)
+            .map(|pair| Some(digit(pair[0])? << 4 | digit(pair[1])?))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(|| invalid("record contains non hex digits"))?;
+
+        let len = bytes[0] as usize;
+        if bytes.len() != len + 5 {
+            return Err(invalid("record length does not match byte count"));
+        }
+        let expected = bytes[len + 4];
+        let found = bytes[..len + 4]
+            .iter()
+            .fold(0u8, |sum, b| sum.wrapping_add(*b))
+            .wrapping_neg();
+        if expected != found {
+            return Err(Error::HexChecksum {
+                line: line_no,
+                expected,
+                found,
+            });
+        }
+
+        let addr = (bytes[1] as u16) << 8 | bytes[2] as u16;
+        let data = &bytes[4..len + 4];
+        match bytes[3] {
+            0x00 => chunks.push((addr, data.to_vec())),
+            0x01 => return Ok(chunks),
+            0x02 | 0x04 if data.iter().all(|&b| b == 0) => {}
+            0x02 | 0x04 => return Err(invalid("address beyond 64K")),
+            0x03 | 0x05 => {}
+            _ => return Err(invalid("unknown record type")),
+        }
+    }
+    Err(Error::InvalidHex {
+        line: text.lines().count(),
+        reason: "missing end of file record",
+    })
+}
+
+fn digit(c: u8) -> Option<u8> {
+    (c as char).to_digit(16).map(|d| d as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use crate::{interconnect::Rom, Error};
+    use alloc::vec;
+
+    const IMAGE: &str = "\
+:03000000310024A8
+:03010000C3000039
+:00000001FF
+";
+
+    #[test]
+    fn parses_records() {
+        let chunks = parse(IMAGE).unwrap();
+        assert_eq!(chunks[0], (0x0000, vec![0x31, 0x00, 0x24]));
+        assert_eq!(chunks[1], (0x0100, vec![0xc3, 0x00, 0x00]));
+        let rom = Rom::from_hex(IMAGE).unwrap();
+        assert_eq!(rom.len(), 0x0103);
+        assert_eq!(rom.read_byte(0x0100), 0xc3);
+    }
+
+    #[test]
+    fn rejects_bad_checksum() {
+        let image = IMAGE.replace(":03010000C3000039", ":03010000C3000038");
+        match parse(&image) {
+            Err(Error::HexChecksum {
+                line: 2,
+                expected: 0x38,
+                found: 0x39,
+            }) => {}
+            r => panic!("expected checksum error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_records() {
+        assert!(parse("0300000031002499\n:00000001FF").is_err());
+        assert!(parse(":03000000310024A8").is_err());
+        assert!(parse(":020000040001F9\n:00000001FF").is_err());
+        match parse(":0€0000310024A8\n:00000001FF") {
+            Err(Error::InvalidHex { line: 1, .. }) => {}
+            r => panic!("expected invalid hex, got {:?}", r),
+        }
+    }
+}
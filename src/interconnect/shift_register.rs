@@ -0,0 +1,68 @@
+use crate::{
+    state::{StateReader, StateWriter},
+    Error,
+};
+
+/// Writes shift the byte into the top of the register.
+pub const SHIFT_DATA_PORT: u8 = 4;
+/// Writes set the shift amount, 0 to 7.
+pub const SHIFT_OFFSET_PORT: u8 = 2;
+/// Reads return the shifted result.
+pub const SHIFT_RESULT_PORT: u8 = 3;
+
+/// The board's 16 bit external shift register.
+///
+/// The 8080 can only shift by one bit at a time, so the game uses this to
+/// move sprites to arbitrary horizontal positions.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShiftRegister {
+    value: u16,
+    offset: u8,
+}
+
+impl ShiftRegister {
+    pub fn new() -> ShiftRegister {
+        ShiftRegister::default()
+    }
+
+    pub fn write_data(&mut self, value: u8) {
+        self.value = (value as u16) << 8 | self.value >> 8;
+    }
+
+    pub fn set_offset(&mut self, value: u8) {
+        self.offset = value & 0x07;
+    }
+
+    pub(crate) fn save_state(&self, w: &mut StateWriter) {
+        w.u16(self.value);
+        w.u8(self.offset);
+    }
+
+    pub(crate) fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.value = r.u16()?;
+        self.offset = r.u8()? & 0x07;
+        Ok(())
+    }
+
+    /// The 8 bits starting `offset` bits below the top of the register.
+    pub fn result(&self) -> u8 {
+        (self.value >> (8 - self.offset)) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ShiftRegister;
+
+    #[test]
+    fn shifts() {
+        let mut shift = ShiftRegister::new();
+        shift.write_data(0xab);
+        shift.write_data(0xcd);
+        assert_eq!(shift.result(), 0xcd);
+        shift.set_offset(4);
+        assert_eq!(shift.result(), 0xda);
+        shift.set_offset(0x0f);
+        assert_eq!(shift.result(), 0xd5);
+    }
+}
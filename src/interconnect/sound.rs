@@ -0,0 +1,230 @@
+use crate::{
+    state::{StateReader, StateWriter},
+    Error,
+};
+use alloc::{boxed::Box, collections::VecDeque, vec::Vec};
+
+/// Port driving the UFO, shot, player death, invader death and extra life circuits.
+pub const SOUND_PORT_1: u8 = 3;
+/// Port driving the fleet movement and UFO hit circuits.
+pub const SOUND_PORT_2: u8 = 5;
+
+/// Undelivered events beyond this are dropped, oldest first.
+const QUEUE_LIMIT: usize = 256;
+
+/// The discrete sound circuits on the Space Invaders board.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Sound {
+    /// Repeats for as long as its bit is held high.
+    Ufo,
+    Shot,
+    PlayerDeath,
+    InvaderDeath,
+    ExtraLife,
+    Fleet1,
+    Fleet2,
+    Fleet3,
+    Fleet4,
+    UfoHit,
+}
+
+/// `(port, bit, sound)` for every sound-producing bit.
+const SOUND_BITS: [(u8, u8, Sound); 10] = [
+    (SOUND_PORT_1, 0, Sound::Ufo),
+    (SOUND_PORT_1, 1, Sound::Shot),
+    (SOUND_PORT_1, 2, Sound::PlayerDeath),
+    (SOUND_PORT_1, 3, Sound::InvaderDeath),
+    (SOUND_PORT_1, 4, Sound::ExtraLife),
+    (SOUND_PORT_2, 0, Sound::Fleet1),
+    (SOUND_PORT_2, 1, Sound::Fleet2),
+    (SOUND_PORT_2, 2, Sound::Fleet3),
+    (SOUND_PORT_2, 3, Sound::Fleet4),
+    (SOUND_PORT_2, 4, Sound::UfoHit),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SoundEvent {
+    /// The sound's bit went high.
+    Start(Sound),
+    /// The sound's bit went low.
+    Stop(Sound),
+}
+
+/// Decodes writes to the sound ports into `SoundEvent`s.
+///
+/// Events can be polled from a queue, or delivered as they happen to
+/// subscribers registered with `subscribe`. Both see every event.
+pub struct SoundPorts {
+    port1: u8,
+    port2: u8,
+    queue: VecDeque<SoundEvent>,
+    subscribers: Vec<Box<dyn FnMut(SoundEvent)>>,
+}
+
+impl Default for SoundPorts {
+    fn default() -> Self {
+        SoundPorts::new()
+    }
+}
+
+impl SoundPorts {
+    pub fn new() -> SoundPorts {
+        SoundPorts {
+            port1: 0,
+            port2: 0,
+            queue: VecDeque::new(),
+            subscribers: Vec::new(),
+        }
+    }
+
+    /// Latches `value` into `port`, emitting an event for every bit that changed.
+    pub fn write(&mut self, port: u8, value: u8) {
+        let previous = match port {
+            SOUND_PORT_1 => core::mem::replace(&mut self.port1, value),
+            SOUND_PORT_2 => core::mem::replace(&mut self.port2, value),
+            _ => return,
+        };
+        let changed = previous ^ value;
+        for &(_, bit, sound) in SOUND_BITS.iter().filter(|(p, _, _)| *p == port) {
+            let mask = 1 << bit;
+            if changed & mask == 0 {
+                continue;
+            }
+            let event = match value & mask {
+                0 => SoundEvent::Stop(sound),
+                _ => SoundEvent::Start(sound),
+            };
+            self.emit(event);
+        }
+    }
+
+    /// Whether the sound circuits are enabled (bit 5 of port 3).
+    pub fn amplifier_enabled(&self) -> bool {
+        self.port1 & 0x20 != 0
+    }
+
+    /// Whether `sound`'s bit is currently held high.
+    pub fn is_playing(&self, sound: Sound) -> bool {
+        SOUND_BITS
+            .iter()
+            .find(|(_, _, s)| *s == sound)
+            .map(|&(port, bit, _)| {
+                let value = match port {
+                    SOUND_PORT_1 => self.port1,
+                    _ => self.port2,
+                };
+                value & (1 << bit) != 0
+            })
+            .unwrap_or(false)
+    }
+
+    /// The latched values of ports 3 and 5.
+    pub(crate) fn latches(&self) -> (u8, u8) {
+        (self.port1, self.port2)
+    }
+
+    /// Restores latches without emitting events.
+    pub(crate) fn set_latches(&mut self, (port1, port2): (u8, u8)) {
+        self.port1 = port1;
+        self.port2 = port2;
+    }
+
+    /// Saves the port latches. Restoring them emits no events.
+    pub(crate) fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.port1);
+        w.u8(self.port2);
+    }
+
+    pub(crate) fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.port1 = r.u8()?;
+        self.port2 = r.u8()?;
+        Ok(())
+    }
+
+    /// Takes the oldest undelivered event.
+    pub fn poll(&mut self) -> Option<SoundEvent> {
+        self.queue.pop_front()
+    }
+
+    /// Takes every undelivered event.
+    pub fn drain(&mut self) -> impl Iterator<Item = SoundEvent> + '_ {
+        self.queue.drain(..)
+    }
+
+    /// Calls `f` with every event from now on.
+    pub fn subscribe<F: FnMut(SoundEvent) + 'static>(&mut self, f: F) {
+        self.subscribers.push(Box::new(f));
+    }
+
+    fn emit(&mut self, event: SoundEvent) {
+        for subscriber in self.subscribers.iter_mut() {
+            subscriber(event);
+        }
+        if self.queue.len() == QUEUE_LIMIT {
+            self.queue.pop_front();
+        }
+        self.queue.push_back(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Sound, SoundEvent, SoundPorts};
+    use crate::Emulator;
+    use alloc::{rc::Rc, vec::Vec};
+    use core::cell::RefCell;
+
+    #[test]
+    fn decodes_edges() {
+        let mut ports = SoundPorts::new();
+        ports.write(3, 0x21);
+        ports.write(3, 0x22);
+        ports.write(5, 0x01);
+        ports.write(5, 0x01);
+        ports.write(4, 0xff);
+        let events: Vec<SoundEvent> = ports.drain().collect();
+        assert_eq!(
+            events,
+            [
+                SoundEvent::Start(Sound::Ufo),
+                SoundEvent::Stop(Sound::Ufo),
+                SoundEvent::Start(Sound::Shot),
+                SoundEvent::Start(Sound::Fleet1),
+            ]
+        );
+        assert!(ports.amplifier_enabled());
+        assert!(ports.is_playing(Sound::Shot));
+        assert!(!ports.is_playing(Sound::Ufo));
+        assert_eq!(ports.poll(), None);
+    }
+
+    #[test]
+    fn subscribers_see_events() {
+        let mut ports = SoundPorts::new();
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let sink = seen.clone();
+        ports.subscribe(move |event| sink.borrow_mut().push(event));
+        ports.write(5, 0x10);
+        ports.write(5, 0x00);
+        assert_eq!(
+            *seen.borrow(),
+            [
+                SoundEvent::Start(Sound::UfoHit),
+                SoundEvent::Stop(Sound::UfoHit)
+            ]
+        );
+        assert_eq!(ports.poll(), Some(SoundEvent::Start(Sound::UfoHit)));
+    }
+
+    #[test]
+    fn out_reaches_sound_ports() {
+        let bytecode = [
+            0x3e, 0x08, // MVI A, 0x08
+            0xd3, 0x03, // OUT 0x03
+        ];
+        let mut system = Emulator::new(bytecode);
+        system.run();
+        let sound = system.interconnect_mut().sound_mut();
+        assert_eq!(sound.poll(), Some(SoundEvent::Start(Sound::InvaderDeath)));
+    }
+}
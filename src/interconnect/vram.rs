@@ -1,4 +1,5 @@
 use crate::mem_map::{VRAM_END, VRAM_START};
+use alloc::boxed::Box;
 
 pub struct Vram {
     bytes: Box<[u8]>,
@@ -12,6 +13,14 @@ impl Vram {
         }
     }
 
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn load(&mut self, bytes: &[u8]) {
+        self.bytes.copy_from_slice(bytes);
+    }
+
     pub fn read_byte(&self, addr: u16) -> u8 {
         self.bytes[addr as usize]
     }
@@ -0,0 +1,203 @@
+use crate::{
+    i8080::I8080,
+    state::{StateReader, StateWriter},
+    Error,
+};
+use alloc::collections::VecDeque;
+use log::warn;
+
+/// Writing any value to this port resets the watchdog.
+pub const WATCHDOG_PORT: u8 = 6;
+
+/// The board's watchdog fires after 255 frames without a write,
+/// a little over four seconds at 2 MHz.
+pub const WATCHDOG_TIMEOUT: u64 = 255 * 2_000_000 / 60;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchdogEvent {
+    /// The game went `cycles` cycles without writing to the watchdog port and
+    /// the cpu was reset while at `pc`.
+    Expired { pc: u16, cycles: u64 },
+}
+
+/// Tracks writes to the watchdog port.
+///
+/// The watchdog is disabled by default; writes are still counted so hosts can
+/// inspect them. Once enabled, `tick` reports when the timeout has passed
+/// without a write, which the machine answers by resetting the cpu.
+pub struct Watchdog {
+    enabled: bool,
+    timeout: u64,
+    since_kick: u64,
+    kicks: u64,
+    events: VecDeque<WatchdogEvent>,
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Watchdog::new()
+    }
+}
+
+impl Watchdog {
+    pub fn new() -> Watchdog {
+        Watchdog {
+            enabled: false,
+            timeout: WATCHDOG_TIMEOUT,
+            since_kick: 0,
+            kicks: 0,
+            events: VecDeque::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.since_kick = 0;
+    }
+
+    pub fn timeout(&self) -> u64 {
+        self.timeout
+    }
+
+    /// Sets the timeout in cpu cycles.
+    pub fn set_timeout(&mut self, cycles: u64) {
+        self.timeout = cycles;
+    }
+
+    /// Number of writes to the watchdog port so far.
+    pub fn kicks(&self) -> u64 {
+        self.kicks
+    }
+
+    pub fn cycles_since_kick(&self) -> u64 {
+        self.since_kick
+    }
+
+    pub(crate) fn kick(&mut self) {
+        self.kicks += 1;
+        self.since_kick = 0;
+    }
+
+    /// Advances the watchdog by `cycles`, returning true when it fires.
+    pub(crate) fn tick(&mut self, cycles: u64, pc: u16) -> bool {
+        self.since_kick += cycles;
+        if !self.enabled || self.since_kick < self.timeout {
+            return false;
+        }
+        self.events.push_back(WatchdogEvent::Expired {
+            pc,
+            cycles: self.since_kick,
+        });
+        self.since_kick = 0;
+        true
+    }
+
+    /// Advances the watchdog by the `cycles` `cpu` just ran for, resetting the
+    /// cpu if it fires.
+    pub(crate) fn service(&mut self, cpu: &mut I8080, cycles: u64) {
+        let pc = cpu.pc();
+        if self.tick(cycles, pc) {
+            warn!("watchdog expired at 0x{:04x}, resetting cpu", pc);
+            cpu.reset();
+        }
+    }
+
+    /// Cycles since the last kick, and the number of kicks.
+    pub(crate) fn counters(&self) -> (u64, u64) {
+        (self.since_kick, self.kicks)
+    }
+
+    pub(crate) fn set_counters(&mut self, (since_kick, kicks): (u64, u64)) {
+        self.since_kick = since_kick;
+        self.kicks = kicks;
+    }
+
+    pub(crate) fn save_state(&self, w: &mut StateWriter) {
+        w.u64(self.since_kick);
+        w.u64(self.kicks);
+    }
+
+    pub(crate) fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.since_kick = r.u64()?;
+        self.kicks = r.u64()?;
+        Ok(())
+    }
+
+    /// Takes the oldest undelivered event.
+    pub fn poll(&mut self) -> Option<WatchdogEvent> {
+        self.events.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Watchdog, WatchdogEvent};
+    use crate::Emulator;
+
+    #[test]
+    fn fires_only_when_enabled() {
+        let mut watchdog = Watchdog::new();
+        watchdog.set_timeout(100);
+        assert!(!watchdog.tick(150, 0x0010));
+        watchdog.set_enabled(true);
+        assert!(!watchdog.tick(60, 0x0010));
+        watchdog.kick();
+        assert!(!watchdog.tick(60, 0x0010));
+        assert!(watchdog.tick(40, 0x0020));
+        assert_eq!(
+            watchdog.poll(),
+            Some(WatchdogEvent::Expired {
+                pc: 0x0020,
+                cycles: 100
+            })
+        );
+        assert_eq!(watchdog.kicks(), 1);
+        assert_eq!(watchdog.cycles_since_kick(), 0);
+    }
+
+    #[test]
+    fn resets_hung_cpu() {
+        let bytecode = [
+            0x31, 0x00, 0x24, // LXI SP, 0x2400
+            0xc3, 0x03, 0x00, // JMP 0x0003
+        ];
+        let mut system = Emulator::new(bytecode);
+        let watchdog = system.interconnect_mut().watchdog_mut();
+        watchdog.set_enabled(true);
+        watchdog.set_timeout(100);
+        for _ in 0..10 {
+            system.step();
+        }
+        assert_eq!(system.cpu().pc(), 0x0000);
+        let watchdog = system.interconnect_mut().watchdog_mut();
+        assert_eq!(
+            watchdog.poll(),
+            Some(WatchdogEvent::Expired {
+                pc: 0x0003,
+                cycles: 100
+            })
+        );
+    }
+
+    #[test]
+    fn kicked_cpu_keeps_running() {
+        let bytecode = [
+            0xd3, 0x06, // OUT 0x06
+            0xc3, 0x00, 0x00, // JMP 0x0000
+        ];
+        let mut system = Emulator::new(bytecode);
+        let watchdog = system.interconnect_mut().watchdog_mut();
+        watchdog.set_enabled(true);
+        watchdog.set_timeout(100);
+        for _ in 0..100 {
+            system.step();
+        }
+        let watchdog = system.interconnect_mut().watchdog_mut();
+        assert_eq!(watchdog.poll(), None);
+        assert_eq!(watchdog.kicks(), 50);
+    }
+}
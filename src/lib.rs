@@ -1,17 +1,57 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+// An instruction's length is never zero, so `Instruction::len` has no
+// `is_empty` to go with it.
+#![allow(clippy::len_without_is_empty)]
+// The older tests compare flags with `assert_eq!(.., true)` and lend the
+// bytecode to `Emulator::new`.
+#![cfg_attr(
+    test,
+    allow(
+        clippy::bool_assert_comparison,
+        clippy::needless_borrows_for_generic_args
+    )
+)]
+
+extern crate alloc;
+
+#[cfg(feature = "audio")]
+pub mod audio;
+mod bus;
+pub mod cheats;
+pub mod coverage;
+#[cfg(feature = "dap")]
+pub mod dap;
+pub mod debugger;
+mod error;
+#[cfg(feature = "gdb")]
+pub mod gdb;
+pub mod heatmap;
 pub mod i8080;
 pub mod instruction;
 pub mod interconnect;
+#[cfg(feature = "std")]
+pub mod listing;
+pub mod machine;
+pub mod manifest;
+pub mod patch;
+pub mod profiler;
+#[cfg(feature = "std")]
+pub mod screenshot;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod symbols;
 
 pub(crate) mod mem_map;
+pub(crate) mod state;
+
+pub use self::bus::{Bus, Probe, Probed};
+pub use self::error::Error;
 
 use log::error;
 
 use self::i8080::I8080;
-use self::instruction::{Instruction, Opcode};
 use self::interconnect::{Interconnect, Rom};
 
-use failure::Error;
-
 pub struct Emulator {
     cpu: I8080,
     interconnect: Interconnect,
@@ -26,65 +66,56 @@ impl Emulator {
     }
 
     pub fn step(&mut self) {
-        if let Some(instruction) = self.next_instruction() {
-            if let Err(e) = self
-                .cpu
-                .emulate_instruction(instruction, &mut self.interconnect)
-            {
-                error!("{}", e);
-            }
+        if let Err(e) = self.try_step() {
+            error!("{}", e);
         }
     }
 
     pub fn try_step(&mut self) -> Result<(), Error> {
-        if let Some(instruction) = self.next_instruction() {
-            self.cpu
-                .emulate_instruction(instruction, &mut self.interconnect)?;
+        self.try_step_probed(&mut ())
+    }
+
+    /// Like `try_step`, reporting every memory access the instruction makes.
+    pub fn try_step_probed<P: Probe>(&mut self, probe: &mut P) -> Result<(), Error> {
+        if self.has_next_instruction() {
+            self.execute(probe)?;
         }
         Ok(())
     }
 
     pub fn run(&mut self) {
-        while let Some(instruction) = self.next_instruction() {
-            if let Err(e) = self
-                .cpu
-                .emulate_instruction(instruction, &mut self.interconnect)
-            {
-                error!("{}", e);
-                break;
-            }
+        if let Err(e) = self.try_run() {
+            error!("{}", e);
         }
     }
 
+    /// Runs until the cpu leaves the rom or halts.
     pub fn try_run(&mut self) -> Result<(), Error> {
-        while let Some(instruction) = self.next_instruction() {
-            self.cpu
-                .emulate_instruction(instruction, &mut self.interconnect)?
+        while self.has_next_instruction() {
+            self.execute(&mut ())?;
         }
         Ok(())
     }
 
-    fn next_instruction(&self) -> Option<Instruction> {
-        use self::instruction::opcode::OpcodeSize;
-        if (self.cpu.pc() as usize) >= self.interconnect.rom_len() {
-            None
-        } else {
-            let opcode = Opcode::from(self.interconnect.read_byte(self.cpu.pc()));
-            let instruction = match opcode.size() {
-                OpcodeSize::Binary => {
-                    let data = self.interconnect.read_byte(self.cpu.pc() + 1);
-                    Instruction::new_binary(opcode, data).unwrap()
-                }
-                OpcodeSize::Trinary => {
-                    let data_low = self.interconnect.read_byte(self.cpu.pc() + 1) as u16;
-                    let data_high = self.interconnect.read_byte(self.cpu.pc() + 2) as u16;
-                    let addr = (data_high << 8) | data_low;
-                    Instruction::new_trinary(opcode, addr).unwrap()
-                }
-                OpcodeSize::Unary => Instruction::new_unary(opcode).unwrap(),
-            };
-            Some(instruction)
-        }
+    fn execute<P: Probe>(&mut self, probe: &mut P) -> Result<(), Error> {
+        let cycles = self.cpu.cycles();
+        self.cpu
+            .step(&mut Probed::new(&mut self.interconnect, probe))?;
+        let elapsed = self.cpu.cycles() - cycles;
+        self.interconnect
+            .watchdog_mut()
+            .service(&mut self.cpu, elapsed);
+        Ok(())
+    }
+
+    /// Whether the cpu has halted or run off the end of the rom, after which
+    /// stepping does nothing.
+    pub fn is_finished(&self) -> bool {
+        !self.has_next_instruction()
+    }
+
+    fn has_next_instruction(&self) -> bool {
+        !self.cpu.is_halted() && (self.cpu.pc() as usize) < self.interconnect.rom_len()
     }
 
     pub fn cpu(&self) -> &I8080 {
@@ -0,0 +1,139 @@
+//! Assembler listings, mapping source lines to the addresses they assembled
+//! to.
+//!
+//! A line maps to an address if it starts with a four digit hex address
+//! followed by the code bytes, optionally after a line number:
+//!
+//! ```text
+//!     5 0006 32 00 20         STA 2000H
+//! ```
+//!
+//! Lines whose source is a `DB`, `DW` or `DS` directive hold data rather
+//! than code.
+
+use crate::Error;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Line {
+    addr: u16,
+    data: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Listing {
+    path: PathBuf,
+    /// By 1-based line number.
+    lines: BTreeMap<u64, Line>,
+    /// The first code line at each address.
+    addresses: BTreeMap<u16, u64>,
+}
+
+impl Listing {
+    pub fn parse(path: PathBuf, text: &str) -> Listing {
+        let mut listing = Listing {
+            path,
+            ..Listing::default()
+        };
+        for (i, text) in text.lines().enumerate() {
+            if let Some(line) = parse_line(text) {
+                let number = i as u64 + 1;
+                listing.lines.insert(number, line);
+                if !line.data {
+                    listing.addresses.entry(line.addr).or_insert(number);
+                }
+            }
+        }
+        listing
+    }
+
+    pub fn load(path: &Path) -> Result<Listing, Error> {
+        Ok(Listing::parse(
+            path.to_path_buf(),
+            &fs::read_to_string(path)?,
+        ))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The first line at or after `line` with code on it, and its address.
+    pub fn line_address(&self, line: u64) -> Option<(u64, u16)> {
+        self.lines
+            .range(line..)
+            .find(|(_, l)| !l.data)
+            .map(|(&number, l)| (number, l.addr))
+    }
+
+    /// The code line that assembled to `addr`.
+    pub fn address_line(&self, addr: u16) -> Option<u64> {
+        self.addresses.get(&addr).copied()
+    }
+
+    /// Every line with code on it and its address, in line order.
+    pub fn code_lines(&self) -> impl Iterator<Item = (u64, u16)> + '_ {
+        self.lines
+            .iter()
+            .filter(|(_, l)| !l.data)
+            .map(|(&number, l)| (number, l.addr))
+    }
+}
+
+/// `0100 C3 00 02 START: JMP 0200`, possibly after a line number.
+fn parse_line(line: &str) -> Option<Line> {
+    let mut tokens = line.split_whitespace();
+    let is_addr = |t: &str| t.len() == 4 && t.chars().all(|c| c.is_ascii_hexdigit());
+    let mut token = tokens.next()?.trim_end_matches(':');
+    if !is_addr(token) {
+        token = tokens.next()?.trim_end_matches(':');
+    }
+    let code = tokens.next()?;
+    let is_code = code.len() >= 2 && code.chars().all(|c| c.is_ascii_hexdigit());
+    if !is_addr(token) || !is_code {
+        return None;
+    }
+    let data = tokens.any(|t| {
+        ["DB", "DW", "DS", ".BYTE", ".WORD"]
+            .iter()
+            .any(|d| t.eq_ignore_ascii_case(d))
+    });
+    Some(Line {
+        addr: u16::from_str_radix(token, 16).ok()?,
+        data,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Listing;
+    use std::path::PathBuf;
+
+    const LISTING: &str = "\
+; counter
+    1 0000 31 00 24         LXI SP,2400H
+    2 0003 3E 00            MVI A,0
+    3                  LOOP:
+    4 0005 3C               INR A
+    5 0006 32 00 20         STA 2000H
+    6 0009 C3 05 00         JMP LOOP
+    7 000C 48 49       MSG: DB 'HI'
+    8 000E C9          SUB: RET
+";
+
+    #[test]
+    fn maps_lines_to_addresses() {
+        let listing = Listing::parse(PathBuf::from("counter.lst"), LISTING);
+        assert_eq!(listing.line_address(4), Some((5, 0x0005)));
+        assert_eq!(listing.address_line(0x0006), Some(6));
+        assert_eq!(listing.line_address(1), Some((2, 0x0000)));
+        assert_eq!(listing.line_address(8), Some((9, 0x000e)));
+        assert_eq!(listing.address_line(0x000c), None);
+        assert_eq!(listing.line_address(10), None);
+        assert_eq!(listing.code_lines().count(), 6);
+    }
+}
@@ -0,0 +1,451 @@
+//! The Space Invaders arcade board.
+
+use crate::{
+    cheats::Cheats,
+    heatmap::Heatmap,
+    i8080::{CallStack, Registers, I8080},
+    interconnect::{GamePad, Interconnect, Latches, Rom},
+    profiler::Profiler,
+    state::{StateReader, StateWriter},
+    Error, Probe, Probed,
+};
+use alloc::{boxed::Box, vec::Vec};
+
+mod frame;
+mod movie;
+mod rewind;
+pub(crate) use self::frame::screen_position;
+pub use self::frame::{overlay_color, Frame, SCREEN_HEIGHT, SCREEN_WIDTH};
+pub use self::movie::{Movie, MovieFrame, Replay};
+pub use self::rewind::Rewind;
+
+/// Cycles per 60 Hz video frame at 2 MHz.
+pub const CYCLES_PER_FRAME: u64 = 33_333;
+
+/// The cycle within a frame at which the beam reaches mid-screen and the
+/// board raises `RST 1`. `RST 2` follows at the end of the frame, at VBlank.
+pub const MID_FRAME: u64 = CYCLES_PER_FRAME / 2;
+
+/// Identifies a save state, followed by a format version byte.
+const STATE_MAGIC: &[u8; 8] = b"SI8080ST";
+const STATE_VERSION: u8 = 2;
+
+/// Everything an instruction can change on the board apart from memory.
+///
+/// Only the call stack, and only while call tracking is on, is kept on the
+/// heap.
+pub(crate) struct Checkpoint {
+    registers: Registers,
+    call_stack: Option<Box<CallStack>>,
+    /// None once `forget_unchanged_latches` finds they weren't changed.
+    latches: Option<Latches>,
+    watchdog: (u64, u64),
+    frame_start: u64,
+    mid_frame: bool,
+    frames: u64,
+}
+
+/// A complete machine: cpu, memory, shift register, inputs, sound ports and
+/// watchdog, driven a frame at a time.
+pub struct SpaceInvaders {
+    cpu: I8080,
+    interconnect: Interconnect,
+    frame: Frame,
+    /// Cpu cycle count at which the current frame began.
+    frame_start: u64,
+    /// Whether the current frame's `RST 1` has been raised.
+    mid_frame: bool,
+    frames: u64,
+    profiler: Option<Box<Profiler>>,
+    heatmap: Option<Box<Heatmap>>,
+    cheats: Cheats,
+}
+
+impl SpaceInvaders {
+    /// Builds the machine as it stands at power on, with interrupts disabled.
+    pub fn new<T: Into<Rom>>(rom: T) -> SpaceInvaders {
+        let mut cpu = I8080::new();
+        cpu.reset();
+        SpaceInvaders {
+            cpu,
+            interconnect: Interconnect::new(rom.into()),
+            frame: Frame::new(),
+            frame_start: 0,
+            mid_frame: false,
+            frames: 0,
+            profiler: None,
+            heatmap: None,
+            cheats: Cheats::new(),
+        }
+    }
+
+    /// Runs one video frame and returns the picture at its end.
+    ///
+    /// Instructions straddling a frame boundary are finished, and the extra
+    /// cycles are taken from the next frame so the machine keeps time.
+    pub fn run_frame(&mut self) -> Result<&Frame, Error> {
+        let frames = self.frames;
+        while self.frames == frames {
+            self.step()?;
+        }
+        Ok(&self.frame)
+    }
+
+    /// Executes a single instruction, then raises the video interrupt if the
+    /// beam has reached mid-screen or the end of the frame.
+    pub fn step(&mut self) -> Result<(), Error> {
+        self.step_probed(&mut ())
+    }
+
+    /// Like `step`, reporting every memory access made by the instruction
+    /// and any interrupt it leads to.
+    pub fn step_probed<P: Probe>(&mut self, probe: &mut P) -> Result<(), Error> {
+        let pc = self.cpu.pc();
+        let frames = self.frames;
+        let elapsed = match self.heatmap.take() {
+            Some(mut heatmap) => {
+                let fetch = Some(pc).filter(|_| !self.cpu.is_halted());
+                heatmap.begin_step(&self.interconnect, fetch);
+                let elapsed = self.execute(&mut (probe, &mut *heatmap));
+                if self.frames != frames {
+                    heatmap.end_frame();
+                }
+                self.heatmap = Some(heatmap);
+                elapsed?
+            }
+            None => self.execute(probe)?,
+        };
+        if let Some(profiler) = &mut self.profiler {
+            if self.frames != frames {
+                profiler.end_frame();
+            }
+            let frames = self.cpu.call_stack().map_or(&[][..], |s| s.frames());
+            profiler.record(pc, elapsed, frames);
+        }
+        Ok(())
+    }
+
+    /// Runs an instruction and any interrupt due after it, returning the
+    /// cycles the instruction took.
+    fn execute<P: Probe>(&mut self, probe: &mut P) -> Result<u64, Error> {
+        let cycles = self.cpu.cycles();
+        self.cpu
+            .step(&mut Probed::new(&mut self.interconnect, probe))?;
+        let elapsed = self.cpu.cycles() - cycles;
+        self.interconnect
+            .watchdog_mut()
+            .service(&mut self.cpu, elapsed);
+
+        let mut bus = Probed::new(&mut self.interconnect, probe);
+        if !self.mid_frame && self.cpu.cycles() >= self.frame_start + MID_FRAME {
+            self.cpu.interrupt(1, &mut bus)?;
+            self.mid_frame = true;
+        }
+        if self.mid_frame && self.cpu.cycles() >= self.frame_start + CYCLES_PER_FRAME {
+            // Written through the probe so the debugger can undo them.
+            self.cheats.apply(&mut bus);
+            self.cpu.interrupt(2, &mut bus)?;
+            self.frame_start += CYCLES_PER_FRAME;
+            self.mid_frame = false;
+            self.frames += 1;
+            self.frame.render(self.interconnect.vram());
+        }
+        Ok(elapsed)
+    }
+
+    /// Starts profiling every instruction the machine runs, or stops if
+    /// given None. Profiling turns on the cpu's call tracking, which it
+    /// needs to charge time to subroutines.
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) {
+        if profiler.is_some() {
+            self.cpu.set_call_tracking(true);
+        }
+        self.profiler = profiler.map(Box::new);
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_deref()
+    }
+
+    /// Stops profiling and returns the results.
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take().map(|p| *p)
+    }
+
+    /// Starts counting memory accesses and recording video ram writes, or
+    /// stops if given None.
+    pub fn set_heatmap(&mut self, heatmap: Option<Heatmap>) {
+        self.heatmap = heatmap.map(Box::new);
+    }
+
+    pub fn heatmap(&self) -> Option<&Heatmap> {
+        self.heatmap.as_deref()
+    }
+
+    pub fn heatmap_mut(&mut self) -> Option<&mut Heatmap> {
+        self.heatmap.as_deref_mut()
+    }
+
+    /// Stops counting and returns the counts.
+    pub fn take_heatmap(&mut self) -> Option<Heatmap> {
+        self.heatmap.take().map(|h| *h)
+    }
+
+    /// The bytes written back at the end of every frame, just before the
+    /// VBlank interrupt.
+    pub fn cheats(&self) -> &Cheats {
+        &self.cheats
+    }
+
+    pub fn cheats_mut(&mut self) -> &mut Cheats {
+        &mut self.cheats
+    }
+
+    pub fn set_cheats(&mut self, cheats: Cheats) {
+        self.cheats = cheats;
+    }
+
+    pub(crate) fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            registers: self.cpu.registers(),
+            call_stack: self.cpu.call_stack().cloned().map(Box::new),
+            latches: Some(self.interconnect.latches()),
+            watchdog: self.interconnect.watchdog().counters(),
+            frame_start: self.frame_start,
+            mid_frame: self.mid_frame,
+            frames: self.frames,
+        }
+    }
+
+    /// Returns to a checkpoint. Memory is left to the caller.
+    pub(crate) fn restore(&mut self, checkpoint: &Checkpoint) {
+        self.cpu.set_registers(checkpoint.registers);
+        if let (Some(stack), Some(saved)) = (self.cpu.call_stack_mut(), &checkpoint.call_stack) {
+            stack.clone_from(saved);
+        }
+        if let Some(latches) = checkpoint.latches {
+            self.interconnect.set_latches(latches);
+        }
+        self.interconnect
+            .watchdog_mut()
+            .set_counters(checkpoint.watchdog);
+        self.frame_start = checkpoint.frame_start;
+        self.mid_frame = checkpoint.mid_frame;
+        if self.frames != checkpoint.frames {
+            self.frames = checkpoint.frames;
+            self.frame.render(self.interconnect.vram());
+        }
+    }
+
+    /// Drops the checkpoint's latches if the machine still holds them, so
+    /// only instructions that `OUT` to a latch keep a copy. The checkpoint
+    /// can then only be restored over the state that immediately followed it.
+    pub(crate) fn forget_unchanged_latches(&self, checkpoint: &mut Checkpoint) {
+        if checkpoint.latches == Some(self.interconnect.latches()) {
+            checkpoint.latches = None;
+        }
+    }
+
+    /// Presses the board's reset button.
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+    }
+
+    /// Captures everything needed to resume emulation from this point.
+    ///
+    /// Inputs and configuration such as DIP switches and the watchdog timeout
+    /// belong to the host and are not included.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        w.bytes(STATE_MAGIC);
+        w.u8(STATE_VERSION);
+        w.u32(self.interconnect.rom().crc32());
+        w.u64(self.frame_start);
+        w.bool(self.mid_frame);
+        w.u64(self.frames);
+        self.cpu.save_state(&mut w);
+        self.interconnect.save_state(&mut w);
+        w.finish()
+    }
+
+    /// Restores a state from `save_state`.
+    ///
+    /// Fails without changing anything if the state is corrupt or was saved
+    /// with a different rom.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), Error> {
+        let mut r = StateReader::new(state);
+        if r.bytes(STATE_MAGIC.len())? != STATE_MAGIC || r.u8()? != STATE_VERSION {
+            return Err(Error::InvalidState {
+                reason: "not a save state or an unsupported version",
+            });
+        }
+        if r.u32()? != self.interconnect.rom().crc32() {
+            return Err(Error::InvalidState {
+                reason: "saved with a different rom",
+            });
+        }
+        // Every field has a fixed size, so a state of the right length loads
+        // without failing part way through.
+        if state.len() != self.save_state().len() {
+            return Err(Error::InvalidState {
+                reason: "wrong length",
+            });
+        }
+        self.frame_start = r.u64()?;
+        self.mid_frame = r.bool()?;
+        self.frames = r.u64()?;
+        self.cpu.load_state(&mut r)?;
+        self.interconnect.load_state(&mut r)?;
+        r.finish()?;
+        self.frame.render(self.interconnect.vram());
+        Ok(())
+    }
+
+    /// The picture rendered at the end of the last frame.
+    pub fn frame(&self) -> &Frame {
+        &self.frame
+    }
+
+    /// Frames run so far.
+    pub fn frame_count(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn game_pad(&self) -> &GamePad {
+        self.interconnect.game_pad()
+    }
+
+    pub fn game_pad_mut(&mut self) -> &mut GamePad {
+        self.interconnect.game_pad_mut()
+    }
+
+    pub fn cpu(&self) -> &I8080 {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut I8080 {
+        &mut self.cpu
+    }
+
+    pub fn interconnect(&self) -> &Interconnect {
+        &self.interconnect
+    }
+
+    pub fn interconnect_mut(&mut self) -> &mut Interconnect {
+        &mut self.interconnect
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SpaceInvaders, CYCLES_PER_FRAME, SCREEN_HEIGHT};
+    use crate::interconnect::Button;
+
+    /// Counts RST 1 at 0x2000 and RST 2 at 0x2001 while halted in between.
+    fn interrupt_counter() -> [u8; 0x39] {
+        let mut rom = [0; 0x39];
+        rom[..8].copy_from_slice(&[
+            0x31, 0x00, 0x24, // LXI SP, 0x2400
+            0xfb, // EI
+            0x76, // HLT
+            0xc3, 0x04, 0x00, // JMP 0x0004
+        ]);
+        rom[0x08..0x0b].copy_from_slice(&[0xc3, 0x20, 0x00]); // JMP 0x0020
+        rom[0x10..0x13].copy_from_slice(&[0xc3, 0x30, 0x00]); // JMP 0x0030
+        for (base, counter) in [(0x20, 0x00), (0x30, 0x01)].iter() {
+            rom[*base..*base + 9].copy_from_slice(&[
+                0x3a,
+                *counter as u8,
+                0x20, // LDA counter
+                0x3c, // INR A
+                0x32,
+                *counter as u8,
+                0x20, // STA counter
+                0xfb, // EI
+                0xc9, // RET
+            ]);
+        }
+        rom
+    }
+
+    #[test]
+    fn interrupts_twice_per_frame() {
+        let mut machine = SpaceInvaders::new(interrupt_counter());
+        for _ in 0..3 {
+            machine.run_frame().unwrap();
+        }
+        assert_eq!(machine.frame_count(), 3);
+        assert_eq!(machine.interconnect().read_byte(0x2000), 3);
+        // The last VBlank handler only starts running in the next frame.
+        assert_eq!(machine.interconnect().read_byte(0x2001), 2);
+        assert_eq!(machine.cpu().pc(), 0x0010);
+        let frames = 3 * CYCLES_PER_FRAME;
+        assert!((frames..frames + 32).contains(&machine.cpu().cycles()));
+    }
+
+    #[test]
+    fn pc_wraps_around_memory() {
+        // After the first interrupt disables interrupts, the cpu runs NOPs
+        // through the whole address space and back to 0.
+        let mut machine = SpaceInvaders::new([0; 0x10]);
+        for _ in 0..10 {
+            machine.run_frame().unwrap();
+        }
+        assert!(machine.cpu().pc() < 0x8000);
+    }
+
+    #[test]
+    fn renders_vram() {
+        let bytecode = [
+            0x3e, 0xff, // MVI A, 0xff
+            0x32, 0x00, 0x24, // STA 0x2400
+            0x76, // HLT
+        ];
+        let mut machine = SpaceInvaders::new(bytecode);
+        let frame = machine.run_frame().unwrap();
+        assert!((SCREEN_HEIGHT - 8..SCREEN_HEIGHT).all(|y| frame.pixel(0, y)));
+        assert_eq!(frame.pixels().iter().filter(|&&p| p).count(), 8);
+    }
+
+    #[test]
+    fn reads_inputs_and_shift_register() {
+        let bytecode = [
+            0x3e, 0xab, // MVI A, 0xab
+            0xd3, 0x04, // OUT 4
+            0x3e, 0xcd, // MVI A, 0xcd
+            0xd3, 0x04, // OUT 4
+            0x3e, 0x04, // MVI A, 0x04
+            0xd3, 0x02, // OUT 2
+            0xdb, 0x03, // IN 3
+            0x32, 0x00, 0x20, // STA 0x2000
+            0xdb, 0x01, // IN 1
+            0x32, 0x01, 0x20, // STA 0x2001
+            0x76, // HLT
+        ];
+        let mut machine = SpaceInvaders::new(bytecode);
+        machine.game_pad_mut().press(Button::Coin);
+        machine.run_frame().unwrap();
+        assert_eq!(machine.interconnect().read_byte(0x2000), 0xda);
+        assert_eq!(machine.interconnect().read_byte(0x2001), 0x09);
+        assert_eq!(machine.interconnect().read_byte(0x4001), 0x09);
+    }
+
+    #[test]
+    fn save_and_load_state() {
+        let mut machine = SpaceInvaders::new(interrupt_counter());
+        machine.run_frame().unwrap();
+        let state = machine.save_state();
+        machine.run_frame().unwrap();
+        let after = machine.save_state();
+
+        machine.load_state(&state).unwrap();
+        assert_eq!(machine.frame_count(), 1);
+        assert_eq!(machine.save_state(), state);
+        machine.run_frame().unwrap();
+        assert_eq!(machine.save_state(), after);
+
+        assert!(machine.load_state(&state[..state.len() - 1]).is_err());
+        let mut other = SpaceInvaders::new([0x76]);
+        assert!(other.load_state(&state).is_err());
+    }
+}
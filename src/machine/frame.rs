@@ -0,0 +1,230 @@
+use alloc::{string::String, vec, vec::Vec};
+
+/// Width of the upright screen in pixels.
+pub const SCREEN_WIDTH: usize = 224;
+/// Height of the upright screen in pixels.
+pub const SCREEN_HEIGHT: usize = 256;
+
+const WHITE: u32 = 0xff_ff_ff;
+const RED: u32 = 0xff_20_20;
+const GREEN: u32 = 0x20_ff_20;
+
+/// The colour, as `0xRRGGBB`, of a lit pixel seen through the cabinet's
+/// cellophane overlay: red across the UFO's band, green over the shields and
+/// player, and green under the reserve ships at the bottom left.
+pub fn overlay_color(x: usize, y: usize) -> u32 {
+    match y {
+        32..=63 => RED,
+        184..=239 => GREEN,
+        240..=255 if (16..134).contains(&x) => GREEN,
+        _ => WHITE,
+    }
+}
+
+/// A 1 bit image of the screen as the player sees it.
+///
+/// The monitor is mounted rotated, so video ram holds the picture turned a
+/// quarter turn clockwise: each run of 32 bytes is one column, bottom to top,
+/// with the low bit of each byte lowest on screen.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Frame {
+    pixels: Vec<bool>,
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Frame::new()
+    }
+}
+
+impl Frame {
+    pub fn new() -> Frame {
+        Frame {
+            pixels: vec![false; SCREEN_WIDTH * SCREEN_HEIGHT],
+        }
+    }
+
+    /// Builds a frame from pixels listed row by row from the top left.
+    ///
+    /// # Panics
+    /// If there are not exactly `SCREEN_WIDTH * SCREEN_HEIGHT` pixels.
+    pub fn from_pixels(pixels: Vec<bool>) -> Frame {
+        assert_eq!(pixels.len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+        Frame { pixels }
+    }
+
+    /// Renders the bitmap in `vram`.
+    pub fn from_vram(vram: &[u8]) -> Frame {
+        let mut frame = Frame::new();
+        frame.render(vram);
+        frame
+    }
+
+    pub fn width(&self) -> usize {
+        SCREEN_WIDTH
+    }
+
+    pub fn height(&self) -> usize {
+        SCREEN_HEIGHT
+    }
+
+    /// Whether the pixel `x` across and `y` down from the top left is lit.
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        self.pixels[y * SCREEN_WIDTH + x]
+    }
+
+    /// Every pixel, row by row from the top left.
+    pub fn pixels(&self) -> &[bool] {
+        &self.pixels
+    }
+
+    /// Number of pixels that differ between two frames.
+    pub fn diff(&self, other: &Frame) -> usize {
+        self.pixels
+            .iter()
+            .zip(other.pixels.iter())
+            .filter(|(a, b)| a != b)
+            .count()
+    }
+
+    /// CRC32 of the picture, cheap to store per frame and compare later.
+    pub fn crc32(&self) -> u32 {
+        let mut hasher = crc32fast::Hasher::new();
+        for row in self.pixels.chunks(SCREEN_WIDTH) {
+            let mut packed = [0; SCREEN_WIDTH / 8];
+            for (i, _) in row.iter().enumerate().filter(|(_, &lit)| lit) {
+                packed[i / 8] |= 1 << (i % 8);
+            }
+            hasher.update(&packed);
+        }
+        hasher.finalize()
+    }
+
+    /// Every pixel as `0xRRGGBB`, black when unlit, white or the overlay's
+    /// colour when lit.
+    pub fn to_rgb(&self, overlay: bool) -> Vec<u32> {
+        self.pixels
+            .iter()
+            .enumerate()
+            .map(|(i, &lit)| match (lit, overlay) {
+                (false, _) => 0,
+                (true, false) => WHITE,
+                (true, true) => overlay_color(i % SCREEN_WIDTH, i / SCREEN_WIDTH),
+            })
+            .collect()
+    }
+
+    /// The screen as text, two pixel rows per line using half block
+    /// characters: 224 columns by 128 lines.
+    pub fn to_half_blocks(&self) -> Vec<String> {
+        (0..SCREEN_HEIGHT)
+            .step_by(2)
+            .map(|y| {
+                (0..SCREEN_WIDTH)
+                    .map(|x| match (self.pixel(x, y), self.pixel(x, y + 1)) {
+                        (false, false) => ' ',
+                        (true, false) => '\u{2580}',
+                        (false, true) => '\u{2584}',
+                        (true, true) => '\u{2588}',
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// The screen as text, a 2x4 block of pixels per braille character:
+    /// 112 columns by 64 lines.
+    pub fn to_braille(&self) -> Vec<String> {
+        // Dot bit for each pixel of a cell, indexed by [row][column].
+        const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+        (0..SCREEN_HEIGHT)
+            .step_by(4)
+            .map(|y| {
+                (0..SCREEN_WIDTH)
+                    .step_by(2)
+                    .map(|x| {
+                        let mut bits = 0;
+                        for (dy, row) in DOTS.iter().enumerate() {
+                            for (dx, dot) in row.iter().enumerate() {
+                                if self.pixel(x + dx, y + dy) {
+                                    bits |= dot;
+                                }
+                            }
+                        }
+                        core::char::from_u32(0x2800 + bits).unwrap()
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    pub(crate) fn render(&mut self, vram: &[u8]) {
+        for (i, &byte) in vram.iter().enumerate().take(SCREEN_WIDTH * 32) {
+            for bit in 0..8 {
+                let (x, y) = screen_position(i, bit);
+                self.pixels[y * SCREEN_WIDTH + x] = byte & (1 << bit) != 0;
+            }
+        }
+    }
+}
+
+/// Where on the upright screen bit `bit` of video ram byte `offset` shows.
+pub(crate) fn screen_position(offset: usize, bit: usize) -> (usize, usize) {
+    (offset / 32, SCREEN_HEIGHT - 1 - ((offset % 32) * 8 + bit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Frame, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+    #[test]
+    fn rotates_vram() {
+        let mut vram = [0; 0x1c00];
+        vram[0] = 0x01;
+        vram[31] = 0x80;
+        vram[0x1bff] = 0x80;
+        let frame = Frame::from_vram(&vram);
+        assert!(frame.pixel(0, SCREEN_HEIGHT - 1));
+        assert!(frame.pixel(0, 0));
+        assert!(frame.pixel(SCREEN_WIDTH - 1, 0));
+        assert_eq!(frame.pixels().iter().filter(|&&p| p).count(), 3);
+    }
+
+    #[test]
+    fn text() {
+        let mut vram = [0; 0x1c00];
+        // Bottom four pixels of the leftmost column, and the top pixel of the
+        // second column.
+        vram[0] = 0x0f;
+        vram[32 + 31] = 0x80;
+        let frame = Frame::from_vram(&vram);
+
+        let blocks = frame.to_half_blocks();
+        assert_eq!(blocks.len(), SCREEN_HEIGHT / 2);
+        assert_eq!(blocks[0].chars().count(), SCREEN_WIDTH);
+        assert!(blocks[0].starts_with(" \u{2580} "));
+        assert!(blocks[127].starts_with("\u{2588} "));
+        assert!(blocks[126].starts_with("\u{2588} "));
+
+        let braille = frame.to_braille();
+        assert_eq!(braille.len(), SCREEN_HEIGHT / 4);
+        assert_eq!(braille[0].chars().count(), SCREEN_WIDTH / 2);
+        assert!(braille[0].starts_with("\u{2808}\u{2800}"));
+        assert!(braille[63].starts_with("\u{2847}\u{2800}"));
+    }
+
+    #[test]
+    fn overlay() {
+        let mut vram = [0; 0x1c00];
+        vram[0] = 0x01;
+        vram[0x1bff] = 0x80;
+        let frame = Frame::from_vram(&vram);
+        let rgb = frame.to_rgb(true);
+        assert_eq!(rgb[(SCREEN_HEIGHT - 1) * SCREEN_WIDTH], 0xff_ff_ff);
+        assert_eq!(rgb[SCREEN_WIDTH - 1], 0xff_ff_ff);
+        assert_eq!(rgb[1], 0);
+        assert_eq!(super::overlay_color(100, 40), 0xff_20_20);
+        assert_eq!(super::overlay_color(100, 250), 0x20_ff_20);
+        assert_eq!(super::overlay_color(200, 250), 0xff_ff_ff);
+    }
+}
@@ -0,0 +1,353 @@
+//! Recording a session's inputs and replaying them frame for frame.
+//!
+//! A movie holds the rom's CRC32, the DIP switches, the cheats, a save state
+//! of the machine when recording began, and for every frame the buttons held
+//! during it and a CRC32 of the picture it ended with. Because the machine is
+//! fully deterministic, replaying the inputs from the same state must
+//! reproduce the same pictures; the first frame that doesn't is reported as a
+//! desync.
+
+use super::SpaceInvaders;
+use crate::{
+    cheats::Cheats,
+    interconnect::DipSwitches,
+    state::{StateReader, StateWriter},
+    Error,
+};
+use alloc::vec::Vec;
+
+/// Identifies a movie file, followed by a format version byte.
+const MOVIE_MAGIC: &[u8; 8] = b"SI8080MV";
+const MOVIE_VERSION: u8 = 2;
+
+/// One recorded frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MovieFrame {
+    /// Buttons held for the whole frame, as from `GamePad::buttons`.
+    pub buttons: u16,
+    /// `Frame::crc32` of the picture at the end of the frame.
+    pub frame_crc32: u32,
+}
+
+pub struct Movie {
+    rom_crc32: u32,
+    dip_switches: DipSwitches,
+    /// Held every frame as when recording began; replaying restores them.
+    cheats: Cheats,
+    initial_state: Vec<u8>,
+    frames: Vec<MovieFrame>,
+}
+
+impl Movie {
+    /// Starts a recording from the machine's current state.
+    pub fn new(machine: &SpaceInvaders) -> Movie {
+        Movie {
+            rom_crc32: machine.interconnect().rom().crc32(),
+            dip_switches: machine.game_pad().dip_switches(),
+            cheats: machine.cheats().clone(),
+            initial_state: machine.save_state(),
+            frames: Vec::new(),
+        }
+    }
+
+    /// Runs one frame with whatever buttons are pressed now and appends it.
+    ///
+    /// Fails if the machine's cheats have changed since recording began,
+    /// since the movie could no longer be replayed.
+    pub fn record_frame(&mut self, machine: &mut SpaceInvaders) -> Result<(), Error> {
+        if *machine.cheats() != self.cheats {
+            return Err(Error::InvalidMovie {
+                reason: "cheats changed while recording",
+            });
+        }
+        let buttons = machine.game_pad().buttons();
+        let frame_crc32 = machine.run_frame()?.crc32();
+        self.frames.push(MovieFrame {
+            buttons,
+            frame_crc32,
+        });
+        Ok(())
+    }
+
+    /// Puts the machine back where the recording began, with the cheats it
+    /// was recorded with, ready to replay.
+    ///
+    /// Fails if the machine's rom is not the one the movie was recorded with.
+    pub fn replay(&self, machine: &mut SpaceInvaders) -> Result<Replay<'_>, Error> {
+        if machine.interconnect().rom().crc32() != self.rom_crc32 {
+            return Err(Error::InvalidMovie {
+                reason: "recorded with a different rom",
+            });
+        }
+        machine.load_state(&self.initial_state)?;
+        machine.game_pad_mut().set_dip_switches(self.dip_switches);
+        machine.set_cheats(self.cheats.clone());
+        machine.game_pad_mut().set_buttons(0);
+        Ok(Replay {
+            movie: self,
+            next: 0,
+        })
+    }
+
+    /// Replays the whole movie, failing at the first frame that differs.
+    pub fn verify(&self, machine: &mut SpaceInvaders) -> Result<(), Error> {
+        let mut replay = self.replay(machine)?;
+        while replay.step(machine)? {}
+        Ok(())
+    }
+
+    pub fn rom_crc32(&self) -> u32 {
+        self.rom_crc32
+    }
+
+    pub fn cheats(&self) -> &Cheats {
+        &self.cheats
+    }
+
+    pub fn frames(&self) -> &[MovieFrame] {
+        &self.frames
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Encodes the movie for writing to a file.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        w.bytes(MOVIE_MAGIC);
+        w.u8(MOVIE_VERSION);
+        w.u32(self.rom_crc32);
+        w.u8(self.dip_switches.lives);
+        w.bool(self.dip_switches.early_extra_life);
+        w.bool(self.dip_switches.coin_info);
+        w.u32(self.cheats.len() as u32);
+        for (addr, value) in self.cheats.iter() {
+            w.u16(addr);
+            w.u8(value);
+        }
+        w.u32(self.initial_state.len() as u32);
+        w.bytes(&self.initial_state);
+        w.u32(self.frames.len() as u32);
+        for frame in &self.frames {
+            w.u16(frame.buttons);
+            w.u32(frame.frame_crc32);
+        }
+        w.finish()
+    }
+
+    /// Decodes a movie from `to_bytes`.
+    ///
+    /// The initial state is only checked when the movie is replayed.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Movie, Error> {
+        Movie::read(StateReader::new(bytes)).map_err(|e| match e {
+            Error::InvalidState { reason } => Error::InvalidMovie { reason },
+            e => e,
+        })
+    }
+
+    fn read(mut r: StateReader) -> Result<Movie, Error> {
+        if r.bytes(MOVIE_MAGIC.len())? != MOVIE_MAGIC || r.u8()? != MOVIE_VERSION {
+            return Err(Error::InvalidMovie {
+                reason: "not a movie or an unsupported version",
+            });
+        }
+        let rom_crc32 = r.u32()?;
+        let dip_switches = DipSwitches {
+            lives: r.u8()?,
+            early_extra_life: r.bool()?,
+            coin_info: r.bool()?,
+        };
+        let mut cheats = Cheats::new();
+        for _ in 0..r.u32()? {
+            let addr = r.u16()?;
+            cheats.freeze(addr, r.u8()?);
+        }
+        let len = r.u32()? as usize;
+        let initial_state = r.bytes(len)?.to_vec();
+        let count = r.u32()?;
+        let mut frames = Vec::new();
+        for _ in 0..count {
+            frames.push(MovieFrame {
+                buttons: r.u16()?,
+                frame_crc32: r.u32()?,
+            });
+        }
+        r.finish()?;
+        Ok(Movie {
+            rom_crc32,
+            dip_switches,
+            cheats,
+            initial_state,
+            frames,
+        })
+    }
+}
+
+/// A movie being played back, a frame at a time.
+pub struct Replay<'a> {
+    movie: &'a Movie,
+    next: usize,
+}
+
+impl<'a> Replay<'a> {
+    /// Runs the next recorded frame with its inputs, returning false once the
+    /// movie has ended.
+    ///
+    /// Fails with `Error::MovieDesync` if the picture differs from the
+    /// recording.
+    pub fn step(&mut self, machine: &mut SpaceInvaders) -> Result<bool, Error> {
+        let recorded = match self.movie.frames.get(self.next) {
+            Some(recorded) => *recorded,
+            None => return Ok(false),
+        };
+        machine.game_pad_mut().set_buttons(recorded.buttons);
+        let found = machine.run_frame()?.crc32();
+        self.next += 1;
+        if found != recorded.frame_crc32 {
+            return Err(Error::MovieDesync {
+                frame: self.next as u64,
+                expected: recorded.frame_crc32,
+                found,
+            });
+        }
+        Ok(true)
+    }
+
+    /// Frames replayed so far.
+    pub fn position(&self) -> usize {
+        self.next
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next == self.movie.frames.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Movie;
+    use crate::{
+        interconnect::{Button, DipSwitches},
+        machine::SpaceInvaders,
+        Error,
+    };
+
+    /// Adds port 1 into the first byte of VRAM over and over, so the picture
+    /// depends on every input the cpu sees.
+    const ACCUMULATOR: [u8; 11] = [
+        0xdb, 0x01, // IN 1
+        0x47, // MOV B, A
+        0x3a, 0x00, 0x24, // LDA 0x2400
+        0x80, // ADD B
+        0x32, 0x00, 0x24, // STA 0x2400
+        0xc7, // RST 0
+    ];
+
+    fn play(machine: &mut SpaceInvaders, movie: &mut Movie, frames: u64) {
+        for frame in 0..frames {
+            machine
+                .game_pad_mut()
+                .set(Button::Fire1, frame % 3 == 0 || frame % 7 == 0);
+            movie.record_frame(machine).unwrap();
+        }
+    }
+
+    #[test]
+    fn runs_are_deterministic() {
+        let mut a = SpaceInvaders::new(ACCUMULATOR);
+        let mut b = SpaceInvaders::new(ACCUMULATOR);
+        let mut movie_a = Movie::new(&a);
+        let mut movie_b = Movie::new(&b);
+        play(&mut a, &mut movie_a, 20);
+        play(&mut b, &mut movie_b, 20);
+        assert_eq!(a.save_state(), b.save_state());
+        assert_eq!(movie_a.frames(), movie_b.frames());
+    }
+
+    #[test]
+    fn replays_recording() {
+        let mut machine = SpaceInvaders::new(ACCUMULATOR);
+        machine.game_pad_mut().set_dip_switches(DipSwitches {
+            lives: 5,
+            ..DipSwitches::default()
+        });
+        machine.run_frame().unwrap();
+        let mut movie = Movie::new(&machine);
+        play(&mut machine, &mut movie, 30);
+        let end = machine.save_state();
+
+        let movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+        assert_eq!(movie.len(), 30);
+        let mut replayed = SpaceInvaders::new(ACCUMULATOR);
+        movie.verify(&mut replayed).unwrap();
+        assert_eq!(replayed.save_state(), end);
+        assert_eq!(replayed.game_pad().dip_switches().lives, 5);
+    }
+
+    #[test]
+    fn replays_with_recorded_cheats() {
+        let mut machine = SpaceInvaders::new(ACCUMULATOR);
+        machine.cheats_mut().freeze(0x2400, 0x40);
+        let mut movie = Movie::new(&machine);
+        play(&mut machine, &mut movie, 10);
+        let end = machine.save_state();
+
+        // Replaying without cheats restores them from the movie.
+        let movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+        let mut replayed = SpaceInvaders::new(ACCUMULATOR);
+        movie.verify(&mut replayed).unwrap();
+        assert_eq!(replayed.save_state(), end);
+
+        // And replaying with others clears them.
+        let mut movie = Movie::new(&SpaceInvaders::new(ACCUMULATOR));
+        play(&mut SpaceInvaders::new(ACCUMULATOR), &mut movie, 10);
+        movie.verify(&mut replayed).unwrap();
+        assert!(replayed.cheats().is_empty());
+
+        replayed.cheats_mut().freeze(0x2400, 0);
+        match movie.record_frame(&mut replayed) {
+            Err(Error::InvalidMovie { .. }) => {}
+            r => panic!("expected a cheat change to fail, got {:?}", r.err()),
+        }
+    }
+
+    #[test]
+    fn detects_desync() {
+        let mut machine = SpaceInvaders::new(ACCUMULATOR);
+        let mut movie = Movie::new(&machine);
+        play(&mut machine, &mut movie, 10);
+        let mut bytes = movie.to_bytes();
+        // Flip the buttons recorded for frame 5.
+        let frame5 = bytes.len() - 6 * 5;
+        bytes[frame5] ^= 0x10;
+        let tampered = Movie::from_bytes(&bytes).unwrap();
+
+        let mut replayed = SpaceInvaders::new(ACCUMULATOR);
+        match tampered.verify(&mut replayed) {
+            Err(Error::MovieDesync { frame: 6, .. }) => {}
+            r => panic!("expected a desync at frame 6, got {:?}", r.err()),
+        }
+
+        let mut other_rom = SpaceInvaders::new([0x76]);
+        match movie.replay(&mut other_rom) {
+            Err(Error::InvalidMovie { .. }) => {}
+            r => panic!("expected a rom mismatch, got {:?}", r.err()),
+        }
+        match Movie::from_bytes(&bytes[..bytes.len() - 1]) {
+            Err(Error::InvalidMovie { .. }) => {}
+            r => panic!("expected a truncated movie, got {:?}", r.err()),
+        }
+        bytes.push(0);
+        match Movie::from_bytes(&bytes) {
+            Err(Error::InvalidMovie {
+                reason: "trailing data",
+            }) => {}
+            r => panic!("expected trailing data, got {:?}", r.err()),
+        }
+    }
+}
@@ -0,0 +1,251 @@
+//! A ring buffer of recent save states for going back in time.
+//!
+//! Only the newest snapshot is kept whole. Every older one is stored as the
+//! difference from the snapshot after it: the two states are XORed, which
+//! leaves zeros wherever memory didn't change, and the zero runs are
+//! collapsed. Dropping the oldest snapshot therefore never needs the others
+//! rewritten, and a frame's worth of changes usually costs a few hundred bytes
+//! instead of the full 8K of RAM.
+
+use super::SpaceInvaders;
+use crate::Error;
+use alloc::{collections::VecDeque, vec::Vec};
+
+/// An older snapshot, encoded against the one after it.
+struct Delta {
+    frame: u64,
+    /// Alternating runs: a zero run length as a `u16`, a literal length as a
+    /// `u16`, then that many literal bytes, repeated to the end of the state.
+    runs: Vec<u8>,
+}
+
+impl Delta {
+    fn encode(frame: u64, older: &[u8], newer: &[u8]) -> Delta {
+        let xor: Vec<u8> = older.iter().zip(newer).map(|(a, b)| a ^ b).collect();
+        let mut runs = Vec::new();
+        let mut i = 0;
+        while i < xor.len() {
+            let zeros = xor[i..]
+                .iter()
+                .take(u16::MAX as usize)
+                .take_while(|&&b| b == 0)
+                .count();
+            i += zeros;
+            let literals = xor[i..]
+                .iter()
+                .take(u16::MAX as usize)
+                .take_while(|&&b| b != 0)
+                .count();
+            runs.extend_from_slice(&(zeros as u16).to_le_bytes());
+            runs.extend_from_slice(&(literals as u16).to_le_bytes());
+            runs.extend_from_slice(&xor[i..i + literals]);
+            i += literals;
+        }
+        Delta { frame, runs }
+    }
+
+    /// Turns the newer state back into the older one, in place.
+    fn apply(&self, state: &mut [u8]) {
+        let mut runs = &self.runs[..];
+        let mut i = 0;
+        while runs.len() >= 4 {
+            let zeros = u16::from_le_bytes([runs[0], runs[1]]) as usize;
+            let literals = u16::from_le_bytes([runs[2], runs[3]]) as usize;
+            i += zeros;
+            for (byte, x) in state[i..i + literals].iter_mut().zip(&runs[4..]) {
+                *byte ^= x;
+            }
+            i += literals;
+            runs = &runs[4 + literals..];
+        }
+    }
+}
+
+pub struct Rewind {
+    interval: u64,
+    capacity: usize,
+    /// The newest snapshot and the frame it was taken after.
+    latest: Option<(u64, Vec<u8>)>,
+    /// Older snapshots, oldest first.
+    deltas: VecDeque<Delta>,
+}
+
+impl Rewind {
+    /// Keeps up to `capacity` snapshots, taken every `interval` frames.
+    ///
+    /// 60 snapshots every 5 frames, say, reach back five seconds.
+    pub fn new(interval: u64, capacity: usize) -> Rewind {
+        Rewind {
+            interval: interval.max(1),
+            capacity: capacity.max(1),
+            latest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    /// Takes a snapshot if the machine's frame count is due for one. Call it
+    /// after every frame.
+    pub fn record(&mut self, machine: &SpaceInvaders) {
+        let frame = machine.frame_count();
+        if !frame.is_multiple_of(self.interval) {
+            return;
+        }
+        let state = machine.save_state();
+        if let Some((latest_frame, latest)) = self.latest.take() {
+            if latest_frame < frame && latest.len() == state.len() {
+                self.deltas
+                    .push_back(Delta::encode(latest_frame, &latest, &state));
+            } else {
+                // The machine went back or was given a new rom behind our
+                // back; older snapshots no longer lead here.
+                self.deltas.clear();
+            }
+        }
+        self.latest = Some((frame, state));
+        while self.len() > self.capacity {
+            self.deltas.pop_front();
+        }
+    }
+
+    /// Goes back at least `frames` frames, to the newest snapshot that far
+    /// back or to the oldest one kept. Snapshots after it are discarded.
+    ///
+    /// Returns the frame count the machine is now at, or None if there was
+    /// no snapshot to go back to.
+    pub fn rewind(
+        &mut self,
+        machine: &mut SpaceInvaders,
+        frames: u64,
+    ) -> Result<Option<u64>, Error> {
+        let target = machine.frame_count().saturating_sub(frames);
+        self.restore(machine, |frame| frame <= target)
+    }
+
+    /// Goes back to the newest snapshot taken before the machine's current
+    /// frame.
+    pub fn step_back(&mut self, machine: &mut SpaceInvaders) -> Result<Option<u64>, Error> {
+        let current = machine.frame_count();
+        self.restore(machine, |frame| frame < current)
+    }
+
+    fn restore<F>(&mut self, machine: &mut SpaceInvaders, wanted: F) -> Result<Option<u64>, Error>
+    where
+        F: Fn(u64) -> bool,
+    {
+        let (mut frame, mut state) = match self.latest.take() {
+            Some(latest) => latest,
+            None => return Ok(None),
+        };
+        while !wanted(frame) {
+            match self.deltas.pop_back() {
+                Some(delta) => {
+                    delta.apply(&mut state);
+                    frame = delta.frame;
+                }
+                None => break,
+            }
+        }
+        let result = machine.load_state(&state);
+        self.latest = Some((frame, state));
+        result.map(|()| Some(frame))
+    }
+
+    /// Number of snapshots held.
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.latest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    /// The frame count of the oldest snapshot held.
+    pub fn oldest_frame(&self) -> Option<u64> {
+        match self.deltas.front() {
+            Some(delta) => Some(delta.frame),
+            None => self.latest.as_ref().map(|(frame, _)| *frame),
+        }
+    }
+
+    /// Approximate bytes used by the snapshots.
+    pub fn memory_usage(&self) -> usize {
+        let latest = self.latest.as_ref().map_or(0, |(_, state)| state.len());
+        latest + self.deltas.iter().map(|d| d.runs.len()).sum::<usize>()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rewind;
+    use crate::machine::SpaceInvaders;
+    use alloc::vec::Vec;
+
+    /// Counts frames in the first byte of VRAM from the `RST 2` handler.
+    fn counter() -> [u8; 0x16] {
+        let mut rom = [0; 0x16];
+        rom[..8].copy_from_slice(&[
+            0x31, 0x00, 0x24, // LXI SP, 0x2400
+            0xfb, // EI
+            0x76, // HLT
+            0xc3, 0x03, 0x00, // JMP 0x0003
+        ]);
+        rom[0x08..0x0a].copy_from_slice(&[0xfb, 0xc9]); // EI; RET
+        rom[0x10..0x16].copy_from_slice(&[
+            0x21, 0x00, 0x24, // LXI H, 0x2400
+            0x34, // INR M
+            0xfb, // EI
+            0xc9, // RET
+        ]);
+        rom
+    }
+
+    #[test]
+    fn rewinds_to_snapshots() {
+        let mut machine = SpaceInvaders::new(counter());
+        let mut rewind = Rewind::new(4, 5);
+        let mut states = Vec::new();
+        for _ in 0..40 {
+            machine.run_frame().unwrap();
+            rewind.record(&machine);
+            states.push(machine.save_state());
+        }
+        // Only frames 24, 28, ..., 40 are still held.
+        assert_eq!(rewind.len(), 5);
+        assert_eq!(rewind.oldest_frame(), Some(24));
+        assert!(rewind.memory_usage() < states[0].len() + 5 * 64);
+
+        assert_eq!(rewind.rewind(&mut machine, 10).unwrap(), Some(28));
+        assert_eq!(machine.frame_count(), 28);
+        assert_eq!(machine.save_state(), states[27]);
+        // RST 2 at the end of frame 28 hasn't been handled yet.
+        assert_eq!(machine.interconnect().vram()[0], 27);
+
+        assert_eq!(rewind.step_back(&mut machine).unwrap(), Some(24));
+        assert_eq!(machine.save_state(), states[23]);
+        // Nothing older is left, so stepping back stays put.
+        assert_eq!(rewind.step_back(&mut machine).unwrap(), Some(24));
+        assert_eq!(rewind.len(), 1);
+
+        // Recording carries on from the restored frame.
+        for _ in 0..8 {
+            machine.run_frame().unwrap();
+            rewind.record(&machine);
+        }
+        assert_eq!(rewind.len(), 3);
+        assert_eq!(rewind.rewind(&mut machine, 4).unwrap(), Some(28));
+        assert_eq!(machine.save_state(), states[27]);
+    }
+
+    #[test]
+    fn empty_buffer() {
+        let mut machine = SpaceInvaders::new(counter());
+        let mut rewind = Rewind::new(1, 10);
+        assert_eq!(rewind.step_back(&mut machine).unwrap(), None);
+        assert!(rewind.is_empty());
+    }
+}
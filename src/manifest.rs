@@ -0,0 +1,515 @@
+//! Identification and verification of known rom sets.
+//!
+//! A `Manifest` lists rom sets together with the files that make them up,
+//! where each file goes in the address space and, where known, its CRC32 and
+//! SHA1. Verifying a set reports which files are missing, the wrong size,
+//! bad dumps, or belong somewhere else in the set (swapped halves), before
+//! any of them are handed to the emulator.
+//!
+//! Files without a recorded hash can only be checked for presence and size.
+//! `RomSet::is_verified` tells which sets can catch bad dumps; of the
+//! built-in sets, only `invaders` and `cpudiag` can.
+
+use crate::{interconnect::Rom, Error};
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt::{self, Display};
+use sha1::{Digest, Sha1};
+
+const KNOWN: &str = include_str!("manifest/known.txt");
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Manifest {
+    sets: Vec<RomSet>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RomSet {
+    name: String,
+    description: String,
+    files: Vec<RomFile>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RomFile {
+    name: String,
+    offset: u16,
+    size: Option<usize>,
+    crc32: Option<u32>,
+    sha1: Option<[u8; 20]>,
+}
+
+/// The outcome of checking one file of a set.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FileStatus {
+    /// The file matches every recorded hash.
+    Ok,
+    /// The file is present but the manifest records no hash to check it against.
+    Unverified,
+    Missing,
+    WrongSize {
+        expected: usize,
+        found: usize,
+    },
+    /// The contents match no file of the set.
+    BadDump {
+        crc32: u32,
+    },
+    /// The contents are those of another file in the set.
+    Misplaced {
+        belongs_to: String,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Verification {
+    pub set: String,
+    pub files: Vec<(String, FileStatus)>,
+}
+
+impl Manifest {
+    /// The rom sets recognised out of the box.
+    pub fn known() -> Manifest {
+        Manifest::parse(KNOWN).expect("built-in manifest is valid")
+    }
+
+    /// Parses a manifest in the format of `manifest/known.txt`.
+    pub fn parse(text: &str) -> Result<Manifest, Error> {
+        let mut sets: Vec<RomSet> = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |reason| Error::InvalidManifest {
+                line: i + 1,
+                reason,
+            };
+            if let Some(header) = line.strip_prefix('[') {
+                let end = header
+                    .find(']')
+                    .ok_or_else(|| invalid("unclosed set name"))?;
+                sets.push(RomSet {
+                    name: header[..end].to_string(),
+                    description: header[end + 1..].trim().to_string(),
+                    files: Vec::new(),
+                });
+                continue;
+            }
+            let set = sets
+                .last_mut()
+                .ok_or_else(|| invalid("file listed before any set"))?;
+            let mut fields = line.split_whitespace();
+            let name = fields.next().unwrap().to_string();
+            let offset = fields
+                .next()
+                .and_then(|f| u16::from_str_radix(f, 16).ok())
+                .ok_or_else(|| invalid("missing or invalid offset"))?;
+            let size = match fields.next() {
+                Some("*") => None,
+                Some(f) => Some(usize::from_str_radix(f, 16).map_err(|_| invalid("invalid size"))?),
+                None => return Err(invalid("missing size")),
+            };
+            let mut file = RomFile {
+                name,
+                offset,
+                size,
+                crc32: None,
+                sha1: None,
+            };
+            for field in fields {
+                if let Some(crc) = field.strip_prefix("crc32=") {
+                    let crc = u32::from_str_radix(crc, 16).map_err(|_| invalid("invalid crc32"))?;
+                    file.crc32 = Some(crc);
+                } else if let Some(sha1) = field.strip_prefix("sha1=") {
+                    file.sha1 = Some(parse_sha1(sha1).ok_or_else(|| invalid("invalid sha1"))?);
+                } else {
+                    return Err(invalid("unknown field"));
+                }
+            }
+            set.files.push(file);
+        }
+        Ok(Manifest { sets })
+    }
+
+    pub fn sets(&self) -> &[RomSet] {
+        &self.sets
+    }
+
+    pub fn get(&self, name: &str) -> Option<&RomSet> {
+        self.sets.iter().find(|set| set.name == name)
+    }
+
+    /// Finds the set and file whose recorded hashes match `data`.
+    pub fn identify(&self, data: &[u8]) -> Option<(&RomSet, &RomFile)> {
+        let hashes = Hashes::of(data);
+        self.sets.iter().find_map(|set| {
+            set.files
+                .iter()
+                .find(|file| file.matches(&hashes) == Some(true))
+                .map(|file| (set, file))
+        })
+    }
+}
+
+impl RomSet {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    pub fn files(&self) -> &[RomFile] {
+        &self.files
+    }
+
+    /// Whether every file of the set has a recorded hash, so that `verify`
+    /// can tell good dumps from bad ones and `Manifest::identify` can find
+    /// them by their contents.
+    pub fn is_verified(&self) -> bool {
+        self.files
+            .iter()
+            .all(|file| file.crc32.is_some() || file.sha1.is_some())
+    }
+
+    /// Checks every file of the set, fetching contents by file name through `lookup`.
+    pub fn verify<'a, F>(&self, mut lookup: F) -> Verification
+    where
+        F: FnMut(&str) -> Option<&'a [u8]>,
+    {
+        let files = self
+            .files
+            .iter()
+            .map(|file| {
+                let status = match lookup(&file.name) {
+                    Some(data) => self.check(file, data),
+                    None => FileStatus::Missing,
+                };
+                (file.name.clone(), status)
+            })
+            .collect();
+        Verification {
+            set: self.name.clone(),
+            files,
+        }
+    }
+
+    /// Verifies the set and lays its files out into a `Rom`.
+    ///
+    /// Fails if any file is missing, the wrong size or fails its hash check.
+    /// Files without recorded hashes are accepted.
+    pub fn build<'a, F>(&self, mut lookup: F) -> Result<Rom, Error>
+    where
+        F: FnMut(&str) -> Option<&'a [u8]>,
+    {
+        let verification = self.verify(&mut lookup);
+        if !verification.is_ok() {
+            return Err(Error::BadRomSet(verification));
+        }
+        let parts = self
+            .files
+            .iter()
+            .map(|file| (file.offset, lookup(&file.name).unwrap_or(&[])));
+        Rom::from_parts(parts)
+    }
+
+    fn check(&self, file: &RomFile, data: &[u8]) -> FileStatus {
+        if let Some(expected) = file.size {
+            if expected != data.len() {
+                return FileStatus::WrongSize {
+                    expected,
+                    found: data.len(),
+                };
+            }
+        }
+        let hashes = Hashes::of(data);
+        match file.matches(&hashes) {
+            Some(true) => FileStatus::Ok,
+            None => FileStatus::Unverified,
+            Some(false) => match self
+                .files
+                .iter()
+                .find(|other| other.matches(&hashes) == Some(true))
+            {
+                Some(other) => FileStatus::Misplaced {
+                    belongs_to: other.name.clone(),
+                },
+                None => FileStatus::BadDump {
+                    crc32: hashes.crc32,
+                },
+            },
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl RomSet {
+    /// Verifies the set against the files in `dir`.
+    pub fn verify_dir<P: AsRef<std::path::Path>>(&self, dir: P) -> Verification {
+        let contents = self.read_dir(dir.as_ref());
+        self.verify(|name| lookup(&contents, name))
+    }
+
+    /// Verifies the set against the files in `dir` and lays them out into a `Rom`.
+    pub fn load_dir<P: AsRef<std::path::Path>>(&self, dir: P) -> Result<Rom, Error> {
+        let contents = self.read_dir(dir.as_ref());
+        self.build(|name| lookup(&contents, name))
+    }
+
+    fn read_dir(&self, dir: &std::path::Path) -> Vec<(String, Vec<u8>)> {
+        self.files
+            .iter()
+            .filter_map(|file| {
+                let data = std::fs::read(dir.join(&file.name)).ok()?;
+                Some((file.name.clone(), data))
+            })
+            .collect()
+    }
+}
+
+#[cfg(feature = "std")]
+fn lookup<'a>(contents: &'a [(String, Vec<u8>)], name: &str) -> Option<&'a [u8]> {
+    contents
+        .iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, data)| data.as_slice())
+}
+
+impl RomFile {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn offset(&self) -> u16 {
+        self.offset
+    }
+
+    pub fn size(&self) -> Option<usize> {
+        self.size
+    }
+
+    pub fn crc32(&self) -> Option<u32> {
+        self.crc32
+    }
+
+    pub fn sha1(&self) -> Option<[u8; 20]> {
+        self.sha1
+    }
+
+    /// `None` if no hash is recorded, otherwise whether every recorded hash matches.
+    fn matches(&self, hashes: &Hashes) -> Option<bool> {
+        match (self.crc32, self.sha1) {
+            (None, None) => None,
+            (crc32, sha1) => Some(
+                crc32.iter().all(|&c| c == hashes.crc32) && sha1.iter().all(|&s| s == hashes.sha1),
+            ),
+        }
+    }
+}
+
+impl Verification {
+    /// True when no file is missing, the wrong size or a bad dump.
+    pub fn is_ok(&self) -> bool {
+        self.files
+            .iter()
+            .all(|(_, status)| matches!(status, FileStatus::Ok | FileStatus::Unverified))
+    }
+
+    /// The files that were present but could not be checked for bad dumps.
+    pub fn unverified(&self) -> impl Iterator<Item = &str> {
+        self.files
+            .iter()
+            .filter(|(_, status)| *status == FileStatus::Unverified)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// The files that stop the set from loading.
+    pub fn problems(&self) -> impl Iterator<Item = &(String, FileStatus)> {
+        self.files
+            .iter()
+            .filter(|(_, status)| !matches!(status, FileStatus::Ok | FileStatus::Unverified))
+    }
+}
+
+impl Display for FileStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FileStatus::Ok => write!(f, "ok"),
+            FileStatus::Unverified => write!(f, "present, no known hash"),
+            FileStatus::Missing => write!(f, "missing"),
+            FileStatus::WrongSize { expected, found } => {
+                write!(f, "expected {} bytes, found {}", expected, found)
+            }
+            FileStatus::BadDump { crc32 } => write!(f, "bad dump (crc32 {:08x})", crc32),
+            FileStatus::Misplaced { belongs_to } => write!(f, "contains {}", belongs_to),
+        }
+    }
+}
+
+impl Display for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:", self.set)?;
+        for (name, status) in &self.files {
+            write!(f, " {} {};", name, status)?;
+        }
+        Ok(())
+    }
+}
+
+struct Hashes {
+    crc32: u32,
+    sha1: [u8; 20],
+}
+
+impl Hashes {
+    fn of(data: &[u8]) -> Hashes {
+        Hashes {
+            crc32: crc32fast::hash(data),
+            sha1: Sha1::digest(data).into(),
+        }
+    }
+}
+
+fn parse_sha1(hex: &str) -> Option<[u8; 20]> {
+    if hex.len() != 40 {
+        return None;
+    }
+    let mut sha1 = [0; 20];
+    for (i, byte) in sha1.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(sha1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileStatus, Manifest};
+    use crate::Error;
+    use alloc::{string::ToString, vec::Vec};
+
+    const MANIFEST: &str = "
+        [demo] Demo set
+        demo.h 0000 0004 crc32=b63cfbcd
+        demo.g 0004 0004 crc32=538d4d69
+        demo.x 0100 *
+    ";
+
+    const H: &[u8] = &[1, 2, 3, 4];
+    const G: &[u8] = &[5, 6, 7, 8];
+
+    #[test]
+    fn known_sets() {
+        let manifest = Manifest::known();
+        let invaders = manifest.get("invaders").unwrap();
+        let offsets: Vec<u16> = invaders.files().iter().map(|f| f.offset()).collect();
+        assert_eq!(offsets, [0x0000, 0x0800, 0x1000, 0x1800]);
+        for name in &["invadpt2", "lrescue", "ballbomb", "cpudiag", "8080exm"] {
+            assert!(manifest.get(name).is_some(), "{} missing", name);
+        }
+        // Only these sets have hashes to catch bad dumps with so far.
+        let verified: Vec<&str> = manifest
+            .sets()
+            .iter()
+            .filter(|set| set.is_verified())
+            .map(|set| set.name())
+            .collect();
+        assert_eq!(verified, ["invaders", "cpudiag"]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn identifies_cpudiag() {
+        let data = std::fs::read("tests/test.rom.org").unwrap();
+        let manifest = Manifest::known();
+        let (set, file) = manifest.identify(&data).unwrap();
+        assert_eq!(set.name(), "cpudiag");
+        assert_eq!(file.name(), "cpudiag.bin");
+        let rom = set.build(|_| Some(&data[..])).unwrap();
+        assert_eq!(rom.read_byte(0x0100), 0xc3);
+    }
+
+    #[test]
+    fn reports_problems() {
+        let manifest = Manifest::parse(MANIFEST).unwrap();
+        let set = manifest.get("demo").unwrap();
+        let verification = set.verify(|name| match name {
+            "demo.h" => Some(G),
+            "demo.g" => Some(&[0, 0, 0, 0]),
+            _ => None,
+        });
+        assert_eq!(
+            verification.files,
+            [
+                (
+                    "demo.h".to_string(),
+                    FileStatus::Misplaced {
+                        belongs_to: "demo.g".to_string()
+                    }
+                ),
+                (
+                    "demo.g".to_string(),
+                    FileStatus::BadDump { crc32: 0x2144df1c }
+                ),
+                ("demo.x".to_string(), FileStatus::Missing),
+            ]
+        );
+        assert!(!verification.is_ok());
+        assert_eq!(verification.problems().count(), 3);
+        match set.build(|_| Some(H)) {
+            Err(Error::BadRomSet(v)) => assert_eq!(
+                v.problems().collect::<Vec<_>>(),
+                [&(
+                    "demo.g".to_string(),
+                    FileStatus::Misplaced {
+                        belongs_to: "demo.h".to_string()
+                    }
+                )]
+            ),
+            r => panic!("expected bad rom set, got {:?}", r.map(|rom| rom.len())),
+        }
+        let verification = set.verify(|_| Some(&[0; 8]));
+        assert_eq!(
+            verification.files[0].1,
+            FileStatus::WrongSize {
+                expected: 4,
+                found: 8
+            }
+        );
+    }
+
+    #[test]
+    fn builds_layout() {
+        let manifest = Manifest::parse(MANIFEST).unwrap();
+        let set = manifest.get("demo").unwrap();
+        let rom = set
+            .build(|name| match name {
+                "demo.h" => Some(H),
+                "demo.g" => Some(G),
+                _ => Some(&[0xff]),
+            })
+            .unwrap();
+        assert_eq!(rom.len(), 0x0101);
+        assert_eq!(rom.read_byte(0x0003), 4);
+        assert_eq!(rom.read_byte(0x0004), 5);
+        assert_eq!(rom.read_byte(0x0100), 0xff);
+        let verification = set.verify(|name| match name {
+            "demo.h" => Some(H),
+            "demo.g" => Some(G),
+            _ => Some(&[0xff]),
+        });
+        assert!(verification.is_ok());
+        assert_eq!(verification.unverified().collect::<Vec<_>>(), ["demo.x"]);
+        assert!(!set.is_verified());
+    }
+
+    #[test]
+    fn rejects_malformed_manifest() {
+        assert!(Manifest::parse("demo.h 0000 0004").is_err());
+        assert!(Manifest::parse("[demo]\ndemo.h zzzz 0004").is_err());
+        assert!(Manifest::parse("[demo]\ndemo.h 0000 0004 md5=00").is_err());
+    }
+}
@@ -0,0 +1,383 @@
+//! IPS and BPS patches, for rom hacks, translations and test fixtures.
+//!
+//! IPS lists bytes to overwrite at fixed offsets and can grow or truncate the
+//! image. BPS rebuilds the target from copies of the source and of itself,
+//! and carries CRC32s of the source, the target and the patch, all of which
+//! are checked.
+
+use crate::Error;
+use alloc::{boxed::Box, vec::Vec};
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: usize = 0x45_4f_46;
+const BPS_MAGIC: &[u8] = b"BPS1";
+/// The source, target and patch CRC32s closing a BPS patch.
+const BPS_FOOTER: usize = 12;
+/// The largest image a patch may produce, the most IPS offsets can address.
+const MAX_TARGET: usize = 1 << 24;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Ips,
+    Bps,
+}
+
+/// A patch file, recognised by its magic.
+#[derive(Clone, Debug)]
+pub struct Patch {
+    format: Format,
+    data: Box<[u8]>,
+}
+
+impl Patch {
+    /// Checks the header, and for BPS the patch's own checksum.
+    pub fn parse(data: &[u8]) -> Result<Patch, Error> {
+        let format = if data.starts_with(IPS_MAGIC) {
+            Format::Ips
+        } else if data.starts_with(BPS_MAGIC) {
+            if data.len() < BPS_MAGIC.len() + BPS_FOOTER {
+                return Err(invalid("truncated BPS patch"));
+            }
+            let body = data.len() - 4;
+            check(
+                "patch",
+                le_u32(&data[body..]),
+                crc32fast::hash(&data[..body]),
+            )?;
+            Format::Bps
+        } else {
+            return Err(invalid("not an IPS or BPS patch"));
+        };
+        Ok(Patch {
+            format,
+            data: Box::from(data),
+        })
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Patch, Error> {
+        Patch::parse(&std::fs::read(path)?)
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Builds the patched image from `source`.
+    pub fn apply(&self, source: &[u8]) -> Result<Vec<u8>, Error> {
+        match self.format {
+            Format::Ips => apply_ips(&self.data[IPS_MAGIC.len()..], source),
+            Format::Bps => apply_bps(&self.data, source),
+        }
+    }
+}
+
+fn apply_ips(mut records: &[u8], source: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut target = source.to_vec();
+    loop {
+        let offset = be(take(&mut records, 3)?);
+        if offset == IPS_EOF {
+            break;
+        }
+        let len = be(take(&mut records, 2)?);
+        let end;
+        if len == 0 {
+            let count = be(take(&mut records, 2)?);
+            let value = take(&mut records, 1)?[0];
+            end = offset + count;
+            grow(&mut target, end);
+            target[offset..end].iter_mut().for_each(|b| *b = value);
+        } else {
+            end = offset + len;
+            grow(&mut target, end);
+            target[offset..end].copy_from_slice(take(&mut records, len)?);
+        }
+    }
+    match records.len() {
+        0 => {}
+        3 => target.truncate(be(records)),
+        _ => return Err(invalid("trailing bytes after IPS EOF")),
+    }
+    Ok(target)
+}
+
+fn apply_bps(patch: &[u8], source: &[u8]) -> Result<Vec<u8>, Error> {
+    let footer = &patch[patch.len() - BPS_FOOTER..];
+    let mut actions = &patch[BPS_MAGIC.len()..patch.len() - BPS_FOOTER];
+    if varint(&mut actions)? != source.len() {
+        return Err(invalid("source size does not match"));
+    }
+    check("source", le_u32(&footer[0..4]), crc32fast::hash(source))?;
+    let target_len = varint(&mut actions)?;
+    if target_len > MAX_TARGET {
+        return Err(invalid("target too large"));
+    }
+    let metadata = varint(&mut actions)?;
+    take(&mut actions, metadata)?;
+
+    // The target size comes from the patch, so only trust it as far as the
+    // source and actions could fill it.
+    let mut target = Vec::with_capacity(target_len.min(source.len() + actions.len()));
+    let mut source_offset = 0;
+    let mut target_offset = 0;
+    while !actions.is_empty() {
+        let action = varint(&mut actions)?;
+        let len = (action >> 2) + 1;
+        if len > target_len - target.len() {
+            return Err(invalid("action writes past the end of the target"));
+        }
+        match action & 3 {
+            // SourceRead
+            0 => {
+                let start = target.len();
+                target.extend_from_slice(slice(source, start, len)?);
+            }
+            // TargetRead
+            1 => target.extend_from_slice(take(&mut actions, len)?),
+            // SourceCopy
+            2 => {
+                source_offset = relative(source_offset, varint(&mut actions)?)?;
+                target.extend_from_slice(slice(source, source_offset, len)?);
+                source_offset += len;
+            }
+            // TargetCopy, which may overlap the bytes it is producing.
+            _ => {
+                target_offset = relative(target_offset, varint(&mut actions)?)?;
+                if target_offset >= target.len() {
+                    return Err(invalid("target copy reads past the output"));
+                }
+                for _ in 0..len {
+                    target.push(target[target_offset]);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+    if target.len() != target_len {
+        return Err(invalid("target size does not match"));
+    }
+    check("target", le_u32(&footer[4..8]), crc32fast::hash(&target))?;
+    Ok(target)
+}
+
+fn invalid(reason: &'static str) -> Error {
+    Error::InvalidPatch { reason }
+}
+
+fn check(which: &'static str, expected: u32, found: u32) -> Result<(), Error> {
+    if expected == found {
+        Ok(())
+    } else {
+        Err(Error::PatchChecksum {
+            which,
+            expected,
+            found,
+        })
+    }
+}
+
+fn take<'a>(data: &mut &'a [u8], len: usize) -> Result<&'a [u8], Error> {
+    if data.len() < len {
+        return Err(invalid("truncated patch"));
+    }
+    let (head, tail) = data.split_at(len);
+    *data = tail;
+    Ok(head)
+}
+
+fn slice(data: &[u8], start: usize, len: usize) -> Result<&[u8], Error> {
+    start
+        .checked_add(len)
+        .and_then(|end| data.get(start..end))
+        .ok_or_else(|| invalid("source copy reads past the source"))
+}
+
+fn grow(data: &mut Vec<u8>, len: usize) {
+    if data.len() < len {
+        data.resize(len, 0);
+    }
+}
+
+fn be(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |acc, &b| acc << 8 | b as usize)
+}
+
+fn le_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// BPS numbers: seven bits at a time, least significant first, with the top
+/// bit marking the last byte and each continuation adding one.
+fn varint(data: &mut &[u8]) -> Result<usize, Error> {
+    let mut value: usize = 0;
+    let mut shift: usize = 1;
+    loop {
+        let byte = take(data, 1)?[0];
+        value = (byte as usize & 0x7f)
+            .checked_mul(shift)
+            .and_then(|v| v.checked_add(value))
+            .ok_or_else(|| invalid("number out of range"))?;
+        if byte & 0x80 != 0 {
+            return Ok(value);
+        }
+        shift = shift
+            .checked_mul(0x80)
+            .ok_or_else(|| invalid("number out of range"))?;
+        value = value
+            .checked_add(shift)
+            .ok_or_else(|| invalid("number out of range"))?;
+    }
+}
+
+/// Moves `offset` by a BPS signed delta: the low bit is the sign.
+fn relative(offset: usize, delta: usize) -> Result<usize, Error> {
+    let moved = if delta & 1 == 0 {
+        offset.checked_add(delta >> 1)
+    } else {
+        offset.checked_sub(delta >> 1)
+    };
+    moved.ok_or_else(|| invalid("copy offset out of range"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Format, Patch, MAX_TARGET};
+    use crate::Error;
+    use alloc::vec::Vec;
+
+    fn varint(out: &mut Vec<u8>, mut value: usize) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                out.push(byte | 0x80);
+                return;
+            }
+            out.push(byte);
+            value -= 1;
+        }
+    }
+
+    fn bps(source: &[u8], target: &[u8], actions: &[u8]) -> Vec<u8> {
+        bps_sized(source, target.len(), target, actions)
+    }
+
+    /// A BPS patch claiming a target of `target_len` bytes, whatever the
+    /// actions build.
+    fn bps_sized(source: &[u8], target_len: usize, target: &[u8], actions: &[u8]) -> Vec<u8> {
+        let mut patch = b"BPS1".to_vec();
+        varint(&mut patch, source.len());
+        varint(&mut patch, target_len);
+        varint(&mut patch, 0);
+        patch.extend_from_slice(actions);
+        patch.extend_from_slice(&crc32fast::hash(source).to_le_bytes());
+        patch.extend_from_slice(&crc32fast::hash(target).to_le_bytes());
+        let crc = crc32fast::hash(&patch);
+        patch.extend_from_slice(&crc.to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn ips_records() {
+        let mut patch = b"PATCH".to_vec();
+        patch.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x02, 0xaa, 0xbb]);
+        // Run of four 0x55s, past the end of the source.
+        patch.extend_from_slice(&[0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x04, 0x55]);
+        patch.extend_from_slice(b"EOF");
+        let patch = Patch::parse(&patch).unwrap();
+        assert_eq!(patch.format(), Format::Ips);
+        assert_eq!(
+            patch.apply(&[1, 2, 3, 4]).unwrap(),
+            [1, 0xaa, 0xbb, 4, 0, 0, 0x55, 0x55, 0x55, 0x55]
+        );
+
+        let truncate = Patch::parse(b"PATCHEOF\x00\x00\x02").unwrap();
+        assert_eq!(truncate.apply(&[1, 2, 3, 4]).unwrap(), [1, 2]);
+        assert!(Patch::parse(b"PATCH\x00\x00\x01\x00\x05\xaa")
+            .unwrap()
+            .apply(&[0; 8])
+            .is_err());
+    }
+
+    #[test]
+    fn bps_actions() {
+        let source = b"invaders";
+        let target = b"INvaders, invaders!!!";
+        let mut actions = Vec::new();
+        // TargetRead "IN", SourceRead "vaders", TargetRead ", ".
+        varint(&mut actions, (2 - 1) << 2 | 1);
+        actions.extend_from_slice(b"IN");
+        varint(&mut actions, (6 - 1) << 2);
+        varint(&mut actions, (2 - 1) << 2 | 1);
+        actions.extend_from_slice(b", ");
+        // SourceCopy "invaders" from offset 0, TargetRead "!".
+        varint(&mut actions, (8 - 1) << 2 | 2);
+        varint(&mut actions, 0);
+        varint(&mut actions, 1);
+        actions.push(b'!');
+        // TargetCopy "!!" from the byte just written, overlapping itself.
+        varint(&mut actions, (2 - 1) << 2 | 3);
+        varint(&mut actions, 18 << 1);
+        let patch = Patch::parse(&bps(source, target, &actions)).unwrap();
+        assert_eq!(patch.format(), Format::Bps);
+        assert_eq!(patch.apply(source).unwrap(), &target[..]);
+
+        match patch.apply(b"INVADERS") {
+            Err(Error::PatchChecksum {
+                which: "source", ..
+            }) => {}
+            r => panic!("expected a source checksum mismatch, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn bps_checksums() {
+        let mut patch = bps(b"ab", b"ab", &[0x84]);
+        assert_eq!(Patch::parse(&patch).unwrap().apply(b"ab").unwrap(), b"ab");
+
+        // A wrong target crc, with the patch crc fixed up to match.
+        let len = patch.len();
+        patch[len - 8] ^= 0xff;
+        let crc = crc32fast::hash(&patch[..len - 4]);
+        patch[len - 4..].copy_from_slice(&crc.to_le_bytes());
+        match Patch::parse(&patch).unwrap().apply(b"ab") {
+            Err(Error::PatchChecksum {
+                which: "target", ..
+            }) => {}
+            r => panic!("expected a target checksum mismatch, got {:?}", r),
+        }
+
+        patch[len - 1] ^= 0xff;
+        match Patch::parse(&patch) {
+            Err(Error::PatchChecksum { which: "patch", .. }) => {}
+            r => panic!(
+                "expected a patch checksum mismatch, got {:?}",
+                r.map(|p| p.format())
+            ),
+        }
+    }
+
+    #[test]
+    fn bps_malformed() {
+        let invalid = |patch: Vec<u8>| match Patch::parse(&patch).unwrap().apply(b"ab") {
+            Err(Error::InvalidPatch { .. }) => {}
+            r => panic!("expected an invalid patch, got {:?}", r),
+        };
+        // A huge target size is refused before running any action.
+        invalid(bps_sized(b"ab", usize::MAX >> 1, b"ab", &[0x84]));
+        match Patch::parse(&bps_sized(b"ab", MAX_TARGET + 1, b"ab", &[0x84]))
+            .unwrap()
+            .apply(b"ab")
+        {
+            Err(Error::InvalidPatch { reason }) => assert_eq!(reason, "target too large"),
+            r => panic!("expected an oversized target, got {:?}", r),
+        }
+        // A source copy from far past the source, of as many bytes as fit.
+        let mut actions = Vec::new();
+        varint(&mut actions, (usize::MAX >> 2) << 2 | 2);
+        varint(&mut actions, (usize::MAX >> 1) << 1);
+        invalid(bps_sized(b"ab", usize::MAX, b"ab", &actions));
+        // A target copy before anything has been written.
+        invalid(bps(b"ab", b"aa", &[0x87, 0x80]));
+    }
+}
@@ -0,0 +1,382 @@
+//! Where the emulated program spends its time.
+//!
+//! A `Profiler` counts the instructions executed and cycles taken at every
+//! address, and charges them to subroutines using the cpu's shadow call
+//! stack: a routine is known by its entry point, the target of the `CALL`,
+//! `RST` or interrupt that entered it. Time spent outside any call is charged
+//! to the top level.
+//!
+//! `SpaceInvaders::set_profiler` profiles a machine however it is driven.
+//! The results can be shown as a report sorted by cycles, or written as
+//! folded stacks, one line per distinct call stack with its cycles, for
+//! flamegraph tools.
+
+use crate::{i8080::CallFrame, symbols::Symbols};
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::fmt::{self, Display};
+
+/// Rows shown in each table of a report by default.
+pub const REPORT_ROWS: usize = 20;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AddressStats {
+    pub instructions: u64,
+    pub cycles: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RoutineStats {
+    /// The routine's entry point, or None for the top level.
+    pub entry: Option<u16>,
+    pub calls: u64,
+    pub instructions: u64,
+    /// Cycles spent in the routine itself.
+    pub self_cycles: u64,
+    /// Cycles spent in the routine and everything it called.
+    pub total_cycles: u64,
+}
+
+pub struct Profiler {
+    /// Indexed by address.
+    addresses: Vec<AddressStats>,
+    routines: BTreeMap<Option<u16>, RoutineStats>,
+    /// Cycles by call stack, as the entry points of its frames.
+    stacks: Vec<(Vec<u16>, u64)>,
+    stack_index: BTreeMap<Vec<u16>, usize>,
+    /// The stack left by the last instruction, in `stacks`.
+    current: usize,
+    instructions: u64,
+    cycles: u64,
+    frames: u64,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            addresses: vec![AddressStats::default(); 0x10000],
+            routines: BTreeMap::new(),
+            stacks: vec![(Vec::new(), 0)],
+            stack_index: vec![(Vec::new(), 0)].into_iter().collect(),
+            current: 0,
+            instructions: 0,
+            cycles: 0,
+            frames: 0,
+        }
+    }
+
+    /// Records an instruction at `pc` that took `cycles` and left the call
+    /// stack as `frames`, innermost last. The instruction is charged to the
+    /// stack left by the one before, so a `CALL` counts against its caller
+    /// and a `RET` against the routine returning.
+    pub fn record(&mut self, pc: u16, cycles: u64, frames: &[CallFrame]) {
+        let address = &mut self.addresses[pc as usize];
+        address.instructions += 1;
+        address.cycles += cycles;
+        self.instructions += 1;
+        self.cycles += cycles;
+
+        let (stack, stack_cycles) = &mut self.stacks[self.current];
+        *stack_cycles += cycles;
+        let innermost = routine(&mut self.routines, stack.last().copied());
+        innermost.instructions += 1;
+        innermost.self_cycles += cycles;
+        routine(&mut self.routines, None).total_cycles += cycles;
+        for (i, &entry) in stack.iter().enumerate() {
+            // A recursive routine is only charged once.
+            if !stack[..i].contains(&entry) {
+                routine(&mut self.routines, Some(entry)).total_cycles += cycles;
+            }
+        }
+
+        let previous = self.stacks[self.current].0.len();
+        if frames.len() > previous {
+            for frame in &frames[previous..] {
+                routine(&mut self.routines, Some(frame.target)).calls += 1;
+            }
+        }
+        let unchanged = self.stacks[self.current]
+            .0
+            .iter()
+            .eq(frames.iter().map(|f| &f.target));
+        if !unchanged {
+            let key: Vec<u16> = frames.iter().map(|f| f.target).collect();
+            let stacks = &mut self.stacks;
+            self.current = *self.stack_index.entry(key.clone()).or_insert_with(|| {
+                stacks.push((key, 0));
+                stacks.len() - 1
+            });
+        }
+    }
+
+    /// Counts a finished video frame, for the per-frame figures.
+    pub fn end_frame(&mut self) {
+        self.frames += 1;
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn address(&self, addr: u16) -> AddressStats {
+        self.addresses[addr as usize]
+    }
+
+    /// Every routine seen, the most expensive including its callees first.
+    pub fn routines(&self) -> Vec<RoutineStats> {
+        let mut routines: Vec<RoutineStats> = self.routines.values().copied().collect();
+        routines.sort_by(|a, b| {
+            b.total_cycles
+                .cmp(&a.total_cycles)
+                .then(a.entry.cmp(&b.entry))
+        });
+        routines
+    }
+
+    /// Every address executed, the most cycles first.
+    pub fn hot_addresses(&self) -> Vec<(u16, AddressStats)> {
+        let mut addresses: Vec<(u16, AddressStats)> = (0..=0xffff)
+            .map(|addr| (addr, self.address(addr)))
+            .filter(|(_, stats)| stats.instructions > 0)
+            .collect();
+        addresses.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(&b.0)));
+        addresses
+    }
+
+    /// A report of the `rows` most expensive routines and addresses.
+    pub fn report<'a>(&'a self, symbols: Option<&'a Symbols>, rows: usize) -> Report<'a> {
+        Report {
+            profiler: self,
+            symbols,
+            rows,
+        }
+    }
+
+    /// Writes the cycles spent in each call stack as `outer;inner cycles`
+    /// lines, the folded format flamegraph tools read.
+    pub fn write_folded<W: fmt::Write>(
+        &self,
+        out: &mut W,
+        symbols: Option<&Symbols>,
+    ) -> fmt::Result {
+        let mut lines: Vec<(String, u64)> = self
+            .stacks
+            .iter()
+            .filter(|(_, cycles)| *cycles > 0)
+            .map(|(stack, cycles)| {
+                let names: Vec<String> = match stack.is_empty() {
+                    true => vec![routine_name(None, symbols)],
+                    false => stack
+                        .iter()
+                        .map(|&entry| routine_name(Some(entry), symbols))
+                        .collect(),
+                };
+                (names.join(";"), *cycles)
+            })
+            .collect();
+        lines.sort();
+        for (stack, cycles) in lines {
+            writeln!(out, "{} {}", stack, cycles)?;
+        }
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        *self = Profiler::new();
+    }
+}
+
+fn routine(
+    routines: &mut BTreeMap<Option<u16>, RoutineStats>,
+    entry: Option<u16>,
+) -> &mut RoutineStats {
+    routines.entry(entry).or_insert(RoutineStats {
+        entry,
+        ..RoutineStats::default()
+    })
+}
+
+fn routine_name(entry: Option<u16>, symbols: Option<&Symbols>) -> String {
+    match (entry, symbols) {
+        (None, _) => "[top]".to_string(),
+        (Some(addr), Some(symbols)) => symbols.address(addr).to_string(),
+        (Some(addr), None) => alloc::format!("0x{:04x}", addr),
+    }
+}
+
+fn percent(part: u64, whole: u64) -> f64 {
+    match whole {
+        0 => 0.0,
+        _ => part as f64 * 100.0 / whole as f64,
+    }
+}
+
+pub struct Report<'a> {
+    profiler: &'a Profiler,
+    symbols: Option<&'a Symbols>,
+    rows: usize,
+}
+
+impl<'a> Display for Report<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let profiler = self.profiler;
+        let total = profiler.cycles;
+        write!(
+            f,
+            "{} instructions, {} cycles",
+            profiler.instructions, total
+        )?;
+        if profiler.frames > 0 {
+            write!(
+                f,
+                " over {} frames, {} cycles per frame",
+                profiler.frames,
+                total / profiler.frames
+            )?;
+        }
+        writeln!(f)?;
+
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:<24} {:>8} {:>12} {:>12} {:>7} {:>12} {:>7}",
+            "routine", "calls", "instructions", "self", "self%", "total", "total%"
+        )?;
+        for routine in profiler.routines().iter().take(self.rows) {
+            writeln!(
+                f,
+                "{:<24} {:>8} {:>12} {:>12} {:>6.2}% {:>12} {:>6.2}%",
+                routine_name(routine.entry, self.symbols),
+                routine.calls,
+                routine.instructions,
+                routine.self_cycles,
+                percent(routine.self_cycles, total),
+                routine.total_cycles,
+                percent(routine.total_cycles, total),
+            )?;
+        }
+
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:<24} {:>12} {:>12} {:>7}",
+            "address", "instructions", "cycles", "cycles%"
+        )?;
+        for (addr, stats) in profiler.hot_addresses().iter().take(self.rows) {
+            let name = match self.symbols.filter(|s| s.lookup(*addr).is_some()) {
+                Some(symbols) => alloc::format!("0x{:04x} {}", addr, symbols.address(*addr)),
+                None => alloc::format!("0x{:04x}", addr),
+            };
+            writeln!(
+                f,
+                "{:<24} {:>12} {:>12} {:>6.2}%",
+                name,
+                stats.instructions,
+                stats.cycles,
+                percent(stats.cycles, total)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Profiler;
+    use crate::{machine::SpaceInvaders, symbols::Symbols};
+    use alloc::{
+        string::{String, ToString},
+        vec,
+        vec::Vec,
+    };
+
+    /// Calls `Inner` twice from `Outer`, from a main loop, forever.
+    fn rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x40];
+        rom[..0x0c].copy_from_slice(&[
+            0x31, 0x00, 0x24, // 0x0000 LXI SP,0x2400
+            0xcd, 0x20, 0x00, // 0x0003 Main: CALL Outer
+            0xc3, 0x03, 0x00, // 0x0006 JMP Main
+            0x00, 0x00, 0x00,
+        ]);
+        rom[0x20..0x29].copy_from_slice(&[
+            0xcd, 0x30, 0x00, // 0x0020 Outer: CALL Inner
+            0xcd, 0x30, 0x00, // 0x0023 CALL Inner
+            0xc9, // 0x0026 RET
+            0x00, 0x00,
+        ]);
+        rom[0x30] = 0xc9; // 0x0030 Inner: RET
+        rom
+    }
+
+    #[test]
+    fn charges_routines() {
+        let mut machine = SpaceInvaders::new(rom());
+        machine.set_profiler(Some(Profiler::new()));
+        for _ in 0..7 {
+            machine.step().unwrap();
+        }
+        let profiler = machine.profiler().unwrap();
+        assert_eq!(profiler.instructions(), 7);
+        assert_eq!(profiler.address(0x0030).instructions, 2);
+        assert_eq!(profiler.address(0x0030).cycles, 20);
+
+        let routines = profiler.routines();
+        let (top, outer, inner) = (routines[0], routines[1], routines[2]);
+        assert_eq!(top.entry, None);
+        assert_eq!(top.total_cycles, profiler.cycles());
+        assert_eq!((top.instructions, top.self_cycles), (2, 10 + 17));
+        assert_eq!(outer.entry, Some(0x0020));
+        assert_eq!((outer.calls, outer.instructions), (1, 3));
+        assert_eq!(outer.self_cycles, 17 + 17 + 10);
+        assert_eq!(outer.total_cycles, outer.self_cycles + 20);
+        assert_eq!(inner.entry, Some(0x0030));
+        assert_eq!(
+            (inner.calls, inner.self_cycles, inner.total_cycles),
+            (2, 20, 20)
+        );
+
+        let symbols = Symbols::parse("Outer = $0020\nInner = $0030").unwrap();
+        let mut folded = String::new();
+        profiler.write_folded(&mut folded, Some(&symbols)).unwrap();
+        assert_eq!(folded, "Outer 44\nOuter;Inner 20\n[top] 27\n");
+        let report = profiler.report(Some(&symbols), 2).to_string();
+        assert!(
+            report.starts_with("7 instructions, 91 cycles\n"),
+            "{}",
+            report
+        );
+        assert!(report.contains("\nOuter "), "{}", report);
+        assert!(!report.contains("\nInner "), "{}", report);
+    }
+
+    #[test]
+    fn counts_frames() {
+        let mut machine = SpaceInvaders::new(rom());
+        machine.set_profiler(Some(Profiler::new()));
+        machine.run_frame().unwrap();
+        machine.run_frame().unwrap();
+        let profiler = machine.take_profiler().unwrap();
+        assert_eq!(profiler.frames(), 2);
+        assert!(profiler.cycles() >= 2 * crate::machine::CYCLES_PER_FRAME);
+        assert!(machine.profiler().is_none());
+    }
+}
@@ -0,0 +1,377 @@
+//! Saving frames as images and loading them back for comparison.
+//!
+//! PPM (binary `P6`) is always available. PNG needs the `png` feature.
+//! Loaded images are reduced back to a 1 bit `Frame`, any non-black pixel
+//! counting as lit, so a screenshot taken with the colour overlay compares
+//! equal to the frame it came from.
+
+use crate::{
+    machine::{Frame, SpaceInvaders, SCREEN_HEIGHT, SCREEN_WIDTH},
+    Error,
+};
+use std::{
+    fs,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Ppm,
+    #[cfg(feature = "png")]
+    Png,
+}
+
+impl ImageFormat {
+    /// Picks the format from a file extension, `ppm` or `png`.
+    pub fn from_extension(extension: &str) -> Option<ImageFormat> {
+        match extension.to_ascii_lowercase().as_str() {
+            "ppm" => Some(ImageFormat::Ppm),
+            #[cfg(feature = "png")]
+            "png" => Some(ImageFormat::Png),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Ppm => "ppm",
+            #[cfg(feature = "png")]
+            ImageFormat::Png => "png",
+        }
+    }
+
+    fn from_path(path: &Path) -> Result<ImageFormat, Error> {
+        path.extension()
+            .and_then(|e| e.to_str())
+            .and_then(ImageFormat::from_extension)
+            .ok_or(Error::InvalidImage {
+                reason: "unsupported image file extension",
+            })
+    }
+}
+
+/// Writes `frame` as a binary PPM.
+pub fn write_ppm<W: Write>(out: W, frame: &Frame, overlay: bool) -> Result<(), Error> {
+    encode_ppm(out, SCREEN_WIDTH, SCREEN_HEIGHT, &frame.to_rgb(overlay))
+}
+
+/// Writes `frame` as an 8 bit RGB PNG.
+#[cfg(feature = "png")]
+pub fn write_png<W: Write>(out: W, frame: &Frame, overlay: bool) -> Result<(), Error> {
+    encode_png(out, SCREEN_WIDTH, SCREEN_HEIGHT, &frame.to_rgb(overlay))
+}
+
+/// Saves `frame` to `path` in the format its extension names.
+pub fn save<P: AsRef<Path>>(frame: &Frame, path: P, overlay: bool) -> Result<(), Error> {
+    save_rgb(path, SCREEN_WIDTH, SCREEN_HEIGHT, &frame.to_rgb(overlay))
+}
+
+/// Saves any picture, given as `0xRRGGBB` pixels row by row from the top
+/// left, to `path` in the format its extension names.
+pub fn save_rgb<P: AsRef<Path>>(
+    path: P,
+    width: usize,
+    height: usize,
+    pixels: &[u32],
+) -> Result<(), Error> {
+    let path = path.as_ref();
+    let format = ImageFormat::from_path(path)?;
+    let mut out = BufWriter::new(fs::File::create(path)?);
+    match format {
+        ImageFormat::Ppm => encode_ppm(&mut out, width, height, pixels)?,
+        #[cfg(feature = "png")]
+        ImageFormat::Png => encode_png(&mut out, width, height, pixels)?,
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn encode_ppm<W: Write>(
+    mut out: W,
+    width: usize,
+    height: usize,
+    pixels: &[u32],
+) -> Result<(), Error> {
+    write!(out, "P6\n{} {}\n255\n", width, height)?;
+    out.write_all(&rgb_bytes(pixels))?;
+    Ok(())
+}
+
+#[cfg(feature = "png")]
+fn encode_png<W: Write>(out: W, width: usize, height: usize, pixels: &[u32]) -> Result<(), Error> {
+    let mut encoder = png::Encoder::new(out, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(png_error)?;
+    writer
+        .write_image_data(&rgb_bytes(pixels))
+        .map_err(png_error)?;
+    writer.finish().map_err(png_error)
+}
+
+/// Loads an image saved by `save`, or any 224x256 PPM or PNG.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Frame, Error> {
+    let path = path.as_ref();
+    let format = ImageFormat::from_path(path)?;
+    let bytes = fs::read(path)?;
+    match format {
+        ImageFormat::Ppm => read_ppm(&bytes),
+        #[cfg(feature = "png")]
+        ImageFormat::Png => read_png(&bytes),
+    }
+}
+
+/// Decodes a binary (`P6`) PPM.
+pub fn read_ppm(bytes: &[u8]) -> Result<Frame, Error> {
+    let invalid = |reason| Error::InvalidImage { reason };
+    let mut rest = bytes;
+    let mut fields = [0usize; 3];
+    if !rest.starts_with(b"P6") {
+        return Err(invalid("not a binary PPM"));
+    }
+    rest = &rest[2..];
+    for field in fields.iter_mut() {
+        // Skip whitespace and comments up to the next number.
+        loop {
+            match rest.first() {
+                Some(b) if b.is_ascii_whitespace() => rest = &rest[1..],
+                Some(b'#') => {
+                    let end = rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+                    rest = &rest[end..];
+                }
+                _ => break,
+            }
+        }
+        let digits = rest.iter().take_while(|b| b.is_ascii_digit()).count();
+        *field = std::str::from_utf8(&rest[..digits])
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| invalid("malformed PPM header"))?;
+        rest = &rest[digits..];
+    }
+    // A single whitespace byte separates the header from the pixels.
+    rest = rest.get(1..).unwrap_or(&[]);
+    let [width, height, max] = fields;
+    if max == 0 || max > 255 {
+        return Err(invalid("only 8 bit PPMs are supported"));
+    }
+    from_rgb(width, height, 3, rest)
+}
+
+#[cfg(feature = "png")]
+pub fn read_png(bytes: &[u8]) -> Result<Frame, Error> {
+    let mut decoder = png::Decoder::new(bytes);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().map_err(png_decode_error)?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).map_err(png_decode_error)?;
+    let channels = info.color_type.samples();
+    from_rgb(
+        info.width as usize,
+        info.height as usize,
+        channels,
+        &buf[..info.buffer_size()],
+    )
+}
+
+fn from_rgb(width: usize, height: usize, channels: usize, data: &[u8]) -> Result<Frame, Error> {
+    if (width, height) != (SCREEN_WIDTH, SCREEN_HEIGHT) {
+        return Err(Error::InvalidImage {
+            reason: "image is not 224x256",
+        });
+    }
+    if data.len() < width * height * channels {
+        return Err(Error::InvalidImage {
+            reason: "truncated pixel data",
+        });
+    }
+    // Alpha, when present, is ignored.
+    let colors = channels.min(3);
+    let pixels = data
+        .chunks(channels)
+        .take(width * height)
+        .map(|pixel| pixel[..colors].iter().any(|&c| c != 0))
+        .collect();
+    Ok(Frame::from_pixels(pixels))
+}
+
+fn rgb_bytes(pixels: &[u32]) -> Vec<u8> {
+    pixels
+        .iter()
+        .flat_map(|&rgb| [(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8])
+        .collect()
+}
+
+#[cfg(feature = "png")]
+fn png_error(e: png::EncodingError) -> Error {
+    match e {
+        png::EncodingError::IoError(e) => Error::Io(e),
+        _ => Error::InvalidImage {
+            reason: "PNG encoding failed",
+        },
+    }
+}
+
+#[cfg(feature = "png")]
+fn png_decode_error(e: png::DecodingError) -> Error {
+    match e {
+        png::DecodingError::IoError(e) => Error::Io(e),
+        _ => Error::InvalidImage {
+            reason: "malformed PNG",
+        },
+    }
+}
+
+/// Saves every `every`th frame of a run into a directory.
+pub struct FrameDumper {
+    dir: PathBuf,
+    every: u64,
+    /// The frame count the run began at, which `every` counts from.
+    start: u64,
+    format: ImageFormat,
+    overlay: bool,
+}
+
+impl FrameDumper {
+    /// Creates `dir` if needed. Frames are named `frame_000600.ppm` and so on,
+    /// after the machine's frame count.
+    pub fn new<P: Into<PathBuf>>(
+        dir: P,
+        every: u64,
+        format: ImageFormat,
+        overlay: bool,
+    ) -> Result<FrameDumper, Error> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(FrameDumper {
+            dir,
+            every: every.max(1),
+            start: 0,
+            format,
+            overlay,
+        })
+    }
+
+    /// Counts frames from `frame` rather than 0, for runs that begin from a
+    /// loaded state.
+    pub fn with_start(mut self, frame: u64) -> FrameDumper {
+        self.start = frame;
+        self
+    }
+
+    /// Saves the machine's current frame if its number is due, returning the
+    /// path written.
+    pub fn dump(&self, machine: &SpaceInvaders) -> Result<Option<PathBuf>, Error> {
+        self.dump_with(machine, |_| {})
+    }
+
+    /// Like `dump`, letting `decorate` draw over the picture before it is
+    /// saved.
+    pub fn dump_with<F: FnOnce(&mut [u32])>(
+        &self,
+        machine: &SpaceInvaders,
+        decorate: F,
+    ) -> Result<Option<PathBuf>, Error> {
+        let path = match self.due_path(machine, "frame") {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let mut pixels = machine.frame().to_rgb(self.overlay);
+        decorate(&mut pixels);
+        save_rgb(&path, SCREEN_WIDTH, SCREEN_HEIGHT, &pixels)?;
+        Ok(Some(path))
+    }
+
+    /// Saves a picture of the video ram writes the machine's last frame made,
+    /// as `vram_000600.ppm` and so on, if its number is due and the machine
+    /// has a heatmap recording them.
+    pub fn dump_vram_writes(&self, machine: &SpaceInvaders) -> Result<Option<PathBuf>, Error> {
+        let (heatmap, path) = match (machine.heatmap(), self.due_path(machine, "vram")) {
+            (Some(heatmap), Some(path)) => (heatmap, path),
+            _ => return Ok(None),
+        };
+        let pixels = heatmap.vram_writes().to_rgb();
+        save_rgb(&path, SCREEN_WIDTH, SCREEN_HEIGHT, &pixels)?;
+        Ok(Some(path))
+    }
+
+    fn due_path(&self, machine: &SpaceInvaders, prefix: &str) -> Option<PathBuf> {
+        let count = machine.frame_count();
+        if !count.wrapping_sub(self.start).is_multiple_of(self.every) {
+            return None;
+        }
+        Some(self.dir.join(format!(
+            "{}_{:06}.{}",
+            prefix,
+            count,
+            self.format.extension()
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_ppm, write_ppm, FrameDumper, ImageFormat};
+    use crate::machine::{Frame, SpaceInvaders};
+
+    fn frame() -> Frame {
+        let mut vram = [0; 0x1c00];
+        vram[0] = 0x81;
+        vram[0x0e10] = 0x3c;
+        Frame::from_vram(&vram)
+    }
+
+    #[test]
+    fn ppm_round_trip() {
+        let frame = frame();
+        let mut bytes = Vec::new();
+        write_ppm(&mut bytes, &frame, true).unwrap();
+        assert!(bytes.starts_with(b"P6\n224 256\n255\n"));
+        assert_eq!(bytes.len(), 15 + 224 * 256 * 3);
+        assert!(read_ppm(&bytes).unwrap() == frame);
+
+        let mut commented = b"P6 # screenshot\n224 256 255\n".to_vec();
+        commented.extend_from_slice(&bytes[15..]);
+        assert!(read_ppm(&commented).unwrap() == frame);
+        assert!(read_ppm(&bytes[..100]).is_err());
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn png_round_trip() {
+        let frame = frame();
+        let mut bytes = Vec::new();
+        super::write_png(&mut bytes, &frame, true).unwrap();
+        assert!(super::read_png(&bytes).unwrap() == frame);
+    }
+
+    #[test]
+    fn dumps_every_n_frames() {
+        let dir = std::env::temp_dir().join(format!("i8080-dump-{}", std::process::id()));
+        let dumper = FrameDumper::new(&dir, 2, ImageFormat::Ppm, false).unwrap();
+        let mut machine = SpaceInvaders::new([0x76]);
+        let mut written = Vec::new();
+        for _ in 0..4 {
+            machine.run_frame().unwrap();
+            written.extend(dumper.dump(&machine).unwrap());
+        }
+        assert_eq!(
+            written,
+            [dir.join("frame_000002.ppm"), dir.join("frame_000004.ppm")]
+        );
+        assert!(super::load(&written[0]).unwrap() == Frame::new());
+
+        // Counting from frame 5, frame 7 is due rather than 6.
+        machine.run_frame().unwrap();
+        let dumper = dumper.with_start(machine.frame_count());
+        machine.run_frame().unwrap();
+        assert_eq!(dumper.dump(&machine).unwrap(), None);
+        machine.run_frame().unwrap();
+        assert_eq!(
+            dumper.dump(&machine).unwrap(),
+            Some(dir.join("frame_000007.ppm"))
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
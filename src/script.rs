@@ -0,0 +1,506 @@
+//! Rhai scripts driving `SpaceInvaders`, for bots, test scenarios and
+//! overlays.
+//!
+//! A script's top level runs once when it is loaded, typically to register
+//! callbacks; the host then runs the machine a frame at a time through
+//! `Script::run_frame`, which calls them as the machine gets there:
+//!
+//! ```text
+//! on_frame(|frame| {
+//!     if frame == 60 { hold("coin", 8); }
+//!     if frame == 120 { hold("start1", 8); }
+//!     if frame > 200 { press("fire1"); }
+//!     if frame == 300 {
+//!         if peek(0x20f8) == 0 { throw "no score after 100 frames of fire"; }
+//!         stop();
+//!     }
+//! });
+//! ```
+//!
+//! Scripts see these functions:
+//!
+//! - `peek(addr)`, `peek16(addr)` and `poke(addr, value)` read and write
+//!   memory; `peek16` is little endian.
+//! - `reg(name)` and `set_reg(name, value)` for `a` to `l`, the pairs `bc`,
+//!   `de` and `hl`, `sp` and `pc`.
+//! - `press(button)`, `release(button)` and `hold(button, frames)`, with the
+//!   button names of `Button::name`.
+//! - `frame()`, the machine's frame count.
+//! - `on_exec(addr, |pc| ..)`, called before the instruction at `addr` runs;
+//!   `on_write(addr, |addr, before, after| ..)`, called after a write to `addr`;
+//!   and `on_frame(|frame| ..)`, called at the end of every frame.
+//! - `rect(x, y, width, height, color)` outlines a box on this frame's
+//!   overlay, in screen pixels and `0xRRGGBB`.
+//! - `stop()` ends the run once the frame is over.
+//!
+//! A script error, including a `throw`, stops the frame and is returned as
+//! `Error::Script`.
+
+use crate::{
+    debugger::Target,
+    i8080::Register,
+    interconnect::{Button, Rom},
+    machine::{SpaceInvaders, SCREEN_HEIGHT, SCREEN_WIDTH},
+    Error, Probe,
+};
+use alloc::{collections::BTreeMap, format, rc::Rc, vec::Vec};
+use core::{cell::RefCell, convert::TryFrom, mem};
+use rhai::{Engine, EvalAltResult, FnPtr, Scope, AST, INT};
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// A box outlined over the screen by a script's `rect`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    pub color: u32,
+}
+
+/// What the script's functions reach, shared between them and the host.
+struct State {
+    /// The machine being run, swapped in from the host for each call into
+    /// the script and out again afterwards.
+    machine: SpaceInvaders,
+    on_exec: BTreeMap<u16, Vec<FnPtr>>,
+    on_write: BTreeMap<u16, Vec<FnPtr>>,
+    on_frame: Vec<FnPtr>,
+    /// Buttons held by `hold`, with the frame count that releases them.
+    holds: Vec<(Button, u64)>,
+    overlay: Vec<Rect>,
+    stopped: bool,
+}
+
+/// Collects the writes made to addresses with `on_write` callbacks.
+struct Writes<'a> {
+    watched: &'a BTreeMap<u16, Vec<FnPtr>>,
+    hits: Vec<(u16, u8, u8)>,
+}
+
+impl<'a> Probe for Writes<'a> {
+    fn write(&mut self, addr: u16, old: u8, new: u8) {
+        if self.watched.contains_key(&addr) {
+            self.hits.push((addr, old, new));
+        }
+    }
+}
+
+/// A loaded script and the callbacks it registered.
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    state: Rc<RefCell<State>>,
+}
+
+impl Script {
+    /// Compiles `source` and runs its top level against `machine`.
+    pub fn new(source: &str, machine: &mut SpaceInvaders) -> Result<Script, Error> {
+        let state = Rc::new(RefCell::new(State {
+            // Stands in for the host's machine between calls; never run.
+            machine: SpaceInvaders::new(Rom::from([0; 0])),
+            on_exec: BTreeMap::new(),
+            on_write: BTreeMap::new(),
+            on_frame: Vec::new(),
+            holds: Vec::new(),
+            overlay: Vec::new(),
+            stopped: false,
+        }));
+        let engine = engine(&state);
+        let ast = engine
+            .compile(source)
+            .map_err(|e| Error::Script(e.into()))?;
+        let mut script = Script {
+            engine,
+            ast,
+            scope: Scope::new(),
+            state,
+        };
+        script.with_machine(machine, |script| {
+            script
+                .engine
+                .run_ast_with_scope(&mut script.scope, &script.ast)
+                .map_err(Error::Script)
+        })?;
+        Ok(script)
+    }
+
+    pub fn load<P: AsRef<std::path::Path>>(
+        path: P,
+        machine: &mut SpaceInvaders,
+    ) -> Result<Script, Error> {
+        Script::new(&std::fs::read_to_string(path)?, machine)
+    }
+
+    /// Runs one video frame, calling the script's callbacks along the way.
+    pub fn run_frame(&mut self, machine: &mut SpaceInvaders) -> Result<(), Error> {
+        self.with_machine(machine, Script::frame)
+    }
+
+    /// Whether the script has called `stop`.
+    pub fn is_stopped(&self) -> bool {
+        self.state.borrow().stopped
+    }
+
+    /// The boxes the script outlined during the last frame.
+    pub fn overlay(&self) -> Vec<Rect> {
+        self.state.borrow().overlay.clone()
+    }
+
+    /// Outlines the overlay's boxes on a screen-sized picture, such as one
+    /// from `Frame::to_rgb`.
+    pub fn draw_overlay(&self, pixels: &mut [u32]) {
+        for rect in self.state.borrow().overlay.iter() {
+            let right = (rect.x + rect.width).min(SCREEN_WIDTH);
+            let bottom = (rect.y + rect.height).min(SCREEN_HEIGHT);
+            for y in rect.y..bottom {
+                for x in rect.x..right {
+                    let edge = y == rect.y || y + 1 == bottom || x == rect.x || x + 1 == right;
+                    if edge {
+                        pixels[y * SCREEN_WIDTH + x] = rect.color;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Lends `machine` to the script's functions while `f` runs.
+    fn with_machine<R>(
+        &mut self,
+        machine: &mut SpaceInvaders,
+        f: impl FnOnce(&mut Script) -> R,
+    ) -> R {
+        mem::swap(machine, &mut self.state.borrow_mut().machine);
+        let result = f(self);
+        mem::swap(machine, &mut self.state.borrow_mut().machine);
+        result
+    }
+
+    fn frame(&mut self) -> Result<(), Error> {
+        loop {
+            let (pc, frames) = {
+                let state = self.state.borrow();
+                let cpu = state.machine.cpu();
+                (
+                    Some(cpu.pc()).filter(|_| !cpu.is_halted()),
+                    state.machine.frame_count(),
+                )
+            };
+            if let Some(pc) = pc {
+                let callbacks = self.state.borrow().on_exec.get(&pc).cloned();
+                for callback in callbacks.unwrap_or_default() {
+                    self.call(&callback, (INT::from(pc),))?;
+                }
+            }
+
+            let hits = {
+                let mut state = self.state.borrow_mut();
+                let State {
+                    machine, on_write, ..
+                } = &mut *state;
+                let mut writes = Writes {
+                    watched: on_write,
+                    hits: Vec::new(),
+                };
+                machine.step_probed(&mut writes)?;
+                writes.hits
+            };
+            for (addr, old, new) in hits {
+                let callbacks = self.state.borrow().on_write.get(&addr).cloned();
+                for callback in callbacks.unwrap_or_default() {
+                    let args = (INT::from(addr), INT::from(old), INT::from(new));
+                    self.call(&callback, args)?;
+                }
+            }
+
+            let frame = {
+                let mut state = self.state.borrow_mut();
+                let frame = state.machine.frame_count();
+                if frame == frames {
+                    continue;
+                }
+                let State { machine, holds, .. } = &mut *state;
+                holds.retain(|&(button, until)| {
+                    if until <= frame {
+                        machine.game_pad_mut().release(button);
+                    }
+                    until > frame
+                });
+                state.overlay.clear();
+                frame
+            };
+            let callbacks = self.state.borrow().on_frame.clone();
+            for callback in callbacks {
+                self.call(&callback, (frame as INT,))?;
+            }
+            return Ok(());
+        }
+    }
+
+    fn call(&mut self, callback: &FnPtr, args: impl rhai::FuncArgs) -> Result<(), Error> {
+        callback
+            .call::<rhai::Dynamic>(&self.engine, &self.ast, args)
+            .map(|_| ())
+            .map_err(Error::Script)
+    }
+}
+
+/// Builds an engine whose functions act on `state`.
+fn engine(state: &Rc<RefCell<State>>) -> Engine {
+    let mut engine = Engine::new();
+
+    let s = state.clone();
+    engine.register_fn("peek", move |addr: INT| -> ScriptResult<INT> {
+        Ok(s.borrow().machine.read_byte(address(addr)?).into())
+    });
+    let s = state.clone();
+    engine.register_fn("peek16", move |addr: INT| -> ScriptResult<INT> {
+        let state = s.borrow();
+        let low = state.machine.read_byte(address(addr)?);
+        let high = state.machine.read_byte(address(addr)?.wrapping_add(1));
+        Ok(u16::from_le_bytes([low, high]).into())
+    });
+    let s = state.clone();
+    engine.register_fn("poke", move |addr: INT, value: INT| -> ScriptResult<()> {
+        let (addr, value) = (address(addr)?, byte(value)?);
+        s.borrow_mut().machine.write_byte(addr, value);
+        Ok(())
+    });
+
+    let s = state.clone();
+    engine.register_fn("reg", move |name: &str| -> ScriptResult<INT> {
+        let state = s.borrow();
+        let cpu = state.machine.cpu();
+        let get = |r| cpu.get_8bit_register(r).map(INT::from);
+        let pair = |h, l| Ok(get(h)? << 8 | get(l)?);
+        let value = match name {
+            "bc" => pair(Register::B, Register::C),
+            "de" => pair(Register::D, Register::E),
+            "hl" => pair(Register::H, Register::L),
+            "sp" => Ok(cpu.sp().into()),
+            "pc" => Ok(cpu.pc().into()),
+            _ => get(register(name)?),
+        };
+        value.map_err(|e| format!("{}", e).into())
+    });
+    let s = state.clone();
+    engine.register_fn(
+        "set_reg",
+        move |name: &str, value: INT| -> ScriptResult<()> {
+            let mut state = s.borrow_mut();
+            let cpu = state.machine.cpu_mut();
+            let (high, low) = match name {
+                "bc" => (Register::B, Register::C),
+                "de" => (Register::D, Register::E),
+                "hl" => (Register::H, Register::L),
+                "sp" => {
+                    cpu.set_sp(address(value)?);
+                    return Ok(());
+                }
+                "pc" => {
+                    cpu.set_pc(address(value)?);
+                    return Ok(());
+                }
+                _ => {
+                    let r = register(name)?;
+                    return cpu
+                        .set_register(r, byte(value)?)
+                        .map_err(|e| format!("{}", e).into());
+                }
+            };
+            let [h, l] = address(value)?.to_be_bytes();
+            cpu.set_register(high, h)
+                .and_then(|_| cpu.set_register(low, l))
+                .map_err(|e| format!("{}", e).into())
+        },
+    );
+    let s = state.clone();
+    engine.register_fn("press", move |name: &str| -> ScriptResult<()> {
+        s.borrow_mut().machine.game_pad_mut().press(button(name)?);
+        Ok(())
+    });
+    let s = state.clone();
+    engine.register_fn("release", move |name: &str| -> ScriptResult<()> {
+        s.borrow_mut().machine.game_pad_mut().release(button(name)?);
+        Ok(())
+    });
+    let s = state.clone();
+    engine.register_fn("hold", move |name: &str, frames: INT| -> ScriptResult<()> {
+        let button = button(name)?;
+        let frames = u64::try_from(frames).map_err(|_| "negative frame count")?;
+        let mut state = s.borrow_mut();
+        let until = state.machine.frame_count() + frames;
+        state.machine.game_pad_mut().press(button);
+        state.holds.retain(|&(b, _)| b != button);
+        state.holds.push((button, until));
+        Ok(())
+    });
+    let s = state.clone();
+    engine.register_fn("frame", move || s.borrow().machine.frame_count() as INT);
+
+    let s = state.clone();
+    engine.register_fn("on_exec", move |addr: INT, f: FnPtr| -> ScriptResult<()> {
+        let addr = address(addr)?;
+        s.borrow_mut().on_exec.entry(addr).or_default().push(f);
+        Ok(())
+    });
+    let s = state.clone();
+    engine.register_fn("on_write", move |addr: INT, f: FnPtr| -> ScriptResult<()> {
+        let addr = address(addr)?;
+        s.borrow_mut().on_write.entry(addr).or_default().push(f);
+        Ok(())
+    });
+    let s = state.clone();
+    engine.register_fn("on_frame", move |f: FnPtr| s.borrow_mut().on_frame.push(f));
+
+    let s = state.clone();
+    engine.register_fn(
+        "rect",
+        move |x: INT, y: INT, width: INT, height: INT, color: INT| -> ScriptResult<()> {
+            let size = |v: INT| usize::try_from(v).map_err(|_| "negative rectangle size");
+            s.borrow_mut().overlay.push(Rect {
+                x: size(x)?,
+                y: size(y)?,
+                width: size(width)?,
+                height: size(height)?,
+                color: color as u32 & 0xff_ffff,
+            });
+            Ok(())
+        },
+    );
+    let s = state.clone();
+    engine.register_fn("stop", move || s.borrow_mut().stopped = true);
+
+    engine
+}
+
+fn address(addr: INT) -> ScriptResult<u16> {
+    u16::try_from(addr).map_err(|_| format!("address {} out of range", addr).into())
+}
+
+fn byte(value: INT) -> ScriptResult<u8> {
+    u8::try_from(value).map_err(|_| format!("byte {} out of range", value).into())
+}
+
+fn register(name: &str) -> ScriptResult<Register> {
+    Ok(match name {
+        "a" => Register::A,
+        "b" => Register::B,
+        "c" => Register::C,
+        "d" => Register::D,
+        "e" => Register::E,
+        "h" => Register::H,
+        "l" => Register::L,
+        _ => return Err(format!("unknown register {}", name).into()),
+    })
+}
+
+fn button(name: &str) -> ScriptResult<Button> {
+    Button::from_name(name).ok_or_else(|| format!("unknown button {}", name).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Rect, Script};
+    use crate::{
+        i8080::Register,
+        interconnect::Button,
+        machine::{SpaceInvaders, SCREEN_HEIGHT, SCREEN_WIDTH},
+        Error,
+    };
+
+    /// Counts up in the byte at 0x2000 forever.
+    fn counter() -> SpaceInvaders {
+        SpaceInvaders::new([
+            0x31, 0x00, 0x24, // LXI SP, 0x2400
+            0x3a, 0x00, 0x20, // LDA 0x2000
+            0x3c, // INR A
+            0x32, 0x00, 0x20, // STA 0x2000
+            0xc3, 0x03, 0x00, // JMP 0x0003
+        ])
+    }
+
+    #[test]
+    fn callbacks() {
+        let mut machine = counter();
+        let source = r#"
+            let increments = 0;
+            let writes = 0;
+            on_exec(0x0006, |pc| increments += 1);
+            on_write(0x2000, |addr, before, after| {
+                if after != increments % 256 { throw "write without an increment"; }
+                writes += 1;
+            });
+            on_frame(|frame| {
+                if reg("sp") != 0x2400 { throw "stack moved"; }
+                if frame == 2 {
+                    if writes < 1000 { throw "missed writes"; }
+                    stop();
+                }
+            });
+        "#;
+        let mut script = Script::new(source, &mut machine).unwrap();
+        script.run_frame(&mut machine).unwrap();
+        assert!(!script.is_stopped());
+        script.run_frame(&mut machine).unwrap();
+        assert!(script.is_stopped());
+        assert_eq!(machine.frame_count(), 2);
+    }
+
+    #[test]
+    fn machine_access() {
+        let mut machine = counter();
+        let source = r#"
+            hold("coin", 2);
+            poke(0x2100, 0x42);
+            set_reg("hl", 0x1234);
+            on_frame(|frame| rect(1, 1, 3, 3, 0xff0000));
+        "#;
+        let mut script = Script::new(source, &mut machine).unwrap();
+        assert_eq!(machine.interconnect().read_byte(0x2100), 0x42);
+        assert_eq!(machine.cpu().get_8bit_register(Register::H).unwrap(), 0x12);
+        assert_eq!(machine.cpu().get_8bit_register(Register::L).unwrap(), 0x34);
+        assert!(machine.game_pad().is_pressed(Button::Coin));
+
+        script.run_frame(&mut machine).unwrap();
+        assert!(machine.game_pad().is_pressed(Button::Coin));
+        script.run_frame(&mut machine).unwrap();
+        assert!(!machine.game_pad().is_pressed(Button::Coin));
+
+        let red = Rect {
+            x: 1,
+            y: 1,
+            width: 3,
+            height: 3,
+            color: 0xff0000,
+        };
+        assert_eq!(script.overlay(), [red]);
+        let mut pixels = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+        script.draw_overlay(&mut pixels);
+        assert_eq!(pixels[SCREEN_WIDTH + 1], 0xff0000);
+        assert_eq!(pixels[3 * SCREEN_WIDTH + 3], 0xff0000);
+        assert_eq!(pixels[2 * SCREEN_WIDTH + 2], 0);
+    }
+
+    #[test]
+    fn errors() {
+        let mut machine = counter();
+        assert!(matches!(
+            Script::new("poke(0x10000, 1);", &mut machine),
+            Err(Error::Script(_))
+        ));
+        assert!(matches!(
+            Script::new("press(\"jump\");", &mut machine),
+            Err(Error::Script(_))
+        ));
+
+        let source = r#"on_frame(|frame| { if frame == 1 { throw "boom"; } });"#;
+        let mut script = Script::new(source, &mut machine).unwrap();
+        let err = script.run_frame(&mut machine).unwrap_err();
+        assert!(err.to_string().contains("boom"), "{}", err);
+        // The machine is handed back even when the script fails.
+        assert_eq!(machine.frame_count(), 1);
+    }
+}
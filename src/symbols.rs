@@ -0,0 +1,293 @@
+//! Symbol maps, naming addresses in traces, disassembly and the debuggers.
+//!
+//! Symbol files have one symbol per line, in any of the forms assemblers and
+//! hand-annotated lists commonly use:
+//!
+//! ```text
+//! ; comments start with ';' or '#'
+//! DrawSprite = $15d3
+//! DrawSprite EQU 15D3H
+//! 15D3 DrawSprite
+//! DrawSprite 0x15d3
+//! ```
+//!
+//! Numbers are hex, with or without a `$`, `0x` or `h` marker. An address
+//! without a symbol of its own is shown relative to the nearest symbol below
+//! it, as `DrawSprite+0x12`.
+
+use crate::{instruction::Instruction, Error};
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt::{self, Display};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Symbols {
+    /// The first name given to each address.
+    names: BTreeMap<u16, String>,
+    addresses: BTreeMap<String, u16>,
+}
+
+impl Symbols {
+    pub fn new() -> Symbols {
+        Symbols::default()
+    }
+
+    /// Parses a symbol file in any of the forms described in the module
+    /// documentation.
+    pub fn parse(text: &str) -> Result<Symbols, Error> {
+        let mut symbols = Symbols::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split([';', '#']).next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = |reason| Error::InvalidSymbols {
+                line: i + 1,
+                reason,
+            };
+            let (name, addr) =
+                parse_line(line).ok_or_else(|| invalid("expected a name and an address"))?;
+            if !is_name(name) {
+                return Err(invalid("invalid symbol name"));
+            }
+            symbols.insert(name, addr);
+        }
+        Ok(symbols)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Symbols, Error> {
+        Symbols::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Names `addr`. An address with several names is shown by the first.
+    pub fn insert(&mut self, name: &str, addr: u16) {
+        self.names.entry(addr).or_insert_with(|| name.to_string());
+        self.addresses.insert(name.to_string(), addr);
+    }
+
+    pub fn len(&self) -> usize {
+        self.addresses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    /// The address of the symbol called `name`.
+    pub fn get(&self, name: &str) -> Option<u16> {
+        self.addresses.get(name).copied()
+    }
+
+    /// The symbol naming exactly `addr`.
+    pub fn name(&self, addr: u16) -> Option<&str> {
+        self.names.get(&addr).map(String::as_str)
+    }
+
+    /// The nearest symbol at or below `addr`, and how far past it `addr` is.
+    pub fn lookup(&self, addr: u16) -> Option<(&str, u16)> {
+        self.names
+            .range(..=addr)
+            .next_back()
+            .map(|(&base, name)| (name.as_str(), addr - base))
+    }
+
+    /// Displays `addr` as `Name`, `Name+0x12`, or `0x1234` if no symbol is
+    /// below it.
+    pub fn address(&self, addr: u16) -> SymbolicAddress<'_> {
+        SymbolicAddress {
+            symbols: self,
+            addr,
+        }
+    }
+
+    /// Displays `instruction` with its address operand replaced by the
+    /// symbol naming it, if there is one.
+    pub fn instruction<'a>(&'a self, instruction: &'a Instruction) -> SymbolicInstruction<'a> {
+        SymbolicInstruction {
+            symbols: self,
+            instruction,
+        }
+    }
+
+    /// Parses an address given as a number or a symbol, with an optional
+    /// offset: `0x15d3`, `DrawSprite`, `DrawSprite+0x12`.
+    pub fn resolve(&self, text: &str) -> Option<u16> {
+        let text = text.trim();
+        let (base, offset) = match text.find(['+', '-']) {
+            Some(i) => {
+                let offset = parse_number(text[i + 1..].trim())?;
+                let base = text[..i].trim();
+                match &text[i..=i] {
+                    "+" => (base, offset),
+                    _ => (base, offset.wrapping_neg()),
+                }
+            }
+            None => (text, 0),
+        };
+        let addr = self.get(base).or_else(|| parse_number(base))?;
+        Some(addr.wrapping_add(offset))
+    }
+
+    /// Every symbol, in address order.
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &str)> + '_ {
+        let mut symbols: Vec<(u16, &str)> = self
+            .addresses
+            .iter()
+            .map(|(name, &addr)| (addr, name.as_str()))
+            .collect();
+        symbols.sort_unstable();
+        symbols.into_iter()
+    }
+}
+
+/// Splits `name = addr`, `name equ addr`, `addr name` or `name addr`.
+fn parse_line(line: &str) -> Option<(&str, u16)> {
+    if let Some((name, addr)) = line.split_once('=') {
+        return Some((
+            name.trim().trim_end_matches(':'),
+            parse_number(addr.trim())?,
+        ));
+    }
+    let fields: Vec<&str> = line
+        .split_whitespace()
+        .map(|f| f.trim_end_matches(':'))
+        .filter(|f| !f.eq_ignore_ascii_case("equ") && !f.eq_ignore_ascii_case(".equ"))
+        .collect();
+    let (first, second) = match fields[..] {
+        [first, second] => (first, second),
+        _ => return None,
+    };
+    match (parse_number(first), parse_number(second)) {
+        // A hex-looking name, like `ADD 0x1234`: the marked or four digit
+        // number is the address.
+        (Some(addr), Some(_)) if is_marked(first) || (!is_marked(second) && first.len() == 4) => {
+            Some((second, addr))
+        }
+        (_, Some(addr)) => Some((first, addr)),
+        (Some(addr), None) => Some((second, addr)),
+        (None, None) => None,
+    }
+}
+
+fn is_marked(number: &str) -> bool {
+    number.starts_with('$') || number.starts_with("0x") || number.ends_with(['h', 'H'])
+}
+
+/// A hex number, optionally written `$1234`, `0x1234` or `1234h`.
+fn parse_number(text: &str) -> Option<u16> {
+    let digits = text
+        .strip_prefix('$')
+        .or_else(|| text.strip_prefix("0x"))
+        .or_else(|| text.strip_prefix("0X"))
+        .or_else(|| text.strip_suffix(['h', 'H']))
+        .unwrap_or(text);
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    u16::from_str_radix(digits, 16).ok()
+}
+
+fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || "_.@".contains(c))
+        && chars.all(|c| c.is_ascii_alphanumeric() || "_.@?$".contains(c))
+}
+
+pub struct SymbolicAddress<'a> {
+    symbols: &'a Symbols,
+    addr: u16,
+}
+
+impl<'a> Display for SymbolicAddress<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.symbols.lookup(self.addr) {
+            Some((name, 0)) => write!(f, "{}", name),
+            Some((name, offset)) => write!(f, "{}+0x{:x}", name, offset),
+            None => write!(f, "0x{:04x}", self.addr),
+        }
+    }
+}
+
+pub struct SymbolicInstruction<'a> {
+    symbols: &'a Symbols,
+    instruction: &'a Instruction,
+}
+
+impl<'a> Display for SymbolicInstruction<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = self
+            .instruction
+            .data()
+            .addr()
+            .and_then(|addr| self.symbols.name(addr));
+        match name {
+            Some(name) => self.instruction.fmt_operand(f, &name),
+            None => write!(f, "{}", self.instruction.to_string().trim_end()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Symbols;
+    use crate::{
+        i8080::Register,
+        instruction::{Instruction, Opcode},
+        Error,
+    };
+    use alloc::{string::ToString, vec::Vec};
+
+    const SYMBOLS: &str = "\
+; Space Invaders
+Reset = $0000
+ScanLine96 EQU 0008H
+0010 ScanLine224
+DrawSprite: 0x15d3
+ADD 1a00            # a hex-looking name
+";
+
+    #[test]
+    fn parses_every_form() {
+        let symbols = Symbols::parse(SYMBOLS).unwrap();
+        assert_eq!(symbols.len(), 5);
+        assert_eq!(symbols.get("Reset"), Some(0x0000));
+        assert_eq!(symbols.get("ScanLine96"), Some(0x0008));
+        assert_eq!(symbols.get("ScanLine224"), Some(0x0010));
+        assert_eq!(symbols.get("DrawSprite"), Some(0x15d3));
+        assert_eq!(symbols.get("ADD"), Some(0x1a00));
+        assert_eq!(
+            symbols.iter().map(|(_, name)| name).collect::<Vec<_>>(),
+            ["Reset", "ScanLine96", "ScanLine224", "DrawSprite", "ADD"]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        match Symbols::parse("Reset = $0000\nDrawSprite\n") {
+            Err(Error::InvalidSymbols { line: 2, .. }) => {}
+            r => panic!("unexpected result: {:?}", r.map(|s| s.len())),
+        }
+        assert!(Symbols::parse("1up = $20e8").is_err());
+    }
+
+    #[test]
+    fn names_addresses() {
+        let symbols = Symbols::parse(SYMBOLS).unwrap();
+        assert_eq!(symbols.address(0x15d3).to_string(), "DrawSprite");
+        assert_eq!(symbols.address(0x15e5).to_string(), "DrawSprite+0x12");
+        assert_eq!(Symbols::new().address(0x15e5).to_string(), "0x15e5");
+        assert_eq!(symbols.resolve("DrawSprite+0x12"), Some(0x15e5));
+        assert_eq!(symbols.resolve("ScanLine224 - 2"), Some(0x000e));
+        assert_eq!(symbols.resolve("$1234"), Some(0x1234));
+        assert_eq!(symbols.resolve("Nowhere"), None);
+
+        let call = Instruction::new_trinary(Opcode::CALL, 0x15d3).unwrap();
+        assert_eq!(symbols.instruction(&call).to_string(), "CALL   DrawSprite");
+        let lxi = Instruction::new_trinary(Opcode::LXI(Register::H), 0x2400).unwrap();
+        assert_eq!(symbols.instruction(&lxi).to_string(), "LXI    H, 0x2400");
+    }
+}